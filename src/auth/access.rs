@@ -2,6 +2,7 @@
 use super::creds::Credentials;
 use super::error::AccessTokenManagerError;
 use super::{AccessTokenManagerOAuth, AccessTokenManagerTokens};
+use crate::log::{self, Target};
 use crate::twitch::TwitchError;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
@@ -17,7 +18,7 @@ pub struct AccessTokenManager {
     client_id: Arc<String>,
     client_secret: Arc<String>,
     token_store: PathBuf,
-    options: crate::options::Options,
+    is_static: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +28,12 @@ struct TokenRequestResponse {
     // expires_in: u64,
 }
 
+#[derive(Debug, Deserialize)]
+struct AppTokenRequestResponse {
+    access_token: String,
+    // expires_in: u64,
+}
+
 #[derive(Debug, Deserialize)]
 struct TokenValidationResponse {
     client_id: String,
@@ -41,14 +48,11 @@ impl AccessTokenManager {
     /// Returns `Err(AccessTokenManagerError...)`:
     /// * `::Net` if a response was not received from Twitch.
     /// * `::OnRequest` if Twitch denied the request to create new Access
-    /// and Refresh tokens.
+    ///   and Refresh tokens.
     /// * `::IO` if the Access and Refresh tokens were not written to Disk.
     /// * `::BadData` if a response from Twitch could not be parsed.
-    pub async fn new_oauth(
-        data: AccessTokenManagerOAuth,
-        options: crate::options::Options,
-    ) -> Result<Self, AccessTokenManagerError> {
-        options.debug("Access: Requesting new tokens");
+    pub async fn new_oauth(data: AccessTokenManagerOAuth) -> Result<Self, AccessTokenManagerError> {
+        log::debug(Target::Auth, "Requesting new tokens");
 
         let client = reqwest::Client::new();
         let response = client
@@ -71,7 +75,7 @@ impl AccessTokenManager {
             AccessTokenManagerError::OnRequest,
         )?;
 
-        options.debug("Access: Got tokens!");
+        log::debug(Target::Auth, "Got tokens!");
 
         let creds = Arc::new(RwLock::new(Credentials {
             oauth: Some(data.oauth),
@@ -84,7 +88,7 @@ impl AccessTokenManager {
             client_id: Arc::new(data.client_id),
             client_secret: Arc::new(data.client_secret),
             token_store: data.tokens_store_path,
-            options,
+            is_static: false,
         };
         manager.write_tokens()?;
         Ok(manager)
@@ -108,7 +112,6 @@ impl AccessTokenManager {
     /// * `::BadData` if a response from Twitch could not be parsed.
     pub async fn new_tokens(
         data: AccessTokenManagerTokens,
-        options: crate::options::Options,
     ) -> Result<Self, AccessTokenManagerError> {
         let tokens = if data.tokens_store_path.try_exists()? {
             std::fs::read_to_string(&data.tokens_store_path)?
@@ -134,7 +137,7 @@ impl AccessTokenManager {
             client_id: Arc::new(data.client_id),
             client_secret: Arc::new(data.client_secret),
             token_store: data.tokens_store_path,
-            options,
+            is_static: false,
         };
 
         if !manager.validate().await? {
@@ -143,8 +146,28 @@ impl AccessTokenManager {
         Ok(manager)
     }
 
+    /// Creates a new `AccessTokenManager` from an Access Token that is managed
+    /// elsewhere, e.g. one issued by the Twitch CLI's mock API. The token is
+    /// never validated, refreshed, or written to Disk, and is also used as the
+    /// App Access Token.
+    #[must_use]
+    pub fn new_static(client_id: String, access_token: String) -> Self {
+        AccessTokenManager {
+            creds: Arc::new(RwLock::new(Credentials {
+                oauth: None,
+                access_token,
+                refresh_token: String::new(),
+            })),
+            client_id: Arc::new(client_id),
+            client_secret: Arc::new(String::new()),
+            token_store: PathBuf::new(),
+            is_static: true,
+        }
+    }
+
     /// Sends a validation request to Twitch. Returns `Ok(true)` if the stored Access
-    /// and Refresh tokens are valid, and `Ok(false)` if they are not.
+    /// and Refresh tokens are valid, and `Ok(false)` if they are not. Tokens
+    /// from [`new_static`](AccessTokenManager::new_static) are always valid.
     ///
     /// # Errors
     /// Returns `Err(AccessTokenManagerError...)`:
@@ -155,7 +178,11 @@ impl AccessTokenManager {
     ///   validation request.
     /// * `::BadData` if a response from Twitch could not be parsed.
     pub async fn validate(&self) -> Result<bool, AccessTokenManagerError> {
-        self.options.debug("Access: Validating tokens");
+        if self.is_static {
+            return Ok(true);
+        }
+
+        log::debug(Target::Auth, "Validating tokens");
 
         let response = reqwest::Client::new()
             .get("https://id.twitch.tv/oauth2/validate")
@@ -199,7 +226,7 @@ impl AccessTokenManager {
     ///   Refresh tokens.
     /// * `::BadData` if a response from Twitch could not be parsed.
     pub async fn refresh(&self) -> Result<(), AccessTokenManagerError> {
-        self.options.debug("Access: Refreshing tokens");
+        log::debug(Target::Auth, "Refreshing tokens");
 
         let response = reqwest::Client::new()
             .post(
@@ -264,8 +291,48 @@ impl AccessTokenManager {
         }
     }
 
+    /// Requests a new App Access Token from Twitch using the client
+    /// credentials grant flow. App Access Tokens are not stored, and are needed
+    /// for APIs that act on behalf of the app instead of a user, like creating
+    /// webhook EventSub subscriptions.
+    ///
+    /// # Errors
+    /// Returns `Err(AccessTokenManagerError...)`:
+    /// * `::Net` if a response was not received from Twitch.
+    /// * `::OnRequest` if Twitch denied the request to create a new App Access
+    ///   Token.
+    /// * `::BadData` if a response from Twitch could not be parsed.
+    pub async fn get_app_access_token(&self) -> Result<String, AccessTokenManagerError> {
+        if self.is_static {
+            return Ok(self.read_credentials_unvalidated().access_token.clone());
+        }
+
+        log::debug(Target::Auth, "Requesting app access token");
+
+        let response = reqwest::Client::new()
+            .post(
+                String::from("https://id.twitch.tv/oauth2/token?grant_type=client_credentials")
+                    + &format!(
+                        "&client_id={}&client_secret={}",
+                        self.client_id, self.client_secret
+                    ),
+            )
+            .send()
+            .await
+            .map_err(AccessTokenManagerError::Net)?
+            .text()
+            .await
+            .map_err(AccessTokenManagerError::Net)?;
+        let response = AccessTokenManager::parse_twitch::<AppTokenRequestResponse>(
+            &response,
+            AccessTokenManagerError::OnRequest,
+        )?;
+
+        Ok(response.access_token)
+    }
+
     fn write_tokens(&self) -> Result<(), AccessTokenManagerError> {
-        self.options.debug("Access: Writing tokens");
+        log::debug(Target::Auth, "Writing tokens");
 
         let creds = self.creds.read().unwrap();
         std::fs::write(
@@ -5,5 +5,7 @@ pub struct BotData {
     pub bot_username: String,
     pub chat_channel: String,
     pub subscriptions: Vec<crate::eventsub::subscription::Subscription>,
+    pub eventsub_transport: crate::eventsub::data::Transport,
+    pub eventsub_urls: crate::eventsub::data::EventsubUrls,
     pub chat_implicit_access: Option<String>,
 }
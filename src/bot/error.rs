@@ -8,6 +8,18 @@ pub enum BotError {
     Custom(String),
 }
 
+impl BotError {
+    /// Fatal errors stop the subsystem that they came from, i.e. chat or
+    /// EventSub. Every other error only fails one action.
+    #[must_use]
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            BotError::Chat(_) | BotError::Eventsub(_) => true,
+            BotError::Say(_) | BotError::IO(_) | BotError::Close | BotError::Custom(_) => false,
+        }
+    }
+}
+
 impl std::fmt::Display for BotError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
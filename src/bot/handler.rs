@@ -0,0 +1,273 @@
+//! Runs handlers on spawned tasks, so that one slow call doesn't hold up every
+//! message after it.
+//!
+//! Each handler can run up to [`HandlerOptions::max_concurrent`] calls at
+//! once. Calls over the limit wait on their tasks, not in the loop that
+//! receives messages: messages come through a watch channel, which only keeps
+//! the latest one, so making the loop wait would skip messages instead of
+//! slowing them down. How long each call takes is recorded in
+//! [`HandlerTimings`].
+use crate::log::{self, Target};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HandlerOptions {
+    /// Identifies the handler in timings and slow call warnings.
+    pub name: &'static str,
+    pub max_concurrent: usize,
+    pub ordering: Ordering,
+}
+
+/// Which calls wait for earlier ones to finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ordering {
+    /// Calls can finish in any order.
+    Unordered,
+    /// Calls for the same chatter run one at a time, in the order that their
+    /// messages arrived.
+    PerUser,
+}
+
+/// How long a handler's calls took.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandlerTiming {
+    pub calls: u64,
+    /// Calls that took longer than `bot.slow_handler_ms`.
+    pub slow_calls: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HandlerTimings(Arc<std::sync::Mutex<HashMap<&'static str, HandlerTiming>>>);
+
+impl HandlerOptions {
+    #[must_use]
+    pub const fn new(name: &'static str) -> Self {
+        HandlerOptions {
+            name,
+            max_concurrent: 8,
+            ordering: Ordering::PerUser,
+        }
+    }
+    #[must_use]
+    pub const fn max_concurrent(self, max_concurrent: usize) -> Self {
+        HandlerOptions {
+            max_concurrent,
+            ..self
+        }
+    }
+    #[must_use]
+    pub const fn ordering(self, ordering: Ordering) -> Self {
+        HandlerOptions { ordering, ..self }
+    }
+}
+
+impl HandlerTiming {
+    #[must_use]
+    pub fn average(&self) -> Duration {
+        u32::try_from(self.calls)
+            .ok()
+            .and_then(|calls| self.total.checked_div(calls))
+            .unwrap_or_default()
+    }
+}
+
+impl HandlerTimings {
+    /// Every handler's timing, sorted by name.
+    #[must_use]
+    pub fn list(&self) -> Vec<(&'static str, HandlerTiming)> {
+        let mut timings = self
+            .0
+            .lock()
+            .expect("The handler timings lock is never poisoned")
+            .iter()
+            .map(|(name, timing)| (*name, *timing))
+            .collect::<Vec<_>>();
+        timings.sort_unstable_by_key(|(name, _)| *name);
+        timings
+    }
+
+    fn record(&self, name: &'static str, elapsed: Duration, is_slow: bool) {
+        let mut timings = self
+            .0
+            .lock()
+            .expect("The handler timings lock is never poisoned");
+        let timing = timings.entry(name).or_default();
+        timing.calls += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+        if is_slow {
+            timing.slow_calls += 1;
+        }
+    }
+}
+
+/// Spawns `f` for each value that `receiver` gets until its sender is dropped.
+/// `user_id` picks out the chatter for [`Ordering::PerUser`].
+pub(super) async fn run_concurrent<T, F, Fut>(
+    handler: HandlerOptions,
+    timings: HandlerTimings,
+    options: crate::options::Options,
+    mut receiver: watch::Receiver<T>,
+    user_id: fn(&T) -> &str,
+    f: F,
+) where
+    T: Clone + Send + Sync + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let f = Arc::new(f);
+    let permits = Arc::new(Semaphore::new(handler.max_concurrent.max(1)));
+    let slow = Duration::from_millis(options.bot.slow_handler_ms);
+    // the latest call for each chatter, which the next call waits for
+    let mut latest: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    while receiver.changed().await.is_ok() {
+        let value = receiver.borrow().clone();
+        let previous = match handler.ordering {
+            Ordering::Unordered => None,
+            Ordering::PerUser => {
+                latest.retain(|_, call| !call.is_finished());
+                latest.remove(user_id(&value))
+            }
+        };
+        let key = (handler.ordering == Ordering::PerUser).then(|| String::from(user_id(&value)));
+
+        let f = f.clone();
+        let timings = timings.clone();
+        let permits = permits.clone();
+        let call = tokio::spawn(async move {
+            // a chatter's queued calls mustn't take permits that other
+            // chatters' calls could run with
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            let permit = permits
+                .acquire_owned()
+                .await
+                .expect("The semaphore is never closed");
+
+            let start = Instant::now();
+            f(value).await;
+            let elapsed = start.elapsed();
+            drop(permit);
+
+            let is_slow = elapsed >= slow;
+            if is_slow {
+                log::warn(
+                    Target::Bot,
+                    format!("Handler {:?} took {}ms", handler.name, elapsed.as_millis()),
+                );
+            }
+            timings.record(handler.name, elapsed, is_slow);
+        });
+
+        if let Some(key) = key {
+            latest.insert(key, call);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    /// Lets the receive loop and the calls it spawned run.
+    async fn settle() {
+        for _ in 0..20 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Runs a handler over `(user, call)` messages whose calls report when
+    /// they start, then wait for a permit from the returned semaphore.
+    fn spawn_handler(
+        handler: HandlerOptions,
+        timings: HandlerTimings,
+    ) -> (
+        watch::Sender<(&'static str, u32)>,
+        mpsc::UnboundedReceiver<u32>,
+        Arc<Semaphore>,
+    ) {
+        let (sender, receiver) = watch::channel(("", 0));
+        let (started_sender, started) = mpsc::unbounded_channel();
+        let release = Arc::new(Semaphore::new(0));
+        let calls_release = release.clone();
+        tokio::spawn(run_concurrent(
+            handler,
+            timings,
+            crate::options::Options::default(),
+            receiver,
+            |(user, _)| user,
+            move |(_, call)| {
+                let (started, release) = (started_sender.clone(), calls_release.clone());
+                async move {
+                    let _ = started.send(call);
+                    release.acquire().await.unwrap().forget();
+                }
+            },
+        ));
+        (sender, started, release)
+    }
+
+    fn drain(receiver: &mut mpsc::UnboundedReceiver<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+    }
+
+    #[tokio::test]
+    async fn chatters_calls_run_in_order_without_blocking_others() {
+        let handler = HandlerOptions::new("test").max_concurrent(2);
+        let (sender, mut started, release) = spawn_handler(handler, HandlerTimings::default());
+
+        for message in [("a", 1), ("a", 2), ("a", 3), ("b", 4)] {
+            sender.send(message).unwrap();
+            settle().await;
+        }
+        // "a"'s queued calls don't hold up "b"
+        assert_eq!(drain(&mut started), [1, 4]);
+
+        // finishing 1 and 4 starts 2, and finishing 2 starts 3
+        for (permits, expected) in [(2, 2), (1, 3)] {
+            release.add_permits(permits);
+            settle().await;
+            assert_eq!(drain(&mut started), [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn at_most_max_concurrent_calls_run_at_once() {
+        let handler = HandlerOptions::new("test")
+            .max_concurrent(2)
+            .ordering(Ordering::Unordered);
+        let timings = HandlerTimings::default();
+        let (sender, mut started, release) = spawn_handler(handler, timings.clone());
+
+        for call in 1..=5 {
+            sender.send(("a", call)).unwrap();
+            settle().await;
+        }
+        assert_eq!(drain(&mut started), [1, 2]);
+
+        for expected in [3, 4, 5] {
+            release.add_permits(1);
+            settle().await;
+            assert_eq!(drain(&mut started), [expected]);
+        }
+        release.add_permits(2);
+        settle().await;
+
+        let timings = timings.list();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].0, "test");
+        assert_eq!(timings[0].1.calls, 5);
+        assert_eq!(timings[0].1.slow_calls, 0);
+        assert!(timings[0].1.max >= timings[0].1.average());
+    }
+}
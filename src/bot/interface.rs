@@ -1,19 +1,26 @@
 use super::error::BotError;
 use crate::chat::interface::ChatInterface;
+use crate::log::{self, Target};
 use crate::twitch::HelixAuth;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BotInterface(pub(super) Arc<InterfaceData>);
 
 #[derive(Debug)]
 pub struct InterfaceData {
     pub(super) helix_auth: HelixAuth,
+    pub(super) eventsub_urls: crate::eventsub::data::EventsubUrls,
     pub(super) chat: ChatInterface,
     pub(super) error_reporter: tokio::sync::mpsc::Sender<super::error::BotError>,
     pub(super) message_history: Arc<(Mutex<VecDeque<String>>, usize)>,
+    pub(super) shutdown: super::shutdown::Shutdown,
+    pub(super) plugins: super::plugin::PluginRegistry,
+    pub(super) bot_user_id: tokio::sync::OnceCell<String>,
+    pub(super) broadcaster_id: tokio::sync::OnceCell<String>,
+    pub(super) handler_timings: super::handler::HandlerTimings,
 }
 
 impl BotInterface {
@@ -48,8 +55,69 @@ impl BotInterface {
             let _ = self.0.error_reporter.send(BotError::Say(err)).await;
         }
     }
+    /// How long each concurrent handler's calls took.
+    #[must_use]
+    pub fn handler_timings(&self) -> &super::handler::HandlerTimings {
+        &self.0.handler_timings
+    }
+    /// The user id of the channel that the bot is in. Only asks Twitch the
+    /// first time it succeeds.
+    pub async fn broadcaster_id(
+        &self,
+    ) -> Result<&str, Box<dyn std::error::Error + Send + Sync>> {
+        self.0
+            .broadcaster_id
+            .get_or_try_init(|| async {
+                crate::twitch::user_from_login(self.0.chat.channel(), &self.0.helix_auth)
+                    .await?
+                    .map(|user| user.id)
+                    .ok_or_else(|| format!("Channel {:?} not found", self.0.chat.channel()).into())
+            })
+            .await
+            .map(String::as_str)
+    }
+    /// Whispers `message` to `user_id` from the bot's account. Failures are
+    /// only logged, since whispers are best-effort.
+    pub async fn whisper<S: Into<String>>(&self, user_id: &str, message: S) {
+        let bot_user_id = self
+            .0
+            .bot_user_id
+            .get_or_try_init(|| async {
+                crate::twitch::token_user(&self.0.helix_auth)
+                    .await?
+                    .map(|user| user.id)
+                    .ok_or_else(|| "The access token has no user".into())
+            })
+            .await;
+        let result = match bot_user_id {
+            Ok(bot_user_id) => {
+                crate::twitch::send_whisper(
+                    bot_user_id,
+                    user_id,
+                    &message.into(),
+                    &self.0.helix_auth,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            log::warn(
+                Target::Bot,
+                format!("Could not whisper to user {user_id}: {err}"),
+            );
+        }
+    }
+    /// Gracefully stops the bot. See [`Shutdown`](super::shutdown::Shutdown).
     pub async fn shutdown(self) {
-        let _ = self.0.error_reporter.send(BotError::Close).await;
+        self.0.shutdown.trigger();
+    }
+    /// The coordinator of the bot's shutdown, for work that has to finish
+    /// before the bot stops.
+    #[must_use]
+    pub fn shutdown_coordinator(&self) -> &super::shutdown::Shutdown {
+        &self.0.shutdown
     }
     pub async fn error<S: Into<String>>(&self, error: S) {
         let _ = self
@@ -59,10 +127,42 @@ impl BotInterface {
             .await;
     }
     #[must_use]
+    pub fn plugins(&self) -> &super::plugin::PluginRegistry {
+        &self.0.plugins
+    }
+    #[must_use]
     pub fn helix_auth(&self) -> &HelixAuth {
         &self.0.helix_auth
     }
+    /// Where the bot connects to EventSub and manages its subscriptions.
+    #[must_use]
+    pub fn eventsub_urls(&self) -> &crate::eventsub::data::EventsubUrls {
+        &self.0.eventsub_urls
+    }
     pub fn mock_message<S: Into<String>>(&self, mock: &crate::chat::data::ChatMessage, text: S) {
         self.0.chat.mock_message(mock.clone(), text);
     }
+    /// An interface that isn't connected to Twitch, for testing handlers.
+    /// Reported errors and sent messages are dropped.
+    #[cfg(test)]
+    pub(crate) async fn mock() -> Self {
+        Self(Arc::new(InterfaceData {
+            helix_auth: HelixAuth {
+                client_id: String::from("mock"),
+                access: crate::auth::access::AccessTokenManager::new_static(
+                    String::from("mock"),
+                    String::from("mock"),
+                ),
+            },
+            eventsub_urls: crate::eventsub::data::EventsubUrls::default(),
+            chat: ChatInterface::mock("mock").await,
+            error_reporter: tokio::sync::mpsc::channel(1).0,
+            message_history: Arc::new((Mutex::new(VecDeque::new()), 0)),
+            shutdown: super::shutdown::Shutdown::new(),
+            plugins: super::plugin::PluginRegistry::default(),
+            bot_user_id: tokio::sync::OnceCell::new(),
+            broadcaster_id: tokio::sync::OnceCell::new(),
+            handler_timings: super::handler::HandlerTimings::default(),
+        }))
+    }
 }
@@ -1,5 +1,7 @@
 use crate::chat;
 use crate::eventsub;
+use crate::eventsub::data::RawNotificationMessage;
+use crate::log::{self, Target};
 use crate::twitch;
 use error::BotError;
 use std::collections::VecDeque;
@@ -8,7 +10,17 @@ use tokio::sync::mpsc;
 
 pub mod data;
 pub mod error;
+pub mod handler;
 pub mod interface;
+pub mod plugin;
+pub mod shutdown;
+mod supervisor;
+
+/// How many errors can be reported before reporters wait for the bot to handle
+/// them.
+const ERROR_CHANNEL_CAPACITY: usize = 32;
+/// How long a shutdown waits for outstanding [`shutdown::ShutdownHold`]s.
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct Bot {
@@ -16,6 +28,7 @@ pub struct Bot {
     eventsub_client: eventsub::client::EventsubClient,
     interface: interface::BotInterface,
     error_listener: mpsc::Receiver<BotError>,
+    options: crate::options::Options,
 }
 
 impl Bot {
@@ -32,7 +45,7 @@ impl Bot {
                 bot_username: data.bot_username,
                 chat_channel: data.chat_channel,
             },
-            options,
+            options.clone(),
         )
         .await?;
 
@@ -41,8 +54,10 @@ impl Bot {
                 client_id: data.client_id.clone(),
                 access: data.access.clone(),
                 subscriptions: data.subscriptions,
+                transport: data.eventsub_transport,
+                urls: data.eventsub_urls.clone(),
             },
-            options,
+            options.clone(),
         )
         .await?;
 
@@ -51,13 +66,35 @@ impl Bot {
             access: data.access,
         };
 
-        let (error_sender, error_receiver) = mpsc::channel(1);
+        Ok(Self::from_clients(
+            chat_client,
+            eventsub_client,
+            helix_auth,
+            data.eventsub_urls,
+            options,
+        ))
+    }
+
+    fn from_clients(
+        chat_client: chat::client::ChatClient,
+        eventsub_client: eventsub::client::EventsubClient,
+        helix_auth: twitch::HelixAuth,
+        eventsub_urls: eventsub::data::EventsubUrls,
+        options: crate::options::Options,
+    ) -> Self {
+        let (error_sender, error_receiver) = mpsc::channel(ERROR_CHANNEL_CAPACITY);
 
-        Ok(Self {
+        Self {
             interface: interface::BotInterface(std::sync::Arc::new(interface::InterfaceData {
                 helix_auth,
+                eventsub_urls,
                 chat: chat_client.get_interface(),
                 error_reporter: error_sender,
+                shutdown: shutdown::Shutdown::new(),
+                plugins: plugin::PluginRegistry::default(),
+                bot_user_id: tokio::sync::OnceCell::new(),
+                broadcaster_id: tokio::sync::OnceCell::new(),
+                handler_timings: handler::HandlerTimings::default(),
                 message_history: std::sync::Arc::new((
                     tokio::sync::Mutex::new(VecDeque::with_capacity(
                         options.bot.duplicate_message_depth,
@@ -68,7 +105,8 @@ impl Bot {
             error_listener: error_receiver,
             chat_client,
             eventsub_client,
-        })
+            options,
+        }
     }
 
     pub fn on_chat_message<Fut: Future>(
@@ -78,12 +116,36 @@ impl Bot {
         let interface = self.interface.0.clone();
         let mut receiver = self.chat_client.subscribe();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let chat_message = receiver.borrow().clone();
                 f(chat_message, interface::BotInterface(interface.clone())).await;
             }
-        }
+        })
+    }
+    /// Like [`Bot::on_chat_message`], but runs `f` on a spawned task for each
+    /// message. See [`handler`] for how calls are limited and ordered.
+    pub fn on_chat_message_concurrent<Fut>(
+        &self,
+        handler: handler::HandlerOptions,
+        f: impl Fn(crate::chat::data::ChatMessage, interface::BotInterface) -> Fut
+            + Send
+            + Sync
+            + 'static,
+    ) -> impl Future<Output = ()>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let interface = self.interface.0.clone();
+
+        self.until_shutdown(handler::run_concurrent(
+            handler,
+            self.interface.0.handler_timings.clone(),
+            self.options.clone(),
+            self.chat_client.subscribe(),
+            |message| &message.user_id,
+            move |message| f(message, interface::BotInterface(interface.clone())),
+        ))
     }
     pub fn on_event<E: crate::eventsub::event::Event, Fut: Future>(
         &self,
@@ -92,14 +154,72 @@ impl Bot {
         let interface = self.interface.0.clone();
         let mut receiver = self.eventsub_client.subscribe();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let value = receiver.borrow().clone();
-                if let Ok(value) = serde_json::from_value(value) {
-                    f(value, interface::BotInterface(interface.clone())).await;
+                if let Some(notification) = RawNotificationMessage::from_value(value)
+                    .and_then(RawNotificationMessage::into_event)
+                {
+                    f(notification, interface::BotInterface(interface.clone())).await;
                 }
             }
-        }
+        })
+    }
+    /// Runs `f` on every notification, with its event deserialized into an
+    /// [`AnyEvent`](crate::eventsub::event::AnyEvent).
+    pub fn on_any_event<Fut: Future>(
+        &self,
+        mut f: impl FnMut(
+            crate::eventsub::data::NotificationMessage<crate::eventsub::event::AnyEvent>,
+            interface::BotInterface,
+        ) -> Fut,
+    ) -> impl Future<Output = ()> {
+        let interface = self.interface.0.clone();
+        let mut receiver = self.eventsub_client.subscribe();
+
+        self.until_shutdown(async move {
+            while receiver.changed().await.is_ok() {
+                let value = receiver.borrow().clone();
+                let Some(notification) = RawNotificationMessage::from_value(value) else {
+                    continue;
+                };
+                match notification.into_any_event() {
+                    Ok(notification) => {
+                        f(notification, interface::BotInterface(interface.clone())).await;
+                    }
+                    Err(err) => {
+                        log::warn(
+                            Target::Eventsub,
+                            format!("Received a malformed event: {err}"),
+                        );
+                    }
+                }
+            }
+        })
+    }
+    /// Runs `f` on every notification of `subscription_type` without
+    /// deserializing its event. Useful for subscription types that don't have
+    /// an [`Event`](crate::eventsub::event::Event) implementation.
+    pub fn on_event_type<S: Into<String>, Fut: Future>(
+        &self,
+        subscription_type: S,
+        mut f: impl FnMut(RawNotificationMessage, interface::BotInterface) -> Fut,
+    ) -> impl Future<Output = ()> {
+        let subscription_type = subscription_type.into();
+        let interface = self.interface.0.clone();
+        let mut receiver = self.eventsub_client.subscribe();
+
+        self.until_shutdown(async move {
+            while receiver.changed().await.is_ok() {
+                let value = receiver.borrow().clone();
+                let Some(notification) = RawNotificationMessage::from_value(value) else {
+                    continue;
+                };
+                if notification.subscription_type() == subscription_type {
+                    f(notification, interface::BotInterface(interface.clone())).await;
+                }
+            }
+        })
     }
     pub fn on_chat_event<Fut: Future>(
         &self,
@@ -108,12 +228,12 @@ impl Bot {
         let interface = self.interface.0.clone();
         let mut receiver = self.chat_client.subscribe_events();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let chat_event = receiver.borrow().clone();
                 f(chat_event, interface::BotInterface(interface.clone())).await;
             }
-        }
+        })
     }
     pub fn on_chat_message_comet<Fut: Future>(
         &self,
@@ -128,7 +248,7 @@ impl Bot {
         let mut receiver = self.chat_client.subscribe();
         let comet = comet_server.interface();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let chat_message = receiver.borrow().clone();
                 f(
@@ -138,7 +258,7 @@ impl Bot {
                 )
                 .await;
             }
-        }
+        })
     }
     pub fn on_event_comet<E: crate::eventsub::event::Event, Fut: Future>(
         &self,
@@ -153,19 +273,21 @@ impl Bot {
         let mut receiver = self.eventsub_client.subscribe();
         let comet = comet_server.interface();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let value = receiver.borrow().clone();
-                if let Ok(value) = serde_json::from_value(value) {
+                if let Some(notification) = RawNotificationMessage::from_value(value)
+                    .and_then(RawNotificationMessage::into_event)
+                {
                     f(
-                        value,
+                        notification,
                         interface::BotInterface(interface.clone()),
                         comet.clone(),
                     )
                     .await;
                 }
             }
-        }
+        })
     }
     pub fn on_chat_event_comet<Fut: Future>(
         &self,
@@ -180,7 +302,7 @@ impl Bot {
         let mut receiver = self.chat_client.subscribe_events();
         let comet = comet_server.interface();
 
-        async move {
+        self.until_shutdown(async move {
             while receiver.changed().await.is_ok() {
                 let chat_event = receiver.borrow().clone();
                 f(
@@ -190,7 +312,7 @@ impl Bot {
                 )
                 .await;
             }
-        }
+        })
     }
 
     #[must_use]
@@ -202,18 +324,416 @@ impl Bot {
         self.interface.0.error_reporter.clone()
     }
 
-    pub async fn run(mut self) -> Result<(), BotError> {
-        tokio::select! {
-            Err(chat_err) = self.chat_client.run() => Err(chat_err.into()),
-            Err(eventsub_err) = self.eventsub_client.run() => Err(eventsub_err.into()),
-            received_err = async {
-                let Some(err) = self.error_listener.recv().await else {
-                    loop { tokio::task::yield_now().await; }
+    /// Adds `plugin` to the bot, replacing any plugin with the same name. The
+    /// plugin starts disabled if its name is in `plugins.disabled`.
+    pub async fn add_plugin(&self, plugin: impl plugin::Plugin + 'static) {
+        let is_enabled = !self
+            .options
+            .plugins
+            .disabled
+            .iter()
+            .any(|name| name == plugin.name());
+        self.interface
+            .0
+            .plugins
+            .add(std::sync::Arc::new(plugin), is_enabled)
+            .await;
+    }
+    /// Runs every plugin's [`on_comet_connect`](plugin::Plugin::on_comet_connect)
+    /// whenever a client connects to `comet_server`.
+    pub fn attach_comet(
+        &self,
+        comet_server: &crate::eye::comet::Server,
+    ) -> impl Future<Output = ()> {
+        let interface = self.interface.0.clone();
+        let comet = comet_server.interface();
+
+        self.until_shutdown(async move {
+            let mut receiver = comet.subscribe_connections().await;
+            while receiver.changed().await.is_ok() {
+                for plugin in interface.plugins.enabled().await {
+                    plugin
+                        .on_comet_connect(comet.clone(), interface::BotInterface(interface.clone()))
+                        .await;
+                }
+            }
+        })
+    }
+
+    /// Returns the bot's shutdown coordinator. Triggering it makes
+    /// [`Bot::run`] return `Ok`.
+    #[must_use]
+    pub fn shutdown(&self) -> shutdown::Shutdown {
+        self.interface.0.shutdown.clone()
+    }
+
+    fn until_shutdown(&self, fut: impl Future<Output = ()>) -> impl Future<Output = ()> {
+        let shutdown = self.shutdown();
+        async move {
+            let _ = shutdown.until_triggered(fut).await;
+        }
+    }
+
+    fn run_plugins(&self) {
+        let plugins = self.interface.0.plugins.clone();
+        tokio::spawn(self.on_chat_message(move |message, bot| {
+            let plugins = plugins.clone();
+            async move {
+                for plugin in plugins.enabled().await {
+                    plugin.on_chat(message.clone(), bot.clone()).await;
+                }
+            }
+        }));
+
+        let plugins = self.interface.0.plugins.clone();
+        tokio::spawn(self.on_chat_event(move |event, bot| {
+            let plugins = plugins.clone();
+            async move {
+                for plugin in plugins.enabled().await {
+                    plugin.on_chat_event(event.clone(), bot.clone()).await;
+                }
+            }
+        }));
+
+        let plugins = self.interface.0.plugins.clone();
+        let interface = self.interface.0.clone();
+        let mut receiver = self.eventsub_client.subscribe();
+        tokio::spawn(self.until_shutdown(async move {
+            while receiver.changed().await.is_ok() {
+                let value = receiver.borrow().clone();
+                let Some(notification) = RawNotificationMessage::from_value(value) else {
+                    continue;
                 };
-                self.error_listener.close();
-                err
-            } => Err(received_err),
-            else => Ok(())
+                for plugin in plugins.enabled().await {
+                    plugin
+                        .on_event(
+                            notification.clone(),
+                            interface::BotInterface(interface.clone()),
+                        )
+                        .await;
+                }
+            }
+        }));
+    }
+
+    pub async fn run(mut self) -> Result<(), BotError> {
+        let shutdown = self.shutdown();
+
+        for plugin in self.interface.0.plugins.enabled().await {
+            plugin.on_start(self.interface()).await;
+        }
+        self.run_plugins();
+
+        let chat_reconnector = self.chat_client.reconnector();
+        let eventsub_reconnector = self.eventsub_client.reconnector();
+        let chat = self.chat_client.run();
+        let eventsub = self.eventsub_client.run();
+        tokio::pin!(chat, eventsub);
+        let (mut chat_is_running, mut eventsub_is_running) = (true, true);
+
+        let mut supervisor = supervisor::Supervisor::new(self.options);
+        // errors from restarting a subsystem, handled before waiting for more
+        let mut pending_err = None;
+        loop {
+            if !chat_is_running && !eventsub_is_running && pending_err.is_none() {
+                return Err(BotError::Custom(String::from(
+                    "Chat and EventSub have both stopped",
+                )));
+            }
+
+            let (err, subsystem) = if let Some(pending) = pending_err.take() {
+                pending
+            } else {
+                tokio::select! {
+                    result = &mut chat, if chat_is_running => {
+                        chat_is_running = false;
+                        match result {
+                            Ok(()) => continue,
+                            Err(err) => (err.into(), Some(supervisor::Subsystem::Chat)),
+                        }
+                    }
+                    result = &mut eventsub, if eventsub_is_running => {
+                        eventsub_is_running = false;
+                        match result {
+                            Ok(()) => continue,
+                            Err(err) => (err.into(), Some(supervisor::Subsystem::Eventsub)),
+                        }
+                    }
+                    Some(err) = self.error_listener.recv() => (err, None),
+                    () = shutdown.triggered() => break,
+                }
+            };
+            if let BotError::Close = err {
+                break;
+            }
+
+            match supervisor.handle(&err, subsystem, &self.interface).await {
+                supervisor::Action::Continue => (),
+                supervisor::Action::Exit => return Err(err),
+                supervisor::Action::Restart(subsystem) => {
+                    let delay = tokio::time::sleep(supervisor::RESTART_DELAY);
+                    if shutdown.until_triggered(delay).await.is_none() {
+                        break;
+                    }
+
+                    crate::metrics::METRICS
+                        .reconnects
+                        .inc(&subsystem.to_string().to_lowercase());
+                    match subsystem {
+                        supervisor::Subsystem::Chat => match chat_reconnector.reconnect().await {
+                            Ok(client) => {
+                                chat.set(client.run());
+                                chat_is_running = true;
+                            }
+                            Err(err) => pending_err = Some((err.into(), Some(subsystem))),
+                        },
+                        supervisor::Subsystem::Eventsub => {
+                            match eventsub_reconnector.reconnect().await {
+                                Ok(client) => {
+                                    eventsub.set(client.run());
+                                    eventsub_is_running = true;
+                                }
+                                Err(err) => pending_err = Some((err.into(), Some(subsystem))),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        log::debug(Target::Bot, "Shutting down");
+        shutdown.trigger();
+
+        let plugins = self.interface.0.plugins.enabled().await;
+        let interface = interface::BotInterface(self.interface.0.clone());
+        let plugins_shut_down = async {
+            for plugin in plugins {
+                plugin.on_shutdown(interface.clone()).await;
+            }
+        };
+        tokio::pin!(plugins_shut_down);
+        let finished = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            // chat finishing mustn't cancel the plugins partway through
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut chat, if chat_is_running => chat_is_running = false,
+                    () = &mut plugins_shut_down => break,
+                }
+            }
+        })
+        .await;
+        if finished.is_err() {
+            log::warn(
+                Target::Bot,
+                "Timed out waiting for plugins to finish while shutting down",
+            );
+        }
+
+        if let Err(err) = self.interface.0.chat.part() {
+            log::warn(
+                Target::Bot,
+                format!("Could not leave chat while shutting down: {err}"),
+            );
+        }
+        let finished = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            // keep polling chat first so that the PART is sent
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut chat, if chat_is_running => chat_is_running = false,
+                    () = shutdown.released() => break,
+                }
+            }
+        })
+        .await;
+        if finished.is_err() {
+            log::warn(
+                Target::Bot,
+                "Timed out waiting for tasks to finish while shutting down",
+            );
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plugin::tests::Recorder;
+    use super::Bot;
+    use crate::auth::access::AccessTokenManager;
+    use crate::chat::client::ChatClient;
+    use crate::eventsub::client::EventsubClient;
+    use crate::eventsub::data::{EventsubClientData, Transport};
+    use crate::eventsub::event;
+    use crate::eventsub::mock::MockServer;
+    use crate::eventsub::subscription::Subscription;
+    use crate::options::{Options, Plugins};
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    async fn within<T>(fut: impl std::future::Future<Output = T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), fut)
+            .await
+            .expect("Mock interaction timed out")
+    }
+
+    async fn bot(server: &MockServer, subscriptions: Vec<Subscription>) -> Bot {
+        bot_with(
+            server,
+            subscriptions,
+            ChatClient::mock("streamer").await,
+            Options::default(),
+        )
+        .await
+    }
+
+    async fn bot_with(
+        server: &MockServer,
+        subscriptions: Vec<Subscription>,
+        chat_client: ChatClient,
+        options: Options,
+    ) -> Bot {
+        let access = AccessTokenManager::new_static(String::from("mock"), String::from("mock"));
+        let eventsub_client = EventsubClient::new(
+            EventsubClientData {
+                client_id: String::from("mock"),
+                access: access.clone(),
+                subscriptions,
+                transport: Transport::Websocket,
+                urls: server.urls(),
+            },
+            options.clone(),
+        )
+        .await
+        .expect("Client connects to the mock server");
+
+        Bot::from_clients(
+            chat_client,
+            eventsub_client,
+            crate::twitch::HelixAuth {
+                client_id: String::from("mock"),
+                access,
+            },
+            server.urls(),
+            options,
+        )
+    }
+
+    #[tokio::test]
+    async fn on_event_handles_notifications_until_shutdown() {
+        let mut server = MockServer::new().await.unwrap();
+        let raid = Subscription::raid_to(String::from("1234"));
+        let bot = bot(&server, vec![raid.clone()]).await;
+        assert_eq!(
+            bot.interface().eventsub_urls().subscriptions,
+            server.urls().subscriptions
+        );
+
+        let (sender, mut raids) = mpsc::unbounded_channel();
+        let handler = tokio::spawn(bot.on_event::<event::Raid, _>(move |notification, _| {
+            let sender = sender.clone();
+            async move {
+                let _ = sender.send(notification.payload.event);
+            }
+        }));
+        let shutdown = bot.shutdown();
+        tokio::spawn(bot.eventsub_client.run());
+
+        within(server.next_session()).await;
+        within(server.wait_for_subscriptions(1)).await;
+
+        // a raid that can't be deserialized is skipped
+        server.notify(&raid, json!({ "viewers": "many" }));
+        server.notify(
+            &raid,
+            json!({
+                "from_broadcaster_user_id": "5678",
+                "from_broadcaster_user_login": "raider",
+                "from_broadcaster_user_name": "Raider",
+                "to_broadcaster_user_id": "1234",
+                "to_broadcaster_user_login": "streamer",
+                "to_broadcaster_user_name": "Streamer",
+                "viewers": 10,
+            }),
+        );
+        let received = within(raids.recv()).await.unwrap();
+        assert_eq!(received.from_broadcaster_user_name, "Raider");
+        assert_eq!(received.viewers, 10);
+
+        shutdown.trigger();
+        within(handler).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_stops_once_chat_and_eventsub_have_both_closed() {
+        let mut server = MockServer::new().await.unwrap();
+        let bot = bot_with(
+            &server,
+            Vec::new(),
+            ChatClient::mock_closing("streamer").await,
+            Options::default(),
+        )
+        .await;
+        let run = tokio::spawn(bot.run());
+
+        within(server.next_session()).await;
+        server.close();
+        let result = within(run).await.unwrap();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            super::BotError::Custom(String::from("Chat and EventSub have both stopped"))
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn hooks_skip_disabled_plugins() {
+        let server = MockServer::new().await.unwrap();
+        let options = Options {
+            plugins: Plugins {
+                disabled: vec![String::from("quiet")].into(),
+            },
+            ..Options::default()
+        };
+        // a chat that closes cleanly, so only the shutdown ends the bot
+        let bot = bot_with(
+            &server,
+            Vec::new(),
+            ChatClient::mock_closing("streamer").await,
+            options,
+        )
+        .await;
+
+        let calls = std::sync::Arc::default();
+        for name in ["loud", "quiet", "muted"] {
+            bot.add_plugin(Recorder {
+                name,
+                label: name,
+                calls: std::sync::Arc::clone(&calls),
+            })
+            .await;
+        }
+        let plugins = bot.interface().plugins().clone();
+        assert_eq!(
+            plugins.list().await,
+            vec![("loud", true), ("quiet", false), ("muted", true)]
+        );
+        assert!(plugins.set_enabled("muted", false).await);
+
+        let shutdown = bot.shutdown();
+        let run = tokio::spawn(bot.run());
+        within(async {
+            while calls.lock().unwrap().is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await;
+        shutdown.trigger();
+        within(run).await.unwrap().unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), ["loud: start", "loud: shutdown"]);
     }
 }
@@ -0,0 +1,223 @@
+//! Self-contained units of bot behaviour.
+//!
+//! A [`Plugin`] is registered once with [`Bot::add_plugin`](super::Bot::add_plugin)
+//! and receives every lifecycle hook while it is enabled. Plugins can be
+//! disabled in the `plugins` options table or at runtime through
+//! [`BotInterface::plugins`](super::interface::BotInterface::plugins).
+use super::interface::BotInterface;
+use crate::chat::data::{ChatEvent, ChatMessage};
+use crate::eventsub::data::RawNotificationMessage;
+use crate::eye::comet::CometInterface;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type PluginFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Every hook does nothing by default. Hooks of the same kind run one plugin
+/// at a time, in the order that the plugins were added.
+pub trait Plugin: Send + Sync {
+    /// Identifies the plugin when enabling or disabling it. Should be unique.
+    fn name(&self) -> &'static str;
+
+    /// Runs once when the bot starts.
+    fn on_start(&self, _bot: BotInterface) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+    fn on_chat(&self, _message: ChatMessage, _bot: BotInterface) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+    fn on_chat_event(&self, _event: ChatEvent, _bot: BotInterface) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+    /// Runs on every EventSub notification. Use
+    /// [`into_event`](RawNotificationMessage::into_event) to get a specific
+    /// event.
+    fn on_event(
+        &self,
+        _notification: RawNotificationMessage,
+        _bot: BotInterface,
+    ) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+    /// Runs whenever a new Comet client connects. Only runs if the bot was
+    /// given a Comet server with [`Bot::attach_comet`](super::Bot::attach_comet).
+    fn on_comet_connect(&self, _comet: CometInterface, _bot: BotInterface) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+    /// Runs once when the bot is gracefully shutting down, before it leaves
+    /// chat.
+    fn on_shutdown(&self, _bot: BotInterface) -> PluginFuture<'_> {
+        Box::pin(async {})
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PluginRegistry(Arc<RwLock<Vec<PluginEntry>>>);
+
+struct PluginEntry {
+    plugin: Arc<dyn Plugin>,
+    is_enabled: bool,
+}
+
+impl PluginRegistry {
+    /// Adds `plugin`, replacing any plugin with the same name.
+    pub(super) async fn add(&self, plugin: Arc<dyn Plugin>, is_enabled: bool) {
+        let mut entries = self.0.write().await;
+        let entry = PluginEntry { plugin, is_enabled };
+
+        match entries
+            .iter_mut()
+            .find(|existing| existing.plugin.name() == entry.plugin.name())
+        {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+    }
+
+    /// Returns `false` if there is no plugin named `name`.
+    pub async fn set_enabled(&self, name: &str, is_enabled: bool) -> bool {
+        match self
+            .0
+            .write()
+            .await
+            .iter_mut()
+            .find(|entry| entry.plugin.name() == name)
+        {
+            Some(entry) => {
+                entry.is_enabled = is_enabled;
+                true
+            }
+            None => false,
+        }
+    }
+    pub async fn is_enabled(&self, name: &str) -> Option<bool> {
+        self.0
+            .read()
+            .await
+            .iter()
+            .find(|entry| entry.plugin.name() == name)
+            .map(|entry| entry.is_enabled)
+    }
+    /// Every plugin's name and whether it is enabled, in the order they were
+    /// added.
+    pub async fn list(&self) -> Vec<(&'static str, bool)> {
+        self.0
+            .read()
+            .await
+            .iter()
+            .map(|entry| (entry.plugin.name(), entry.is_enabled))
+            .collect()
+    }
+
+    pub(super) async fn enabled(&self) -> Vec<Arc<dyn Plugin>> {
+        self.0
+            .read()
+            .await
+            .iter()
+            .filter(|entry| entry.is_enabled)
+            .map(|entry| entry.plugin.clone())
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for PluginRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PluginRegistry").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::{Plugin, PluginFuture, PluginRegistry};
+    use crate::bot::interface::BotInterface;
+    use std::sync::{Arc, Mutex};
+
+    /// Records the hooks it runs as `"<label>: <hook>"`.
+    pub(in crate::bot) struct Recorder {
+        pub name: &'static str,
+        pub label: &'static str,
+        pub calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn record(&self, hook: &str) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{}: {hook}", self.label));
+        }
+    }
+
+    impl Plugin for Recorder {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn on_start(&self, _bot: BotInterface) -> PluginFuture<'_> {
+            self.record("start");
+            Box::pin(async {})
+        }
+        fn on_shutdown(&self, _bot: BotInterface) -> PluginFuture<'_> {
+            self.record("shutdown");
+            Box::pin(async {})
+        }
+    }
+
+    fn recorder(name: &'static str, calls: &Arc<Mutex<Vec<String>>>) -> Arc<Recorder> {
+        Arc::new(Recorder {
+            name,
+            label: name,
+            calls: calls.clone(),
+        })
+    }
+
+    #[tokio::test]
+    async fn adding_replaces_plugins_by_name() {
+        let calls = Arc::default();
+        let registry = PluginRegistry::default();
+        registry.add(recorder("a", &calls), true).await;
+        registry.add(recorder("b", &calls), true).await;
+        let replacement = Recorder {
+            name: "a",
+            label: "replacement",
+            calls: calls.clone(),
+        };
+        registry.add(Arc::new(replacement), false).await;
+
+        // the replacement keeps the original's place
+        assert_eq!(registry.list().await, vec![("a", false), ("b", true)]);
+        registry.set_enabled("a", true).await;
+        let bot = BotInterface::mock().await;
+        for plugin in registry.enabled().await {
+            plugin.on_start(bot.clone()).await;
+        }
+        assert_eq!(*calls.lock().unwrap(), ["replacement: start", "b: start"]);
+    }
+
+    #[tokio::test]
+    async fn only_enabled_plugins_are_returned() {
+        let calls = Arc::default();
+        let registry = PluginRegistry::default();
+        registry.add(recorder("a", &calls), true).await;
+        registry.add(recorder("b", &calls), false).await;
+        registry.add(recorder("c", &calls), true).await;
+
+        assert!(registry.set_enabled("c", false).await);
+        assert!(registry.set_enabled("b", true).await);
+        assert!(!registry.set_enabled("d", true).await);
+        assert_eq!(registry.is_enabled("d").await, None);
+        assert_eq!(
+            registry.list().await,
+            vec![("a", true), ("b", true), ("c", false)]
+        );
+
+        let names: Vec<_> = registry
+            .enabled()
+            .await
+            .iter()
+            .map(|plugin| plugin.name())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Coordinates stopping the bot without abandoning work in progress.
+//!
+//! Triggering a [`Shutdown`] stops every handler registered through the
+//! [`Bot`](super::Bot), then [`Bot::run`](super::Bot::run) waits for every
+//! outstanding [`ShutdownHold`] to be dropped before returning.
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone)]
+pub struct Shutdown(Arc<ShutdownData>);
+
+#[derive(Debug)]
+struct ShutdownData {
+    signal: watch::Sender<bool>,
+    holds: watch::Sender<usize>,
+}
+
+/// Delays the end of a shutdown until it is dropped.
+#[derive(Debug)]
+pub struct ShutdownHold(Shutdown);
+
+impl Shutdown {
+    #[must_use]
+    pub(super) fn new() -> Self {
+        Shutdown(Arc::new(ShutdownData {
+            signal: watch::channel(false).0,
+            holds: watch::channel(0).0,
+        }))
+    }
+
+    pub fn trigger(&self) {
+        self.0.signal.send_replace(true);
+    }
+    #[must_use]
+    pub fn is_triggered(&self) -> bool {
+        *self.0.signal.borrow()
+    }
+    /// Waits until the shutdown is triggered.
+    pub async fn triggered(&self) {
+        let mut receiver = self.0.signal.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+    /// Runs `fut` until it completes or the shutdown is triggered, returning
+    /// `None` in the latter case.
+    pub async fn until_triggered<F: Future>(&self, fut: F) -> Option<F::Output> {
+        tokio::select! {
+            output = fut => Some(output),
+            () = self.triggered() => None,
+        }
+    }
+
+    /// Keeps the bot running after a shutdown is triggered until the returned
+    /// hold is dropped.
+    #[must_use]
+    pub fn hold(&self) -> ShutdownHold {
+        self.0.holds.send_modify(|holds| *holds += 1);
+        ShutdownHold(self.clone())
+    }
+    /// Waits until every [`ShutdownHold`] is dropped.
+    pub(super) async fn released(&self) {
+        let mut receiver = self.0.holds.subscribe();
+        while *receiver.borrow_and_update() > 0 {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for ShutdownHold {
+    fn drop(&mut self) {
+        self.0 .0.holds.send_modify(|holds| *holds -= 1);
+    }
+}
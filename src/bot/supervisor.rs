@@ -0,0 +1,231 @@
+//! Decides what [`Bot::run`](super::Bot::run) does about each [`BotError`],
+//! following the `supervisor` options.
+use super::error::BotError;
+use super::interface::BotInterface;
+use crate::log::{self, Target};
+use crate::options::{Options, Policy};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long to wait before restarting a subsystem.
+pub(super) const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Only restarts within this long count towards `max_restarts`, so that a bot
+/// with a few drops over weeks doesn't eventually give up.
+pub(super) const RESTART_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// The parts of the bot that can be restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Subsystem {
+    Chat,
+    Eventsub,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Action {
+    Continue,
+    Restart(Subsystem),
+    Exit,
+}
+
+#[derive(Debug)]
+pub(super) struct Supervisor {
+    options: Options,
+    /// When each restart within the last [`RESTART_WINDOW`] happened, oldest
+    /// first.
+    restarts: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    pub(super) fn new(options: Options) -> Self {
+        Supervisor {
+            options,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// `subsystem` is the subsystem that stopped with `err`, if any.
+    pub(super) async fn handle(
+        &mut self,
+        err: &BotError,
+        subsystem: Option<Subsystem>,
+        bot: &BotInterface,
+    ) -> Action {
+        let policy = if err.is_fatal() {
+            self.options.supervisor.fatal
+        } else {
+            self.options.supervisor.recoverable
+        };
+
+        match policy {
+            Policy::Log => {
+                log::error(Target::Bot, err.to_string());
+                Action::Continue
+            }
+            Policy::Notify => {
+                log::error(Target::Bot, err.to_string());
+                // not `BotInterface::say`, so that a failed notification doesn't
+                // report another error
+                if let Err(say_err) = bot.0.chat.say(format!("Bot error: {err}")) {
+                    log::warn(
+                        Target::Bot,
+                        format!("Could not post an error in chat: {say_err}"),
+                    );
+                }
+                Action::Continue
+            }
+            Policy::Restart => {
+                let Some(subsystem) = subsystem else {
+                    log::error(Target::Bot, err.to_string());
+                    return Action::Continue;
+                };
+                if !self.try_restart(Instant::now()) {
+                    log::error(
+                        Target::Bot,
+                        format!(
+                            "Giving up after {} restarts within {}s: {err}",
+                            self.restarts.len(),
+                            RESTART_WINDOW.as_secs()
+                        ),
+                    );
+                    return Action::Exit;
+                }
+
+                log::warn(
+                    Target::Bot,
+                    format!(
+                        "Restarting {subsystem} ({}/{}) after: {err}",
+                        self.restarts.len(),
+                        self.options.supervisor.max_restarts
+                    ),
+                );
+                Action::Restart(subsystem)
+            }
+            Policy::Exit => Action::Exit,
+        }
+    }
+}
+
+impl Supervisor {
+    /// Records a restart at `now`, unless there have already been
+    /// `max_restarts` within the [`RESTART_WINDOW`] before it.
+    fn try_restart(&mut self, now: Instant) -> bool {
+        while self
+            .restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) >= RESTART_WINDOW)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.options.supervisor.max_restarts as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
+
+impl std::fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subsystem::Chat => f.write_str("chat"),
+            Subsystem::Eventsub => f.write_str("EventSub"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::error::ChatClientError;
+    use crate::eventsub::error::EventsubError;
+
+    fn errors() -> Vec<(BotError, bool)> {
+        vec![
+            (BotError::Chat(ChatClientError::AuthIncomplete), true),
+            (BotError::Eventsub(EventsubError::WelcomeIncomplete), true),
+            (BotError::Say(irc::error::Error::PingTimeout), false),
+            (BotError::IO(std::io::ErrorKind::Other.into()), false),
+            (BotError::Close, false),
+            (BotError::Custom(String::from("oops")), false),
+        ]
+    }
+
+    fn with_policies(recoverable: Policy, fatal: Policy, max_restarts: u32) -> Supervisor {
+        let mut options = Options::default();
+        options.supervisor = crate::options::Supervisor {
+            recoverable,
+            fatal,
+            max_restarts,
+        };
+        Supervisor::new(options)
+    }
+
+    #[tokio::test]
+    async fn policies_pick_actions() {
+        let bot = BotInterface::mock().await;
+        let subsystem = Some(Subsystem::Chat);
+        for (err, is_fatal) in errors() {
+            assert_eq!(err.is_fatal(), is_fatal, "{err}");
+            for (policy, expected) in [
+                (Policy::Log, Action::Continue),
+                (Policy::Notify, Action::Continue),
+                (Policy::Restart, Action::Restart(Subsystem::Chat)),
+                (Policy::Exit, Action::Exit),
+            ] {
+                // only the policy for the error's kind applies
+                let (fatal, recoverable) = if is_fatal {
+                    (policy, Policy::Exit)
+                } else {
+                    (Policy::Exit, policy)
+                };
+                let mut supervisor = with_policies(recoverable, fatal, 5);
+                assert_eq!(
+                    supervisor.handle(&err, subsystem, &bot).await,
+                    expected,
+                    "{err} with {policy:?}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn restarting_without_a_subsystem_continues() {
+        let bot = BotInterface::mock().await;
+        let mut supervisor = with_policies(Policy::Restart, Policy::Restart, 5);
+        for (err, _) in errors() {
+            assert_eq!(
+                supervisor.handle(&err, None, &bot).await,
+                Action::Continue,
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn restarts_run_out_within_the_window() {
+        let bot = BotInterface::mock().await;
+        let err = BotError::Eventsub(EventsubError::WelcomeIncomplete);
+        let mut supervisor = with_policies(Policy::Log, Policy::Restart, 2);
+        for expected in [
+            Action::Restart(Subsystem::Eventsub),
+            Action::Restart(Subsystem::Eventsub),
+            Action::Exit,
+        ] {
+            assert_eq!(
+                supervisor
+                    .handle(&err, Some(Subsystem::Eventsub), &bot)
+                    .await,
+                expected
+            );
+        }
+
+        let mut supervisor = with_policies(Policy::Log, Policy::Restart, 2);
+        let start = Instant::now();
+        assert!(supervisor.try_restart(start));
+        assert!(supervisor.try_restart(start + RESTART_WINDOW / 2));
+        assert!(!supervisor.try_restart(start + RESTART_WINDOW / 2));
+        // the first restart has left the window
+        assert!(supervisor.try_restart(start + RESTART_WINDOW));
+        assert!(!supervisor.try_restart(start + RESTART_WINDOW));
+    }
+}
@@ -2,6 +2,7 @@ use super::data::{ChatAccess, ChatEvent, ChatMessage};
 use super::error::ChatClientError;
 use super::interface::ChatInterface;
 use crate::chat::tag;
+use crate::log::{self, Target};
 use futures_util::StreamExt;
 use irc::client::Client;
 use irc::proto::{Command, Response};
@@ -19,7 +20,17 @@ pub struct ChatClient {
     joined_users: HashSet<String>,
     interface: super::interface::ChatInterface,
     options: crate::options::Options,
-    event_sender: watch::Sender<ChatEvent>,
+    event_sender: Arc<watch::Sender<ChatEvent>>,
+}
+
+/// Reconnects a [`ChatClient`] after it stops, keeping its interface and
+/// subscribers.
+#[derive(Debug, Clone)]
+pub struct ChatReconnector {
+    data: super::data::ChatClientData,
+    interface: ChatInterface,
+    event_sender: Arc<watch::Sender<ChatEvent>>,
+    options: crate::options::Options,
 }
 
 impl ChatClient {
@@ -27,8 +38,79 @@ impl ChatClient {
         data: super::data::ChatClientData,
         options: crate::options::Options,
     ) -> Result<Self, ChatClientError> {
-        options.debug("Chat: Connecting to Twitch");
+        log::debug(Target::Chat, "Connecting to Twitch");
 
+        let (client, stream) = ChatClient::connect(&data).await?;
+        Ok(ChatClient {
+            joined_users: HashSet::new(),
+            interface: ChatInterface::new(client.clone(), data.chat_channel.clone()),
+            event_sender: Arc::new(watch::channel(ChatEvent::ClearChat).0),
+            data,
+            stream,
+            client,
+            options,
+        })
+    }
+
+    /// A client that isn't connected to Twitch, for testing handlers. What it
+    /// sends is dropped, and it can't be run.
+    #[cfg(test)]
+    pub(crate) async fn mock(chat_channel: &str) -> Self {
+        Self::from_mock_config(chat_channel, mock_config()).await
+    }
+    /// A client whose connection logs in, joins `chat_channel`, and is then
+    /// closed by Twitch.
+    #[cfg(test)]
+    pub(crate) async fn mock_closing(chat_channel: &str) -> Self {
+        let lines = [
+            String::from(
+                ":tmi.twitch.tv CAP * ACK :twitch.tv/membership twitch.tv/tags twitch.tv/commands",
+            ),
+            String::from(":tmi.twitch.tv 001 eyebot :Welcome, GLHF!"),
+            String::from(":tmi.twitch.tv 002 eyebot :Your host is tmi.twitch.tv"),
+            String::from(":tmi.twitch.tv 003 eyebot :This server is rather new"),
+            String::from(":tmi.twitch.tv 004 eyebot :-"),
+            String::from(":tmi.twitch.tv 375 eyebot :-"),
+            String::from(":tmi.twitch.tv 372 eyebot :You are in a maze of twisty passages."),
+            String::from(":tmi.twitch.tv 376 eyebot :>"),
+            String::from("@user-id=1 :tmi.twitch.tv GLOBALUSERSTATE"),
+            format!(":eyebot!eyebot@eyebot.tmi.twitch.tv JOIN #{chat_channel}"),
+            format!(":eyebot.tmi.twitch.tv 353 eyebot = #{chat_channel} :eyebot"),
+            format!(":eyebot.tmi.twitch.tv 366 eyebot #{chat_channel} :End of /NAMES list"),
+            format!("@mod=0 :tmi.twitch.tv USERSTATE #{chat_channel}"),
+            format!("@room-id=1 :tmi.twitch.tv ROOMSTATE #{chat_channel}"),
+        ];
+        let config = irc::client::prelude::Config {
+            mock_initial_value: Some(lines.map(|line| line + "\r\n").concat()),
+            ..mock_config()
+        };
+        Self::from_mock_config(chat_channel, config).await
+    }
+    #[cfg(test)]
+    async fn from_mock_config(chat_channel: &str, config: irc::client::prelude::Config) -> Self {
+        let mut client = Client::from_config(config)
+            .await
+            .expect("Mock connections always succeed");
+        let stream = client.stream().expect("The stream is only taken once");
+        let client = Arc::new(client);
+        ChatClient {
+            joined_users: HashSet::new(),
+            interface: ChatInterface::new(client.clone(), String::from(chat_channel)),
+            event_sender: Arc::new(watch::channel(ChatEvent::ClearChat).0),
+            data: super::data::ChatClientData {
+                access: ChatAccess::Implicit(String::from("mock")),
+                bot_username: String::from("eyebot"),
+                chat_channel: String::from(chat_channel),
+            },
+            stream,
+            client,
+            options: crate::options::Options::default(),
+        }
+    }
+
+    async fn connect(
+        data: &super::data::ChatClientData,
+    ) -> Result<(Arc<Client>, irc::client::ClientStream), ChatClientError> {
         let mut client = Client::from_config(irc::client::prelude::Config {
             owners: vec![String::from("eyebot-rs")],
             nickname: Some(data.bot_username.clone()),
@@ -38,17 +120,17 @@ impl ChatClient {
         })
         .await?;
         let stream = client.stream()?;
-        let client = Arc::new(client);
+        Ok((Arc::new(client), stream))
+    }
 
-        Ok(ChatClient {
-            joined_users: HashSet::new(),
-            interface: ChatInterface::new(client.clone(), data.chat_channel.clone()),
-            event_sender: watch::channel(ChatEvent::ClearChat).0,
-            data,
-            stream,
-            client,
-            options,
-        })
+    #[must_use]
+    pub fn reconnector(&self) -> ChatReconnector {
+        ChatReconnector {
+            data: self.data.clone(),
+            interface: self.interface.clone(),
+            event_sender: self.event_sender.clone(),
+            options: self.options.clone(),
+        }
     }
 
     pub fn on_chat<Fut: Future>(
@@ -90,7 +172,7 @@ impl ChatClient {
     }
 
     async fn handle_chat_messages(mut self) -> Result<(), ChatClientError> {
-        self.options.debug("Chat: Ready to receive messages!");
+        log::debug(Target::Chat, "Ready to receive messages!");
 
         while let Some(message) = self.stream.next().await.transpose()? {
             match message.command {
@@ -104,6 +186,11 @@ impl ChatClient {
                     )
                     .expect("Tags are always well formed");
 
+                    let login = match message.prefix {
+                        Some(irc::proto::Prefix::Nickname(login, _, _)) => login,
+                        _ => String::new(),
+                    };
+
                     // TODO: stop sending on error
                     let chat_message = ChatMessage {
                         badges: tags.badges,
@@ -111,18 +198,22 @@ impl ChatClient {
                         channel: self.data.chat_channel.clone(),
                         text,
                         user_id: tags.user_id,
+                        login,
+                        room_id: tags.room_id,
                         is_moderator: tags.is_mod,
                         is_subscriber: tags.subscriber,
+                        is_vip: tags.vip,
                         emotes: tags.emotes,
                         display_name: tags.display_name,
                         name_color: tags.color,
                     };
 
-                    self.options.debug(format!(
-                        "Chat: {}> {:?}",
-                        chat_message.display_name, chat_message.text
-                    ));
+                    log::debug(
+                        Target::Chat,
+                        format!("{}> {:?}", chat_message.display_name, chat_message.text),
+                    );
 
+                    crate::metrics::METRICS.chat_messages_received.inc();
                     let _ = self.interface.0.message_channel.send(chat_message);
                 }
                 Command::JOIN(_, _, _) => {
@@ -172,7 +263,7 @@ impl ChatClient {
                                 &message.tags.expect("Message always has tags"),
                             )
                             .expect("Tags are always well formed");
-                            println!("{tags:?}");
+                            log::trace(Target::Chat, format!("CLEARMSG {tags:?}"));
                             // TODO: send chatevent here
                         }
                         "HOSTTARGET" => todo!(),
@@ -183,7 +274,7 @@ impl ChatClient {
                                 &message.tags.expect("Message always has tags"),
                             )
                             .expect("Tags are always well formed");
-                            println!("{tags:?}");
+                            log::trace(Target::Chat, format!("USERNOTICE {tags:?}"));
                         }
                         // TODO: handle userstates
                         "USERSTATE" => (),
@@ -197,7 +288,8 @@ impl ChatClient {
                 _ => return Err(ChatClientError::ChatUnrecognized(message)),
             }
         }
-        unreachable!("Chat connection closed")
+        log::debug(Target::Chat, "Twitch closed the connection");
+        Ok(())
     }
 
     async fn handle_auth_messages(mut self) -> Result<Self, ChatClientError> {
@@ -214,7 +306,7 @@ impl ChatClient {
             globaluserstate: bool,
         }
 
-        self.options.debug("Chat: Authenticating with Twitch IRC");
+        log::debug(Target::Chat, "Authenticating with Twitch IRC");
 
         self.client.send(Command::CAP(
             None,
@@ -291,7 +383,7 @@ impl ChatClient {
             roomstate: bool,
         }
 
-        self.options.debug("Chat: Joining Twitch IRC");
+        log::debug(Target::Chat, "Joining Twitch IRC");
 
         self.client.send(Command::JOIN(
             format!("#{}", self.data.chat_channel),
@@ -342,3 +434,33 @@ impl ChatClient {
         Err(ChatClientError::JoinIncomplete)
     }
 }
+
+impl ChatReconnector {
+    /// Connects a new [`ChatClient`] that shares the old one's interface, so
+    /// existing handlers keep receiving messages once it runs.
+    pub async fn reconnect(&self) -> Result<ChatClient, ChatClientError> {
+        log::debug(Target::Chat, "Reconnecting to Twitch");
+
+        let (client, stream) = ChatClient::connect(&self.data).await?;
+        self.interface.set_irc_client(client.clone());
+        Ok(ChatClient {
+            joined_users: HashSet::new(),
+            interface: self.interface.clone(),
+            event_sender: self.event_sender.clone(),
+            data: self.data.clone(),
+            stream,
+            client,
+            options: self.options.clone(),
+        })
+    }
+}
+
+/// The configuration of an IRC client that isn't connected to anything.
+#[cfg(test)]
+pub(super) fn mock_config() -> irc::client::prelude::Config {
+    irc::client::prelude::Config {
+        nickname: Some(String::from("eyebot")),
+        use_mock_connection: true,
+        ..Default::default()
+    }
+}
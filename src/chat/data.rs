@@ -2,14 +2,14 @@ pub use super::tag::EmoteInfo;
 use crate::auth::access::AccessTokenManager;
 use std::collections::HashSet;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChatClientData {
     pub access: ChatAccess,
     pub bot_username: String,
     pub chat_channel: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ChatAccess {
     Authorization(AccessTokenManager),
     Implicit(String),
@@ -21,8 +21,13 @@ pub struct ChatMessage {
     pub channel: String,
     pub text: String,
     pub user_id: String,
+    /// The chatter's login name, i.e. their lowercase username.
+    pub login: String,
+    /// The broadcaster's user id.
+    pub room_id: String,
     pub is_moderator: bool,
     pub is_subscriber: bool,
+    pub is_vip: bool,
     pub emotes: Vec<EmoteInfo>,
     pub display_name: String,
     pub name_color: Option<String>,
@@ -8,7 +8,8 @@ pub struct ChatInterface(pub(super) Arc<InterfaceData>);
 #[derive(Debug)]
 pub(super) struct InterfaceData {
     pub(super) twitch_channel: String,
-    pub(super) irc_client: Arc<irc::client::Client>,
+    /// Replaced when the chat client reconnects.
+    pub(super) irc_client: std::sync::RwLock<Arc<irc::client::Client>>,
     pub(super) message_channel: watch::Sender<ChatMessage>,
 }
 
@@ -16,33 +17,62 @@ impl ChatInterface {
     pub(super) fn new(irc_client: Arc<irc::client::Client>, twitch_channel: String) -> Self {
         Self(Arc::new(InterfaceData {
             twitch_channel,
-            irc_client,
+            irc_client: std::sync::RwLock::new(irc_client),
             message_channel: watch::channel(ChatMessage::default()).0,
         }))
     }
 
     pub fn say<S: Into<String>>(&self, message: S) -> irc::error::Result<()> {
-        self.0.irc_client.send(irc::proto::Command::PRIVMSG(
-            format!("#{}", self.0.twitch_channel),
-            message.into(),
-        ))
+        self.irc_client()
+            .send(irc::proto::Command::PRIVMSG(
+                format!("#{}", self.0.twitch_channel),
+                message.into(),
+            ))
+            .map(|()| crate::metrics::METRICS.chat_messages_sent.inc())
     }
     pub fn reply<S: Into<String>>(
         &self,
         target: &ChatMessage,
         message: S,
     ) -> irc::error::Result<()> {
-        self.0.irc_client.send(irc::proto::Message {
-            tags: Some(vec![irc::proto::message::Tag(
-                String::from("reply-parent-msg-id"),
-                Some(target.id.clone()),
-            )]),
-            prefix: None,
-            command: irc::proto::Command::PRIVMSG(
-                format!("#{}", self.0.twitch_channel),
-                message.into(),
-            ),
-        })
+        self.irc_client()
+            .send(irc::proto::Message {
+                tags: Some(vec![irc::proto::message::Tag(
+                    String::from("reply-parent-msg-id"),
+                    Some(target.id.clone()),
+                )]),
+                prefix: None,
+                command: irc::proto::Command::PRIVMSG(
+                    format!("#{}", self.0.twitch_channel),
+                    message.into(),
+                ),
+            })
+            .map(|()| crate::metrics::METRICS.chat_messages_sent.inc())
+    }
+    pub fn part(&self) -> irc::error::Result<()> {
+        self.irc_client().send(irc::proto::Command::PART(
+            format!("#{}", self.0.twitch_channel),
+            None,
+        ))
+    }
+    /// The login name of the channel that the bot is in.
+    #[must_use]
+    pub fn channel(&self) -> &str {
+        &self.0.twitch_channel
+    }
+    pub(super) fn set_irc_client(&self, irc_client: Arc<irc::client::Client>) {
+        *self
+            .0
+            .irc_client
+            .write()
+            .expect("The IRC client lock is never poisoned") = irc_client;
+    }
+    fn irc_client(&self) -> Arc<irc::client::Client> {
+        self.0
+            .irc_client
+            .read()
+            .expect("The IRC client lock is never poisoned")
+            .clone()
     }
     pub fn mock_message<S: Into<String>>(&self, mock: ChatMessage, text: S) {
         let _ = self.0.message_channel.send(ChatMessage {
@@ -50,4 +80,13 @@ impl ChatInterface {
             ..mock
         });
     }
+    /// An interface to `twitch_channel` that isn't connected to Twitch. What
+    /// it sends is dropped.
+    #[cfg(test)]
+    pub(crate) async fn mock(twitch_channel: &str) -> Self {
+        let irc_client = irc::client::Client::from_config(super::client::mock_config())
+            .await
+            .expect("Mock connections always succeed");
+        Self::new(Arc::new(irc_client), String::from(twitch_channel))
+    }
 }
@@ -2,4 +2,6 @@ pub mod client;
 pub mod data;
 pub mod error;
 pub mod interface;
+// TODO: use the remaining parsed tags
+#[allow(dead_code)]
 mod tag;
@@ -30,6 +30,8 @@ pub struct CLEARMSGTags {
 pub struct PRIVMSGTags {
     pub id: String,
     pub user_id: String,
+    /// The broadcaster's user id.
+    pub room_id: String,
     pub display_name: String,
     pub badges: HashMap<String, String>,
     pub bits: Option<u32>,
@@ -187,6 +189,7 @@ impl Tags for PRIVMSGTags {
             Self {
                 id: id.expect("Tag always has a value"),
                 user_id: user_id.expect("Tag always has a value"),
+                room_id: tags.remove("room-id").flatten().unwrap_or_default(),
                 display_name: display_name.expect("Tag always has a value"),
                 badges,
                 bits: bits.map(|tag| {
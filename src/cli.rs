@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "eyebot-rs")]
@@ -19,4 +19,43 @@ pub struct Cli {
     pub reauth: bool,
     #[arg(short = 'o', long = "options-file")]
     pub options_file: Option<String>,
+    /// Receive EventSub notifications through a webhook at this public HTTPS
+    /// URL instead of through a websocket.
+    #[arg(long = "webhook-callback", requires = "webhook_secret")]
+    pub webhook_callback: Option<String>,
+    #[arg(long = "webhook-secret")]
+    pub webhook_secret: Option<String>,
+    #[arg(long = "webhook-address", default_value = "0.0.0.0:8080")]
+    pub webhook_address: String,
+    #[arg(long = "eventsub-url", default_value = "wss://eventsub.wss.twitch.tv/ws")]
+    pub eventsub_url: String,
+    #[arg(
+        long = "eventsub-subscriptions-url",
+        default_value = "https://api.twitch.tv/helix/eventsub/subscriptions"
+    )]
+    pub eventsub_subscriptions_url: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage existing EventSub subscriptions instead of running the bot.
+    Eventsub {
+        /// Use an App Access Token, which is needed to see webhook subscriptions.
+        #[arg(long)]
+        app: bool,
+        #[command(subcommand)]
+        action: EventsubAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EventsubAction {
+    /// Lists every subscription and their total cost.
+    List,
+    /// Deletes the subscription with the given id.
+    Delete { id: String },
+    /// Deletes every subscription that failed or was revoked or disconnected.
+    Prune,
 }
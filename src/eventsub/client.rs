@@ -1,9 +1,12 @@
-use super::data::{self, EventsubClientData, NotificationMessage};
+use super::data::{self, EventsubClientData, NotificationMessage, Transport};
 use super::error::EventsubError;
 use super::event::Event;
 use super::outbound;
+use super::webhook::WebhookListener;
+use crate::log::{self, Target};
 use futures_util::StreamExt;
 use std::future::Future;
+use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::watch;
 use tokio_tungstenite::tungstenite::Message;
@@ -13,36 +16,85 @@ type Websocket =
 
 #[derive(Debug)]
 pub struct EventsubClient {
-    websocket: Websocket,
+    connection: Connection,
     session_id: String,
     data: EventsubClientData,
-    interface: watch::Sender<serde_json::Value>,
+    interface: Arc<watch::Sender<serde_json::Value>>,
     options: crate::options::Options,
 }
 
+/// Reconnects an [`EventsubClient`] after it stops, keeping its subscribers.
+#[derive(Debug, Clone)]
+pub struct EventsubReconnector {
+    data: EventsubClientData,
+    interface: Arc<watch::Sender<serde_json::Value>>,
+    options: crate::options::Options,
+}
+
+#[derive(Debug)]
+enum Connection {
+    Websocket(Box<Websocket>),
+    Webhook(Box<WebhookListener>),
+}
+
 impl EventsubClient {
     pub async fn new(
         data: EventsubClientData,
         options: crate::options::Options,
     ) -> Result<Self, EventsubError> {
-        options.debug("Eventsub: Connecting to Twitch");
+        EventsubClient::with_interface(
+            data,
+            Arc::new(watch::channel(serde_json::Value::Null).0),
+            options,
+        )
+        .await
+    }
+
+    async fn with_interface(
+        data: EventsubClientData,
+        interface: Arc<watch::Sender<serde_json::Value>>,
+        options: crate::options::Options,
+    ) -> Result<Self, EventsubError> {
+        let connection = match &data.transport {
+            Transport::Websocket => {
+                log::debug(Target::Eventsub, "Connecting to Twitch");
 
-        let websocket = EventsubClient::connect_websocket()
-            .await
-            .map_err(EventsubError::OnConnect)?;
+                Connection::Websocket(Box::new(
+                    EventsubClient::connect_websocket(&data.urls.websocket)
+                        .await
+                        .map_err(EventsubError::OnConnect)?,
+                ))
+            }
+            Transport::Webhook(transport) => {
+                log::debug(Target::Eventsub, "Starting webhook server");
+
+                Connection::Webhook(Box::new(
+                    WebhookListener::new(transport).map_err(EventsubError::OnWebhookCreate)?,
+                ))
+            }
+        };
 
         Ok(Self {
             session_id: String::new(),
-            websocket,
+            connection,
             data,
-            interface: watch::channel(serde_json::Value::Null).0,
+            interface,
             options,
         })
     }
 
-    async fn connect_websocket() -> tokio_tungstenite::tungstenite::Result<Websocket> {
+    #[must_use]
+    pub fn reconnector(&self) -> EventsubReconnector {
+        EventsubReconnector {
+            data: self.data.clone(),
+            interface: self.interface.clone(),
+            options: self.options.clone(),
+        }
+    }
+
+    async fn connect_websocket(url: &str) -> tokio_tungstenite::tungstenite::Result<Websocket> {
         let (websocket, _) = tokio_tungstenite::connect_async_tls_with_config(
-            "wss://eventsub.wss.twitch.tv/ws",
+            url,
             None,
             true,
             Some(tokio_tungstenite::Connector::Rustls(
@@ -53,21 +105,28 @@ impl EventsubClient {
         Ok(websocket)
     }
 
-    async fn reconnect(&mut self) -> Result<(), EventsubError> {
-        self.options.debug("Eventsub: Reconnecting to twitch");
+    /// Subscriptions carry over to the new session when Twitch gives a
+    /// `reconnect_url`, so they are only resent when it doesn't.
+    async fn reconnect(&mut self, reconnect_url: Option<String>) -> Result<(), EventsubError> {
+        log::debug(Target::Eventsub, "Reconnecting to twitch");
+        crate::metrics::METRICS.reconnects.inc("eventsub_session");
+
+        let new_websocket = EventsubClient::connect_websocket(
+            reconnect_url.as_deref().unwrap_or(&self.data.urls.websocket),
+        )
+        .await
+        .map_err(EventsubError::OnReconnect)?;
+        let mut old_websocket = std::mem::replace(self.websocket(), new_websocket);
+
+        self.handle_welcome_message(reconnect_url.is_none()).await?;
 
-        self.websocket
+        old_websocket
             .close(Some(tokio_tungstenite::tungstenite::protocol::CloseFrame {
                 code: tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Normal,
                 reason: std::borrow::Cow::Owned(String::from("Reconnecting.")),
             }))
             .await
             .map_err(EventsubError::OnReconnect)?;
-        self.websocket = EventsubClient::connect_websocket()
-            .await
-            .map_err(EventsubError::OnReconnect)?;
-
-        self.handle_welcome_message().await?;
         Ok(())
     }
 
@@ -87,13 +146,27 @@ impl EventsubClient {
     }
     pub fn on_event<E: Event, Fut: Future>(
         &self,
-        f: impl FnMut(NotificationMessage<E>) -> Fut,
+        mut f: impl FnMut(NotificationMessage<E>) -> Fut,
     ) -> impl Future<Output = ()> {
-        self.on_message(f)
+        let mut receiver = self.interface.subscribe();
+        async move {
+            while receiver.changed().await.is_ok() {
+                let value = receiver.borrow().clone();
+                if let Some(notification) = data::RawNotificationMessage::from_value(value)
+                    .and_then(data::RawNotificationMessage::into_event)
+                {
+                    f(notification).await;
+                }
+            }
+        }
     }
 
     pub async fn run(mut self) -> Result<(), EventsubError> {
-        self.handle_welcome_message().await?;
+        if let Connection::Webhook(_) = self.connection {
+            return self.handle_webhook().await;
+        }
+
+        self.handle_welcome_message(true).await?;
         self.handle_messages().await?;
 
         Ok(())
@@ -104,10 +177,10 @@ impl EventsubClient {
     }
 
     async fn handle_messages(mut self) -> Result<(), EventsubError> {
-        self.options.debug("Eventsub: Ready to receive messages!");
+        log::debug(Target::Eventsub, "Ready to receive messages!");
 
         while let Some(message) = self
-            .websocket
+            .websocket()
             .next()
             .await
             .transpose()
@@ -123,7 +196,8 @@ impl EventsubClient {
                         serde_json::from_value::<data::ReconnectMessage>(json.clone())
                     {
                         if message.metadata.message_type == "session_reconnect" {
-                            self.reconnect().await?;
+                            self.reconnect(message.payload.session.reconnect_url)
+                                .await?;
                             continue;
                         }
                     }
@@ -135,11 +209,12 @@ impl EventsubClient {
                         }
                     }
 
+                    crate::metrics::METRICS.eventsub_message(&json);
                     // TODO: stop sending on error
                     let _ = self.interface.send(json);
                 }
                 Message::Ping(data) => self
-                    .websocket
+                    .websocket()
                     .get_mut()
                     .write_all(&Message::Pong(data).into_data())
                     .await
@@ -150,11 +225,11 @@ impl EventsubClient {
         }
         Ok(())
     }
-    async fn handle_welcome_message(&mut self) -> Result<(), EventsubError> {
-        self.options.debug("Eventsub: Receiving Welcome message");
+    async fn handle_welcome_message(&mut self, subscribe: bool) -> Result<(), EventsubError> {
+        log::debug(Target::Eventsub, "Receiving Welcome message");
 
         while let Some(message) = self
-            .websocket
+            .websocket()
             .next()
             .await
             .transpose()
@@ -167,22 +242,33 @@ impl EventsubClient {
                     };
                     self.session_id = welcome.payload.session.id;
 
-                    self.options.debug("Eventsub: Subscribing to events");
+                    if !subscribe || self.data.subscriptions.is_empty() {
+                        return Ok(());
+                    }
+
+                    log::debug(Target::Eventsub, "Subscribing to events");
 
-                    outbound::send_subscriptions(
-                        &self.data.subscriptions,
-                        &self.session_id,
-                        &crate::twitch::HelixAuth {
-                            client_id: self.data.client_id.clone(),
-                            access: self.data.access.clone(),
-                        },
+                    let access_token = self
+                        .data
+                        .access
+                        .get_credentials()
+                        .await
+                        .map_err(EventsubError::Access)?
+                        .access_token;
+                    EventsubClient::send_subscriptions(
+                        &self.data,
+                        serde_json::json!({
+                            "method": "websocket",
+                            "session_id": self.session_id,
+                        }),
+                        &access_token,
                     )
-                    .await?;
+                    .await;
 
                     return Ok(());
                 }
                 Message::Ping(data) => self
-                    .websocket
+                    .websocket()
                     .get_mut()
                     .write_all(&Message::Pong(data).into_data())
                     .await
@@ -193,4 +279,87 @@ impl EventsubClient {
 
         Err(EventsubError::WelcomeIncomplete)
     }
+
+    async fn handle_webhook(self) -> Result<(), EventsubError> {
+        let EventsubClient {
+            connection: Connection::Webhook(listener),
+            data,
+            interface,
+            ..
+        } = self
+        else {
+            unreachable!("EventsubClient::handle_webhook is only called on webhook connections");
+        };
+        let Transport::Webhook(transport) = &data.transport else {
+            unreachable!("Webhook connections always have a webhook transport");
+        };
+
+        // webhook subscriptions require an app access token
+        let access_token = data
+            .access
+            .get_app_access_token()
+            .await
+            .map_err(EventsubError::Access)?;
+
+        // Twitch verifies the callback while the subscriptions are being
+        // created, so the listener has to be running first
+        let listener = tokio::spawn(listener.run(interface));
+
+        log::debug(Target::Eventsub, "Subscribing to events");
+        EventsubClient::send_subscriptions(
+            &data,
+            serde_json::json!({
+                "method": "webhook",
+                "callback": transport.callback_url,
+                "secret": transport.secret,
+            }),
+            &access_token,
+        )
+        .await;
+
+        log::debug(Target::Eventsub, "Ready to receive messages!");
+
+        listener.await.expect("Webhook listener panicked")
+    }
+
+    async fn send_subscriptions(
+        data: &EventsubClientData,
+        transport: serde_json::Value,
+        access_token: &str,
+    ) {
+        for (subscription, err) in outbound::send_subscriptions(
+            &data.urls.subscriptions,
+            &data.subscriptions,
+            transport,
+            &data.client_id,
+            access_token,
+        )
+        .await
+        {
+            log::warn(
+                Target::Eventsub,
+                format!("Could not subscribe to {subscription}: {err}"),
+            );
+        }
+    }
+
+    fn websocket(&mut self) -> &mut Websocket {
+        match &mut self.connection {
+            Connection::Websocket(websocket) => websocket,
+            Connection::Webhook(_) => {
+                unreachable!("Websocket methods are only called on websocket connections")
+            }
+        }
+    }
+}
+
+impl EventsubReconnector {
+    /// Connects a new [`EventsubClient`] that shares the old one's
+    /// subscribers. Subscriptions are recreated once it runs.
+    pub async fn reconnect(&self) -> Result<EventsubClient, EventsubError> {
+        log::debug(Target::Eventsub, "Reconnecting after an error");
+
+        EventsubClient::with_interface(self.data.clone(), self.interface.clone(), self.options.clone())
+            .await
+    }
 }
@@ -1,38 +1,72 @@
 use crate::auth::access::AccessTokenManager;
+use crate::log::{self, Target};
 use serde::Deserialize;
 
 pub type WelcomeMessage = Message<payload::Welcome>;
 pub type KeepaliveMessage = Message<payload::Keepalive>;
 pub type NotificationMessage<E> = Message<payload::Notification<E>>;
+/// A notification whose event hasn't been deserialized yet.
+pub type RawNotificationMessage = NotificationMessage<serde_json::Value>;
 pub type ReconnectMessage = Message<payload::Reconnect>;
 pub type RevocationMessage = Message<payload::Revocation>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EventsubClientData {
     pub client_id: String,
     pub access: AccessTokenManager,
     pub subscriptions: Vec<super::subscription::Subscription>,
+    pub transport: Transport,
+    pub urls: EventsubUrls,
 }
 
-#[derive(Debug, Deserialize)]
+/// Where the client connects to. Defaults to Twitch's servers.
+#[derive(Debug, Clone)]
+pub struct EventsubUrls {
+    /// The websocket server to connect to when using [`Transport::Websocket`].
+    pub websocket: String,
+    /// The Helix endpoint to create subscriptions with.
+    pub subscriptions: String,
+}
+
+/// How Twitch delivers EventSub notifications to the client.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    Websocket,
+    Webhook(WebhookTransport),
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookTransport {
+    /// The public HTTPS URL Twitch sends notifications to. Must use port 443.
+    pub callback_url: String,
+    /// Between 10 and 100 ASCII characters. Used to sign every request Twitch
+    /// sends to `callback_url`.
+    pub secret: String,
+    /// The local address to listen on. Must not contain a protocol, i.e. must
+    /// be in the format of `address:port`.
+    pub host_address: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Message<P> {
     pub metadata: MessageMetadata,
     pub payload: P,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MessageMetadata {
     pub message_id: String,
     pub message_type: String,
     pub message_timestamp: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct EventSession {
     pub id: String,
     pub status: String,
     pub connected_at: String,
-    pub keepalive_timeout_seconds: u64,
+    /// `None` in `session_reconnect` messages.
+    pub keepalive_timeout_seconds: Option<u64>,
     pub reconnect_url: Option<String>,
 }
 
@@ -40,36 +74,102 @@ pub mod payload {
     use super::EventSession;
     use serde::Deserialize;
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Welcome {
         pub session: EventSession,
     }
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Keepalive {}
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Notification<E> {
         pub subscription: Subscription,
         pub event: E,
     }
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Reconnect {
         pub session: EventSession,
     }
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Revocation {
         pub subscription: Subscription,
     }
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Subscription {
         pub id: String,
         pub status: String,
-        pub version: String,
         #[serde(flatten)]
         pub subscription: super::super::subscription::Subscription,
     }
 }
 
+impl Default for EventsubUrls {
+    fn default() -> Self {
+        Self {
+            websocket: String::from("wss://eventsub.wss.twitch.tv/ws"),
+            subscriptions: String::from("https://api.twitch.tv/helix/eventsub/subscriptions"),
+        }
+    }
+}
+
+impl RawNotificationMessage {
+    /// Parses a message received from Twitch, returning `None` if it isn't a
+    /// notification.
+    #[must_use]
+    pub fn from_value(value: serde_json::Value) -> Option<Self> {
+        let message = serde_json::from_value::<Self>(value).ok()?;
+        (message.metadata.message_type == "notification").then_some(message)
+    }
+
+    #[must_use]
+    pub fn subscription_type(&self) -> &str {
+        &self.payload.subscription.subscription.subscription_type
+    }
+
+    /// Deserializes the event if this notification is for `E`'s subscription
+    /// type. An event that doesn't match `E` is logged and skipped.
+    #[must_use]
+    pub fn into_event<E: super::event::Event>(self) -> Option<NotificationMessage<E>> {
+        if self.subscription_type() != E::SUBSCRIPTION_TYPE {
+            return None;
+        }
+        let subscription = self.payload.subscription.subscription.to_string();
+        self.map_event(serde_json::from_value)
+            .map_err(|err| {
+                log::warn(
+                    Target::Eventsub,
+                    format!("Could not parse a {subscription} event: {err}"),
+                );
+            })
+            .ok()
+    }
+
+    /// Deserializes the event into the [`AnyEvent`](super::event::AnyEvent)
+    /// variant for this notification's subscription type.
+    ///
+    /// # Errors
+    /// Returns an error if the event doesn't match its subscription type's event.
+    pub fn into_any_event(
+        self,
+    ) -> Result<NotificationMessage<super::event::AnyEvent>, serde_json::Error> {
+        let subscription_type = String::from(self.subscription_type());
+        self.map_event(|event| super::event::AnyEvent::parse(&subscription_type, event))
+    }
+
+    fn map_event<E>(
+        self,
+        f: impl FnOnce(serde_json::Value) -> Result<E, serde_json::Error>,
+    ) -> Result<NotificationMessage<E>, serde_json::Error> {
+        Ok(Message {
+            metadata: self.metadata,
+            payload: payload::Notification {
+                subscription: self.payload.subscription,
+                event: f(self.payload.event)?,
+            },
+        })
+    }
+}
+
 impl<P> PartialEq for Message<P> {
     fn eq(&self, other: &Self) -> bool {
         self.metadata.message_id == other.metadata.message_id
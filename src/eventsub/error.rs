@@ -13,6 +13,9 @@ pub enum EventsubError {
     Twitch(TwitchError),
     OnReceive(tokio_tungstenite::tungstenite::Error),
     ReceiveInvalid,
+    OnWebhookCreate(Box<dyn std::error::Error + Send + Sync>),
+    OnWebhookReceive(std::io::Error),
+    ManageInvalid(serde_json::Error),
 }
 
 impl std::fmt::Display for EventsubError {
@@ -49,6 +52,15 @@ impl std::fmt::Display for EventsubError {
             EventsubError::ReceiveInvalid => {
                 f.write_str("Eventsub: Message received was not valid JSON")
             }
+            EventsubError::OnWebhookCreate(err) => f.write_fmt(format_args!(
+                "Eventsub error while creating the webhook server: {err}"
+            )),
+            EventsubError::OnWebhookReceive(err) => f.write_fmt(format_args!(
+                "Eventsub error while receiving a webhook request: {err}"
+            )),
+            EventsubError::ManageInvalid(err) => f.write_fmt(format_args!(
+                "Eventsub: Invalid subscription list received from Twitch: {err}"
+            )),
         }
     }
 }
@@ -1,16 +1,28 @@
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::Value;
 use std::collections::HashMap;
 
-/// Trait is sealed.
-pub trait Event: DeserializeOwned + sealed::Sealed {}
-mod sealed {
-    use super::{ChannelPointRedeem, Raid, StreamOnline, Subscription};
-    pub trait Sealed {}
-    impl Sealed for ChannelPointRedeem {}
-    impl Sealed for Subscription {}
-    impl Sealed for Raid {}
-    impl Sealed for StreamOnline {}
+/// The `event` field of an EventSub notification. Implement this to receive
+/// events of subscription types this crate doesn't define.
+pub trait Event: DeserializeOwned {
+    /// The subscription type (e.g. `channel.raid`) this event is sent for.
+    const SUBSCRIPTION_TYPE: &'static str;
+}
+
+/// Any event, routed by its notification's subscription type.
+#[derive(Debug)]
+pub enum AnyEvent {
+    ChannelPointRedeem(ChannelPointRedeem),
+    Subscription(Subscription),
+    Raid(Raid),
+    StreamOnline(StreamOnline),
+    Follow(Follow),
+    /// An event of a subscription type not defined in this enum.
+    Other {
+        subscription_type: String,
+        event: Value,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -45,11 +57,56 @@ pub struct StreamOnline {
     pub stream_type: String,
     pub started_at: String,
 }
+#[derive(Debug, Deserialize)]
+pub struct Follow {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub followed_at: String,
+}
 
-impl Event for ChannelPointRedeem {}
-impl Event for Subscription {}
-impl Event for Raid {}
-impl Event for StreamOnline {}
+impl Event for ChannelPointRedeem {
+    const SUBSCRIPTION_TYPE: &'static str = "channel.channel_points_custom_reward_redemption.add";
+}
+impl Event for Subscription {
+    const SUBSCRIPTION_TYPE: &'static str = "channel.subscription.message";
+}
+impl Event for Raid {
+    const SUBSCRIPTION_TYPE: &'static str = "channel.raid";
+}
+impl Event for StreamOnline {
+    const SUBSCRIPTION_TYPE: &'static str = "stream.online";
+}
+impl Event for Follow {
+    const SUBSCRIPTION_TYPE: &'static str = "channel.follow";
+}
+
+impl AnyEvent {
+    /// Deserializes `event` into the variant for `subscription_type`, or into
+    /// [`AnyEvent::Other`] if there is none.
+    ///
+    /// # Errors
+    /// Returns an error if `event` doesn't match its subscription type's event.
+    pub fn parse(subscription_type: &str, event: Value) -> Result<Self, serde_json::Error> {
+        Ok(match subscription_type {
+            ChannelPointRedeem::SUBSCRIPTION_TYPE => {
+                AnyEvent::ChannelPointRedeem(serde_json::from_value(event)?)
+            }
+            Subscription::SUBSCRIPTION_TYPE => {
+                AnyEvent::Subscription(serde_json::from_value(event)?)
+            }
+            Raid::SUBSCRIPTION_TYPE => AnyEvent::Raid(serde_json::from_value(event)?),
+            StreamOnline::SUBSCRIPTION_TYPE => {
+                AnyEvent::StreamOnline(serde_json::from_value(event)?)
+            }
+            Follow::SUBSCRIPTION_TYPE => AnyEvent::Follow(serde_json::from_value(event)?),
+            subscription_type => AnyEvent::Other {
+                subscription_type: String::from(subscription_type),
+                event,
+            },
+        })
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct Reward {
@@ -0,0 +1,287 @@
+//! Helix-backed management of existing EventSub subscriptions.
+//!
+//! Websocket subscriptions are only visible with a User Access Token, and
+//! webhook subscriptions are only visible with an App Access Token.
+use super::error::EventsubError;
+use super::subscription::Subscription;
+use crate::log::{self, Target};
+use reqwest::Client;
+use serde::Deserialize;
+
+/// A subscription that exists on Twitch's side.
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionInfo {
+    pub id: String,
+    pub status: String,
+    pub cost: u32,
+    pub created_at: String,
+    pub transport: TransportInfo,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransportInfo {
+    pub method: String,
+    pub session_id: Option<String>,
+    pub callback: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SubscriptionList {
+    pub subscriptions: Vec<SubscriptionInfo>,
+    pub cost: CostSummary,
+}
+
+/// What [`prune_subscriptions`] did.
+#[derive(Debug, Default)]
+pub struct PruneResult {
+    /// The subscriptions that were deleted.
+    pub pruned: Vec<SubscriptionInfo>,
+    /// The subscriptions that should have been deleted, but couldn't be.
+    pub failed: Vec<SubscriptionInfo>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CostSummary {
+    /// The number of subscriptions, including ones from other pages.
+    pub total: u32,
+    pub total_cost: u32,
+    pub max_total_cost: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListResponse {
+    data: Vec<SubscriptionInfo>,
+    total: u32,
+    total_cost: u32,
+    max_total_cost: u32,
+    pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+impl SubscriptionInfo {
+    /// Whether Twitch is still sending notifications for this subscription.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.status == "enabled"
+    }
+    /// Whether Twitch has stopped sending notifications for this subscription
+    /// for good, e.g. because it failed, was revoked, or its websocket was
+    /// disconnected. One that's still being verified isn't stale.
+    #[must_use]
+    pub fn is_stale(&self) -> bool {
+        const STALE_STATUSES: [&str; 6] = [
+            "notification_failures_exceeded",
+            "authorization_revoked",
+            "moderator_removed",
+            "user_removed",
+            "chat_user_banned",
+            "version_removed",
+        ];
+        self.status.ends_with("_failed")
+            || self.status.starts_with("websocket_")
+            || STALE_STATUSES.contains(&self.status.as_str())
+    }
+}
+
+/// Gets every subscription visible to `access_token` from the Helix
+/// `subscriptions_url`, following pagination.
+///
+/// # Errors
+/// Returns `Err(EventsubError...)`:
+/// * `::OnOutbound` if a response was not received from Twitch.
+/// * `::Twitch` if Twitch denied the request.
+/// * `::ManageInvalid` if a response from Twitch could not be parsed.
+pub async fn list_subscriptions(
+    subscriptions_url: &str,
+    client_id: &str,
+    access_token: &str,
+) -> Result<SubscriptionList, EventsubError> {
+    let client = Client::new();
+    let mut subscriptions = Vec::new();
+    let mut cursor = None;
+
+    loop {
+        let mut request = client
+            .get(subscriptions_url)
+            .header("Client-Id", client_id)
+            .header("Authorization", format!("Bearer {access_token}"));
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("after", cursor)]);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(EventsubError::OnOutbound)?
+            .text()
+            .await
+            .map_err(EventsubError::OnOutbound)?;
+        if let Ok(twitch_error) = serde_json::from_str::<crate::twitch::TwitchError>(&response) {
+            return Err(EventsubError::Twitch(twitch_error));
+        }
+        let response = serde_json::from_str::<ListResponse>(&response)
+            .map_err(EventsubError::ManageInvalid)?;
+
+        subscriptions.extend(response.data);
+        cursor = response.pagination.cursor;
+
+        if cursor.is_none() {
+            return Ok(SubscriptionList {
+                subscriptions,
+                cost: CostSummary {
+                    total: response.total,
+                    total_cost: response.total_cost,
+                    max_total_cost: response.max_total_cost,
+                },
+            });
+        }
+    }
+}
+
+/// # Errors
+/// Returns `Err(EventsubError...)`:
+/// * `::OnOutbound` if a response was not received from Twitch.
+/// * `::Twitch` if Twitch denied the request.
+pub async fn delete_subscription(
+    subscriptions_url: &str,
+    id: &str,
+    client_id: &str,
+    access_token: &str,
+) -> Result<(), EventsubError> {
+    let response = Client::new()
+        .delete(subscriptions_url)
+        .query(&[("id", id)])
+        .header("Client-Id", client_id)
+        .header("Authorization", format!("Bearer {access_token}"))
+        .send()
+        .await
+        .map_err(EventsubError::OnOutbound)?
+        .text()
+        .await
+        .map_err(EventsubError::OnOutbound)?;
+    if let Ok(twitch_error) = serde_json::from_str::<crate::twitch::TwitchError>(&response) {
+        return Err(EventsubError::Twitch(twitch_error));
+    }
+    Ok(())
+}
+
+/// Deletes every [stale](SubscriptionInfo::is_stale) subscription, e.g. ones
+/// from disconnected websocket sessions. A subscription that can't be deleted
+/// is logged and skipped.
+///
+/// # Errors
+/// Returns the error from [`list_subscriptions`].
+pub async fn prune_subscriptions(
+    subscriptions_url: &str,
+    client_id: &str,
+    access_token: &str,
+) -> Result<PruneResult, EventsubError> {
+    let mut result = PruneResult::default();
+    for subscription in list_subscriptions(subscriptions_url, client_id, access_token)
+        .await?
+        .subscriptions
+    {
+        if !subscription.is_stale() {
+            continue;
+        }
+        match delete_subscription(subscriptions_url, &subscription.id, client_id, access_token)
+            .await
+        {
+            Ok(()) => result.pruned.push(subscription),
+            Err(err) => {
+                log::warn(
+                    Target::Eventsub,
+                    format!("Could not delete subscription {subscription}: {err}"),
+                );
+                result.failed.push(subscription);
+            }
+        }
+    }
+    Ok(result)
+}
+
+impl std::fmt::Display for SubscriptionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} [{}] {}",
+            self.subscription, self.status, self.id
+        ))
+    }
+}
+
+impl std::fmt::Display for PruneResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Pruned {} subscription{}.",
+            self.pruned.len(),
+            if self.pruned.len() == 1 { "" } else { "s" }
+        ))?;
+        if !self.failed.is_empty() {
+            f.write_fmt(format_args!(" {} could not be deleted.", self.failed.len()))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for CostSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} subscription{}, cost {}/{}",
+            self.total,
+            if self.total == 1 { "" } else { "s" },
+            self.total_cost,
+            self.max_total_cost
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionInfo;
+    use serde_json::json;
+
+    fn with_status(status: &str) -> SubscriptionInfo {
+        serde_json::from_value(json!({
+            "id": "mock-subscription",
+            "status": status,
+            "type": "channel.raid",
+            "version": "1",
+            "condition": { "to_broadcaster_user_id": "1234" },
+            "created_at": "2023-01-01T00:00:00Z",
+            "cost": 0,
+            "transport": { "method": "webhook", "callback": "https://example.com" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_stale_subscriptions_are_pruned() {
+        for status in [
+            "webhook_callback_verification_failed",
+            "notification_failures_exceeded",
+            "authorization_revoked",
+            "moderator_removed",
+            "user_removed",
+            "chat_user_banned",
+            "version_removed",
+            "websocket_disconnected",
+            "websocket_failed_ping_pong",
+            "websocket_network_timeout",
+        ] {
+            assert!(with_status(status).is_stale(), "{status}");
+        }
+        for status in [
+            "enabled",
+            "webhook_callback_verification_pending",
+            "beta_maintenance",
+        ] {
+            assert!(!with_status(status).is_stale(), "{status}");
+        }
+    }
+}
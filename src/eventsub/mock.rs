@@ -0,0 +1,457 @@
+//! A local stand-in for Twitch's EventSub websocket server and Helix
+//! subscriptions endpoint, for testing event handlers without a live channel.
+//!
+//! Point an [`EventsubClient`](super::client::EventsubClient) at
+//! [`MockServer::urls`] and authenticate it with
+//! [`AccessTokenManager::new_static`](crate::auth::access::AccessTokenManager::new_static).
+use super::data::EventsubUrls;
+use super::subscription::Subscription;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Every message is sent with this timestamp.
+const MESSAGE_TIMESTAMP: &str = "2023-01-01T00:00:00.000000000Z";
+
+#[derive(Debug, Clone)]
+enum Outbound {
+    Notification(Value),
+    Keepalive,
+    Reconnect,
+    Close,
+}
+
+pub struct MockServer {
+    websocket_address: SocketAddr,
+    helix_address: SocketAddr,
+    helix: Arc<tiny_http::Server>,
+    outbound: broadcast::Sender<Outbound>,
+    session: watch::Receiver<Option<String>>,
+    subscriptions: watch::Receiver<Vec<Subscription>>,
+    accept_task: tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Binds both servers to random local ports.
+    pub async fn new() -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let websocket_address = listener.local_addr()?;
+
+        let helix =
+            Arc::new(tiny_http::Server::http("127.0.0.1:0").map_err(std::io::Error::other)?);
+        let helix_address = helix
+            .server_addr()
+            .to_ip()
+            .expect("Helix server is bound to an IP address");
+
+        let (outbound, _) = broadcast::channel(16);
+        let (session_sender, session) = watch::channel(None);
+        let (subscriptions_sender, subscriptions) = watch::channel(Vec::new());
+
+        tokio::task::spawn_blocking({
+            let helix = helix.clone();
+            move || handle_helix(&helix, &subscriptions_sender)
+        });
+        let accept_task = tokio::spawn(accept_connections(
+            listener,
+            outbound.clone(),
+            Arc::new(session_sender),
+        ));
+
+        Ok(Self {
+            websocket_address,
+            helix_address,
+            helix,
+            outbound,
+            session,
+            subscriptions,
+            accept_task,
+        })
+    }
+
+    #[must_use]
+    pub fn urls(&self) -> EventsubUrls {
+        EventsubUrls {
+            websocket: format!("ws://{}/ws", self.websocket_address),
+            subscriptions: format!("http://{}/eventsub/subscriptions", self.helix_address),
+        }
+    }
+
+    /// Sends a notification to every connected session. `payload` is in the
+    /// format that `twitch event trigger` forwards, i.e.
+    /// `{"subscription": {...}, "event": {...}}`.
+    pub fn trigger(&self, payload: Value) {
+        let _ = self.outbound.send(Outbound::Notification(payload));
+    }
+    /// Sends a notification for `subscription` to every connected session.
+    pub fn notify(&self, subscription: &Subscription, event: Value) {
+        self.trigger(json!({
+            "subscription": {
+                "id": "mock-subscription",
+                "status": "enabled",
+                "type": subscription.subscription_type,
+                "version": subscription.version,
+                "condition": subscription.condition,
+                "transport": { "method": "websocket" },
+                "created_at": MESSAGE_TIMESTAMP,
+                "cost": 0,
+            },
+            "event": event,
+        }));
+    }
+    pub fn keepalive(&self) {
+        let _ = self.outbound.send(Outbound::Keepalive);
+    }
+    /// Asks every connected session to reconnect. Sessions stop receiving
+    /// notifications once they are asked to reconnect.
+    pub fn reconnect(&self) {
+        let _ = self.outbound.send(Outbound::Reconnect);
+    }
+    /// Closes every connected session.
+    pub fn close(&self) {
+        let _ = self.outbound.send(Outbound::Close);
+    }
+
+    /// Waits for a session to be welcomed, returning its id.
+    pub async fn next_session(&mut self) -> String {
+        self.session
+            .changed()
+            .await
+            .expect("Mock server is still accepting connections");
+        self.session
+            .borrow_and_update()
+            .clone()
+            .expect("Sessions are only sent once they exist")
+    }
+    /// Waits until at least `count` subscriptions have been created.
+    pub async fn wait_for_subscriptions(&mut self, count: usize) -> Vec<Subscription> {
+        loop {
+            if self.subscriptions.borrow_and_update().len() >= count {
+                return self.subscriptions();
+            }
+            self.subscriptions
+                .changed()
+                .await
+                .expect("Helix server is still running");
+        }
+    }
+    /// Every subscription created so far.
+    #[must_use]
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.borrow().clone()
+    }
+}
+
+impl std::fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockServer")
+            .field("websocket_address", &self.websocket_address)
+            .field("helix_address", &self.helix_address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.helix.unblock();
+        self.accept_task.abort();
+    }
+}
+
+async fn accept_connections(
+    listener: tokio::net::TcpListener,
+    outbound: broadcast::Sender<Outbound>,
+    session: Arc<watch::Sender<Option<String>>>,
+) {
+    let address = listener
+        .local_addr()
+        .expect("Listener is bound to an address");
+    let mut session_count = 0;
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let Ok(socket) = tokio_tungstenite::accept_async(stream).await else {
+            continue;
+        };
+        session_count += 1;
+
+        tokio::spawn(handle_connection(
+            socket,
+            format!("mock-session-{session_count}"),
+            outbound.subscribe(),
+            session.clone(),
+            address,
+        ));
+    }
+}
+
+async fn handle_connection(
+    mut socket: tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
+    session_id: String,
+    mut outbound: broadcast::Receiver<Outbound>,
+    session: Arc<watch::Sender<Option<String>>>,
+    address: SocketAddr,
+) {
+    let mut message_count = 0;
+    let mut envelope = |message_type: &str, payload: Value| {
+        message_count += 1;
+        Message::Text(
+            json!({
+                "metadata": {
+                    "message_id": format!("{session_id}-{message_count}"),
+                    "message_type": message_type,
+                    "message_timestamp": MESSAGE_TIMESTAMP,
+                },
+                "payload": payload,
+            })
+            .to_string(),
+        )
+    };
+
+    let welcome = envelope(
+        "session_welcome",
+        json!({ "session": session_json(&session_id, "connected", None) }),
+    );
+    if socket.send(welcome).await.is_err() {
+        return;
+    }
+    let _ = session.send(Some(session_id.clone()));
+
+    let mut is_reconnecting = false;
+    loop {
+        tokio::select! {
+            message = outbound.recv() => {
+                let message = match message {
+                    Ok(Outbound::Close) => {
+                        let _ = socket.close(None).await;
+                        break;
+                    }
+                    Ok(_) if is_reconnecting => continue,
+                    Ok(Outbound::Notification(payload)) => envelope("notification", payload),
+                    Ok(Outbound::Keepalive) => envelope("session_keepalive", json!({})),
+                    Ok(Outbound::Reconnect) => {
+                        is_reconnecting = true;
+                        envelope(
+                            "session_reconnect",
+                            json!({
+                                "session": session_json(
+                                    &session_id,
+                                    "reconnecting",
+                                    Some(format!("ws://{address}/ws")),
+                                ),
+                            }),
+                        )
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if socket.send(message).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = socket.send(Message::Pong(data)).await;
+                }
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            }
+        }
+    }
+}
+
+fn session_json(id: &str, status: &str, reconnect_url: Option<String>) -> Value {
+    json!({
+        "id": id,
+        "status": status,
+        "connected_at": MESSAGE_TIMESTAMP,
+        "keepalive_timeout_seconds": reconnect_url.is_none().then_some(10),
+        "reconnect_url": reconnect_url,
+    })
+}
+
+fn handle_helix(server: &tiny_http::Server, subscriptions: &watch::Sender<Vec<Subscription>>) {
+    while let Ok(mut request) = server.recv() {
+        let mut body = String::new();
+        let response = if *request.method() != tiny_http::Method::Post {
+            respond_json(405, &twitch_error(405, "Method Not Allowed"))
+        } else if request.as_reader().read_to_string(&mut body).is_err() {
+            respond_json(400, &twitch_error(400, "Invalid body"))
+        } else {
+            match serde_json::from_str::<Subscription>(&body) {
+                Ok(subscription) => {
+                    let response = json!({
+                        "data": [{
+                            "id": "mock-subscription",
+                            "status": "enabled",
+                            "type": subscription.subscription_type,
+                            "version": subscription.version,
+                            "condition": subscription.condition,
+                            "created_at": MESSAGE_TIMESTAMP,
+                            "cost": 0,
+                        }],
+                        "total": subscriptions.borrow().len() + 1,
+                        "total_cost": 0,
+                        "max_total_cost": 10,
+                    });
+                    subscriptions.send_modify(|subscriptions| subscriptions.push(subscription));
+                    respond_json(202, &response)
+                }
+                Err(err) => respond_json(400, &twitch_error(400, &err.to_string())),
+            }
+        };
+
+        let _ = request.respond(response);
+    }
+}
+
+fn twitch_error(status: u16, message: &str) -> Value {
+    json!({
+        "error": "Mock Error",
+        "status": status,
+        "message": message,
+    })
+}
+
+fn respond_json(code: u16, json: &Value) -> tiny_http::Response<Cursor<Vec<u8>>> {
+    tiny_http::Response::from_string(json.to_string())
+        .with_status_code(code)
+        .with_header(tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockServer;
+    use crate::auth::access::AccessTokenManager;
+    use crate::eventsub::client::EventsubClient;
+    use crate::eventsub::data::{EventsubClientData, Transport};
+    use crate::eventsub::event;
+    use crate::eventsub::subscription::Subscription;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    async fn within<T>(fut: impl std::future::Future<Output = T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), fut)
+            .await
+            .expect("Mock interaction timed out")
+    }
+
+    async fn client(server: &MockServer, subscriptions: Vec<Subscription>) -> EventsubClient {
+        let options = crate::options::Options::default();
+        EventsubClient::new(
+            EventsubClientData {
+                client_id: String::from("mock"),
+                access: AccessTokenManager::new_static(String::from("mock"), String::from("mock")),
+                subscriptions,
+                transport: Transport::Websocket,
+                urls: server.urls(),
+            },
+            options,
+        )
+        .await
+        .expect("Client connects to the mock server")
+    }
+
+    fn raid_event(viewers: u32) -> serde_json::Value {
+        json!({
+            "from_broadcaster_user_id": "5678",
+            "from_broadcaster_user_login": "raider",
+            "from_broadcaster_user_name": "Raider",
+            "to_broadcaster_user_id": "1234",
+            "to_broadcaster_user_login": "streamer",
+            "to_broadcaster_user_name": "Streamer",
+            "viewers": viewers,
+        })
+    }
+
+    fn raid_receiver(client: &EventsubClient) -> mpsc::UnboundedReceiver<event::Raid> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(client.on_event::<event::Raid, _>(move |notification| {
+            let sender = sender.clone();
+            async move {
+                let _ = sender.send(notification.payload.event);
+            }
+        }));
+        receiver
+    }
+
+    #[tokio::test]
+    async fn client_subscribes_and_receives_notifications() {
+        let mut server = MockServer::new().await.unwrap();
+        let raid = Subscription::raid_to(String::from("1234"));
+        let client = client(&server, vec![raid.clone()]).await;
+        let mut raids = raid_receiver(&client);
+        tokio::spawn(client.run());
+
+        within(server.next_session()).await;
+        assert_eq!(
+            within(server.wait_for_subscriptions(1)).await,
+            vec![raid.clone()]
+        );
+
+        server.keepalive();
+        server.notify(&raid, raid_event(10));
+
+        let received = within(raids.recv()).await.unwrap();
+        assert_eq!(received.from_broadcaster_user_name, "Raider");
+        assert_eq!(received.viewers, 10);
+    }
+
+    #[tokio::test]
+    async fn client_routes_by_subscription_type() {
+        let mut server = MockServer::new().await.unwrap();
+        let client = client(&server, Vec::new()).await;
+        let mut raids = raid_receiver(&client);
+        tokio::spawn(client.run());
+
+        within(server.next_session()).await;
+
+        // a raid event sent under another subscription type isn't a raid
+        server.notify(
+            &Subscription::stream_online(String::from("1234")),
+            raid_event(1),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(raids.try_recv().is_err());
+
+        // events in the format of `twitch event trigger`
+        server.trigger(json!({
+            "subscription": {
+                "id": "f1c2a387-161a-49f9-a165-0f21d7a4e1c4",
+                "status": "enabled",
+                "type": "channel.raid",
+                "version": "1",
+                "condition": { "to_broadcaster_user_id": "1234" },
+                "transport": { "method": "websocket" },
+                "created_at": "2023-01-01T00:00:00Z",
+                "cost": 0,
+            },
+            "event": raid_event(2),
+        }));
+        assert_eq!(within(raids.recv()).await.unwrap().viewers, 2);
+    }
+
+    #[tokio::test]
+    async fn client_keeps_subscriptions_on_reconnect() {
+        let mut server = MockServer::new().await.unwrap();
+        let raid = Subscription::raid_to(String::from("1234"));
+        let client = client(&server, vec![raid.clone()]).await;
+        let mut raids = raid_receiver(&client);
+        tokio::spawn(client.run());
+
+        let first_session = within(server.next_session()).await;
+        within(server.wait_for_subscriptions(1)).await;
+
+        server.reconnect();
+        let second_session = within(server.next_session()).await;
+        assert_ne!(first_session, second_session);
+
+        server.notify(&raid, raid_event(3));
+        assert_eq!(within(raids.recv()).await.unwrap().viewers, 3);
+        assert_eq!(server.subscriptions().len(), 1);
+    }
+}
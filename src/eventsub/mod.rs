@@ -2,6 +2,9 @@ pub mod client;
 pub mod data;
 pub mod error;
 pub mod event;
+pub mod manage;
+pub mod mock;
 mod outbound;
 pub mod subscription;
 mod tls;
+mod webhook;
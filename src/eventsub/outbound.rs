@@ -1,63 +1,61 @@
 use super::error::EventsubError;
 use super::subscription::Subscription;
-use crate::twitch::HelixAuth;
 use reqwest::Client;
 use serde_json::Value;
 
-// FIXME: this only works when subscribing to notifications on the same channel
-// as the auth
+/// Sends every subscription request using `transport`, returning the ones that
+/// failed along with their errors.
 pub async fn send_subscriptions(
+    url: &str,
     subscriptions: &[Subscription],
-    session_id: &str,
-    auth: &HelixAuth,
-) -> Result<(), EventsubError> {
-    let access_token = auth
-        .access
-        .get_credentials()
-        .await
-        .map_err(EventsubError::Access)?
-        .access_token;
+    transport: Value,
+    client_id: &str,
+    access_token: &str,
+) -> Vec<(Subscription, EventsubError)> {
+    let client = Client::new();
+    let mut failures = Vec::new();
     for subscription in subscriptions {
-        let mut outbound = serde_json::Map::from_iter(
-            [
-                ("version", Value::String(String::from("1"))),
-                (
-                    "transport",
-                    serde_json::json!({
-                        "method": "websocket",
-                        "session_id": session_id,
-                    }),
-                ),
-            ]
-            .map(|(k, v)| (String::from(k), v)),
-        );
-        for (k, v) in serde_json::to_value(subscription.clone())
-            .expect("Subscription is always serializable")
-            .as_object()
-            .expect("Subscription is always an object")
-        {
-            outbound.insert(k.clone(), v.clone());
+        let request =
+            send_subscription(&client, url, subscription, &transport, client_id, access_token);
+        if let Err(err) = crate::metrics::METRICS.time_helix(request).await {
+            failures.push((subscription.clone(), err));
         }
+    }
+    failures
+}
 
-        let client = Client::new();
-        let response = client
-            .post("https://api.twitch.tv/helix/eventsub/subscriptions")
-            .header("Content-Type", "application/json")
-            .header("Client-Id", &auth.client_id)
-            .header("Authorization", format!("Bearer {access_token}"))
-            .body(
-                serde_json::to_string(&Value::Object(outbound))
-                    .expect("Value::Object always succeeds in serde_json::to_string"),
-            )
-            .send()
-            .await
-            .map_err(EventsubError::OnOutbound)?
-            .text()
-            .await
-            .map_err(EventsubError::OnOutbound)?;
-        if let Ok(twitch_error) = serde_json::from_str::<crate::twitch::TwitchError>(&response) {
-            return Err(EventsubError::Twitch(twitch_error));
-        }
+async fn send_subscription(
+    client: &Client,
+    url: &str,
+    subscription: &Subscription,
+    transport: &Value,
+    client_id: &str,
+    access_token: &str,
+) -> Result<(), EventsubError> {
+    let outbound = serde_json::json!({
+        "type": subscription.subscription_type,
+        "version": subscription.version,
+        "condition": subscription.condition,
+        "transport": transport,
+    });
+
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("Client-Id", client_id)
+        .header("Authorization", format!("Bearer {access_token}"))
+        .body(
+            serde_json::to_string(&outbound)
+                .expect("Value::Object always succeeds in serde_json::to_string"),
+        )
+        .send()
+        .await
+        .map_err(EventsubError::OnOutbound)?
+        .text()
+        .await
+        .map_err(EventsubError::OnOutbound)?;
+    if let Ok(twitch_error) = serde_json::from_str::<crate::twitch::TwitchError>(&response) {
+        return Err(EventsubError::Twitch(twitch_error));
     }
     Ok(())
 }
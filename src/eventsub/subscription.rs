@@ -1,25 +1,81 @@
+use super::event::{self, Event};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(tag = "type", content = "condition")]
-pub enum Subscription {
-    #[serde(rename = "channel.channel_points_custom_reward_redemption.add")]
-    ChannelPointRedeem {
-        broadcaster_user_id: String,
-        reward_id: Option<String>,
-    },
-    #[serde(rename = "channel.subscription.message")]
-    Subscription { broadcaster_user_id: String },
-    #[serde(rename = "channel.raid")]
-    RaidTo {
-        #[serde(rename = "to_broadcaster_user_id")]
-        broadcaster_user_id: String,
-    },
-    #[serde(rename = "channel.raid")]
-    RaidFrom {
-        #[serde(rename = "from_broadcaster_user_id")]
-        broadcaster_user_id: String,
-    },
-    #[serde(rename = "stream.online")]
-    StreamOnline { broadcaster_user_id: String },
+/// An EventSub subscription request. See
+/// <https://dev.twitch.tv/docs/eventsub/eventsub-subscription-types/> for the
+/// available types, their versions, and their conditions.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Subscription {
+    #[serde(rename = "type")]
+    pub subscription_type: String,
+    pub version: String,
+    pub condition: Map<String, Value>,
+}
+
+impl Subscription {
+    /// Creates a subscription with no conditions. Use
+    /// [`with_condition`](Subscription::with_condition) to add them.
+    #[must_use]
+    pub fn new<T: Into<String>, V: Into<String>>(subscription_type: T, version: V) -> Self {
+        Self {
+            subscription_type: subscription_type.into(),
+            version: version.into(),
+            condition: Map::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_condition<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.condition
+            .insert(key.into(), Value::String(value.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn channel_point_redeem(broadcaster_user_id: String, reward_id: Option<String>) -> Self {
+        let subscription = Self::new(event::ChannelPointRedeem::SUBSCRIPTION_TYPE, "1")
+            .with_condition("broadcaster_user_id", broadcaster_user_id);
+        match reward_id {
+            Some(reward_id) => subscription.with_condition("reward_id", reward_id),
+            None => subscription,
+        }
+    }
+    #[must_use]
+    pub fn channel_subscription(broadcaster_user_id: String) -> Self {
+        Self::new(event::Subscription::SUBSCRIPTION_TYPE, "1")
+            .with_condition("broadcaster_user_id", broadcaster_user_id)
+    }
+    #[must_use]
+    pub fn raid_to(broadcaster_user_id: String) -> Self {
+        Self::new(event::Raid::SUBSCRIPTION_TYPE, "1")
+            .with_condition("to_broadcaster_user_id", broadcaster_user_id)
+    }
+    #[must_use]
+    pub fn raid_from(broadcaster_user_id: String) -> Self {
+        Self::new(event::Raid::SUBSCRIPTION_TYPE, "1")
+            .with_condition("from_broadcaster_user_id", broadcaster_user_id)
+    }
+    #[must_use]
+    pub fn stream_online(broadcaster_user_id: String) -> Self {
+        Self::new(event::StreamOnline::SUBSCRIPTION_TYPE, "1")
+            .with_condition("broadcaster_user_id", broadcaster_user_id)
+    }
+    /// `moderator_user_id` must be the user the access token belongs to, and
+    /// must be a moderator (or the broadcaster) of `broadcaster_user_id`'s channel.
+    #[must_use]
+    pub fn follow(broadcaster_user_id: String, moderator_user_id: String) -> Self {
+        Self::new(event::Follow::SUBSCRIPTION_TYPE, "2")
+            .with_condition("broadcaster_user_id", broadcaster_user_id)
+            .with_condition("moderator_user_id", moderator_user_id)
+    }
+}
+
+impl std::fmt::Display for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} (v{})",
+            self.subscription_type, self.version
+        ))
+    }
 }
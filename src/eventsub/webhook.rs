@@ -0,0 +1,307 @@
+use super::data::WebhookTransport;
+use super::error::EventsubError;
+use crate::log::{self, Target};
+use ring::hmac;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{Cursor, Read};
+use std::time::{Duration, SystemTime};
+use tiny_http::{Request, Response};
+use tokio::sync::watch;
+
+/// How many notification ids to remember. Twitch resends notifications that it
+/// doesn't think were received, so duplicates are dropped.
+const MESSAGE_HISTORY_LENGTH: usize = 64;
+/// Requests sent longer ago than this are rejected, so that a captured request
+/// can't be replayed once it has dropped out of the message history.
+const MAX_MESSAGE_AGE: Duration = Duration::from_secs(10 * 60);
+/// Bodies longer than this are rejected before their signature is checked.
+/// Twitch's requests are far smaller.
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+pub(super) struct WebhookListener {
+    server: tiny_http::Server,
+    key: hmac::Key,
+    host_address: String,
+}
+
+impl WebhookListener {
+    pub fn new(
+        transport: &WebhookTransport,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self {
+            server: tiny_http::Server::http(&transport.host_address)?,
+            key: hmac::Key::new(hmac::HMAC_SHA256, transport.secret.as_bytes()),
+            host_address: transport.host_address.clone(),
+        })
+    }
+
+    pub async fn run(
+        self,
+        interface: std::sync::Arc<watch::Sender<Value>>,
+    ) -> Result<(), EventsubError> {
+        log::debug(
+            Target::Eventsub,
+            format!("Listening for webhook requests at {}", self.host_address),
+        );
+
+        tokio::task::spawn_blocking(move || self.handle_requests(&interface))
+            .await
+            .expect("Webhook listener panicked")
+            .map_err(EventsubError::OnWebhookReceive)
+    }
+
+    fn handle_requests(self, interface: &watch::Sender<Value>) -> std::io::Result<()> {
+        let mut history = VecDeque::with_capacity(MESSAGE_HISTORY_LENGTH);
+
+        loop {
+            let mut request = self.server.recv()?;
+            let response = self.handle_request(&mut request, &mut history, interface);
+
+            // a failed response is Twitch's problem, it will retry notifications
+            let _ = request.respond(response);
+        }
+    }
+
+    fn handle_request(
+        &self,
+        request: &mut Request,
+        history: &mut VecDeque<String>,
+        interface: &watch::Sender<Value>,
+    ) -> Response<Cursor<Vec<u8>>> {
+        if *request.method() != tiny_http::Method::Post {
+            return respond_code(405, "Method not allowed.");
+        }
+
+        let (Some(message_id), Some(timestamp), Some(signature), Some(message_type)) = (
+            header(request, "Twitch-Eventsub-Message-Id"),
+            header(request, "Twitch-Eventsub-Message-Timestamp"),
+            header(request, "Twitch-Eventsub-Message-Signature"),
+            header(request, "Twitch-Eventsub-Message-Type"),
+        ) else {
+            return respond_code(400, "Missing EventSub headers.");
+        };
+
+        if request
+            .body_length()
+            .is_some_and(|length| length > MAX_BODY_LENGTH)
+        {
+            return respond_code(413, "Body too long.");
+        }
+        // the length header can be missing, or wrong
+        let mut body = String::new();
+        let limit = u64::try_from(MAX_BODY_LENGTH + 1).unwrap_or(u64::MAX);
+        match request.as_reader().take(limit).read_to_string(&mut body) {
+            Ok(length) if length > MAX_BODY_LENGTH => {
+                return respond_code(413, "Body too long.");
+            }
+            Ok(_) => (),
+            Err(_) => return respond_code(400, "Invalid body."),
+        }
+
+        if !self.verify(&message_id, &timestamp, &body, &signature) {
+            log::debug(
+                Target::Eventsub,
+                "Received a webhook request with an invalid signature",
+            );
+            return respond_code(403, "Invalid signature.");
+        }
+        if !is_recent(&timestamp, SystemTime::now()) {
+            log::debug(
+                Target::Eventsub,
+                format!("Received a webhook request with an old timestamp {timestamp:?}"),
+            );
+            return respond_code(403, "Message too old.");
+        }
+
+        let Ok(json) = serde_json::from_str::<Value>(&body) else {
+            return respond_code(400, "Invalid body.");
+        };
+
+        match message_type.as_str() {
+            "webhook_callback_verification" => {
+                let Some(challenge) = json.get("challenge").and_then(Value::as_str) else {
+                    return respond_code(400, "Missing challenge.");
+                };
+                log::debug(Target::Eventsub, "Verified webhook callback");
+
+                respond_code(200, challenge)
+            }
+            "notification" => {
+                if history.contains(&message_id) {
+                    return respond_code(204, "");
+                }
+                if history.len() >= MESSAGE_HISTORY_LENGTH {
+                    history.pop_front();
+                }
+                history.push_back(message_id.clone());
+
+                // wrapped to match the shape of websocket notifications
+                let notification = serde_json::json!({
+                    "metadata": {
+                        "message_id": message_id,
+                        "message_type": message_type,
+                        "message_timestamp": timestamp,
+                    },
+                    "payload": json,
+                });
+                crate::metrics::METRICS.eventsub_message(&notification);
+                let _ = interface.send(notification);
+
+                respond_code(204, "")
+            }
+            "revocation" => {
+                let subscription = &json["subscription"];
+                log::warn(
+                    Target::Eventsub,
+                    format!(
+                        "Twitch revoked the subscription to {} ({})",
+                        subscription["type"].as_str().unwrap_or("unknown"),
+                        subscription["status"].as_str().unwrap_or("unknown")
+                    ),
+                );
+
+                respond_code(204, "")
+            }
+            _ => respond_code(400, "Unknown message type."),
+        }
+    }
+
+    fn verify(&self, message_id: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+        let Some(signature) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+            return false;
+        };
+        let message = [message_id.as_bytes(), timestamp.as_bytes(), body.as_bytes()].concat();
+
+        hmac::verify(&self.key, &message, &signature).is_ok()
+    }
+}
+
+impl std::fmt::Debug for WebhookListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookListener")
+            .field("host_address", &self.host_address)
+            .finish_non_exhaustive()
+    }
+}
+
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| String::from(header.value.as_str()))
+}
+
+/// Whether `timestamp` is at most [`MAX_MESSAGE_AGE`] before `now`.
+/// Timestamps after `now` are allowed, in case the clocks disagree.
+fn is_recent(timestamp: &str, now: SystemTime) -> bool {
+    crate::twitch::parse_timestamp(timestamp).is_some_and(|time| {
+        now.duration_since(time)
+            .map_or(true, |age| age <= MAX_MESSAGE_AGE)
+    })
+}
+
+fn respond_code(code: u16, description: &str) -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(description)
+        .with_status_code(code)
+        .with_header(tiny_http::Header::from_bytes("Content-Type", "text/plain").unwrap())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    // `u8::from_str_radix` would take a sign, e.g. "+1"
+    let digit = |byte: u8| char::from(byte).to_digit(16);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::try_from(digit(pair[0])? * 16 + digit(pair[1])?).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE_ID: &str = "e76c6bd4-55c9-4987-8304-da1588d8988b";
+    const TIMESTAMP: &str = "2023-01-01T00:00:00.000000000Z";
+    const BODY: &str = r#"{"subscription":{}}"#;
+    /// The HMAC-SHA256 of `MESSAGE_ID`, `TIMESTAMP` and `BODY`.
+    const SIGNATURE: &str =
+        "sha256=6e7bf59d1f07d2e6a190739a2aabc7ec84be9986e11370accbc97a467118dfcc";
+
+    fn listener() -> WebhookListener {
+        WebhookListener::new(&WebhookTransport {
+            callback_url: String::from("https://example.com/eventsub"),
+            secret: String::from("this is a test secret"),
+            host_address: String::from("127.0.0.1:0"),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let listener = listener();
+        assert!(listener.verify(MESSAGE_ID, TIMESTAMP, BODY, SIGNATURE));
+        assert!(listener.verify(
+            MESSAGE_ID,
+            TIMESTAMP,
+            BODY,
+            &SIGNATURE.to_ascii_uppercase().replace("SHA256=", "sha256=")
+        ));
+
+        // tampered with
+        assert!(!listener.verify(MESSAGE_ID, TIMESTAMP, r#"{"subscription":[]}"#, SIGNATURE));
+        assert!(!listener.verify(
+            "e76c6bd4-55c9-4987-8304-da1588d8988c",
+            TIMESTAMP,
+            BODY,
+            SIGNATURE
+        ));
+        assert!(!listener.verify(
+            MESSAGE_ID,
+            "2023-01-01T00:00:01.000000000Z",
+            BODY,
+            SIGNATURE
+        ));
+
+        // malformed
+        let hex = SIGNATURE.trim_start_matches("sha256=");
+        assert!(!listener.verify(MESSAGE_ID, TIMESTAMP, BODY, hex));
+        assert!(!listener.verify(MESSAGE_ID, TIMESTAMP, BODY, &format!("sha1={hex}")));
+        assert!(!listener.verify(
+            MESSAGE_ID,
+            TIMESTAMP,
+            BODY,
+            &SIGNATURE[..SIGNATURE.len() - 1]
+        ));
+        assert!(!listener.verify(MESSAGE_ID, TIMESTAMP, BODY, "sha256="));
+        assert!(!listener.verify(
+            MESSAGE_ID,
+            TIMESTAMP,
+            BODY,
+            &SIGNATURE.replace("6e7b", "6g7b")
+        ));
+    }
+
+    #[test]
+    fn hex_is_decoded() {
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("00ff7F"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("é1"), None);
+    }
+
+    #[test]
+    fn old_timestamps_are_rejected() {
+        let now = crate::twitch::parse_timestamp("2023-01-01T00:10:00Z").unwrap();
+        assert!(is_recent("2023-01-01T00:10:00.123456789Z", now));
+        assert!(is_recent("2023-01-01T00:00:00Z", now));
+        assert!(is_recent("2023-01-01T00:11:00Z", now));
+        assert!(!is_recent("2022-12-31T23:59:59Z", now));
+        assert!(!is_recent("yesterday", now));
+    }
+}
@@ -1,452 +1,124 @@
 use super::comet;
 use super::command::CommandRules;
+use super::cooldown;
+use super::history;
 use super::io;
 use super::listener;
+use super::permission::Permission;
+use super::router::{self, Arg, Command, Invocation};
+use super::timer;
+use crate::bot::handler::HandlerOptions;
+use crate::chat::data::ChatMessage;
+use crate::eventsub;
+use crate::log::{self, Target};
 use regex::Regex;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How many chatters `!counter:top` shows.
+const COUNTER_TOP_LENGTH: usize = 5;
+
+/// How many commands `!cmd:stats` lists without a command name.
+const STATS_LIST_LENGTH: usize = 10;
+
+/// What the bot posts when the channel is raided. `%0` is the raider's name,
+/// and `%1` and `%2` are what they were playing and for how many viewers, if
+/// their stream can be found.
+const RAID_THANKS: &str = "%wait=2s Thank you so much @%0 for the raid!!! <3 %wait=1s \
+    %if(1){%0 was last playing \"%1\" with %2! :D}";
 
 pub fn register_base_commands(
     store: &super::Store,
     bot: &crate::bot::Bot,
 ) -> impl std::future::Future<Output = ()> + 'static {
-    let data_mod = store.0.clone();
     let data_cmd = store.0.clone();
-    let data_cus = store.0.clone();
-    let data_cnt = store.0.clone();
-    let data_cmn = store.0.clone();
-    let data_lis = store.0.clone();
     let data_lse = store.0.clone();
-    let data_als = store.0.clone();
-
-    let commands: [std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>; 8] = [
-        // Mod-only commands
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let _data = data_mod.clone();
-            async move {
-                if !msg.user_is_super() {
-                    return;
-                }
-
-                if msg.text.starts_with("!shutdown") {
-                    bot.shutdown().await;
-                } else if msg.text.starts_with("!ping") {
-                    bot.reply(&msg, "Pong!").await;
-                }
-            }
-        })),
-        // Custom command commands
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_cmd.clone();
-            async move {
-                if !data.read().await.options.features.custom_commands {
-                    return;
-                }
-                if !msg.user_is_super() {
-                    return;
-                }
+    let data_lns = store.0.clone();
 
-                if let Some(command) = msg.text.strip_prefix("!cmd:set") {
-                    if let Some((command_name, command_body)) = command.trim().split_once(' ') {
-                        if let Some(existing_command) =
-                            data.clone().read().await.commands.get(command_name)
-                        {
-                            if existing_command.is_builtin() {
-                                bot.reply(
-                                    &msg,
-                                    format!("Cannot set a builtin cmd {command_name:?}."),
-                                )
-                                .await;
-                                return;
-                            }
-                        }
-                        match CommandRules::parse(command_body) {
-                            Ok(body) => {
-                                data.write()
-                                    .await
-                                    .commands
-                                    .insert(String::from(command_name), Arc::new(body));
-                                io::spawn_io(data.clone(), io::refresh(data.clone()));
-                            }
-                            Err(err) => {
-                                bot.reply(&msg, format!("Could not create command: {err}"))
-                                    .await
-                            }
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            String::from("Command \"cmd:set\" expects at least 2 arguments."),
-                        )
-                        .await
-                    }
-                } else if let Some(command_name) = msg.text.strip_prefix("!cmd:info") {
-                    let command_name = command_name.trim();
-                    if let Some(body) = data.read().await.commands.get(command_name) {
-                        bot.reply(
-                            &msg,
-                            if body.is_builtin() {
-                                format!("!{command_name} is a builtin command")
-                            } else {
-                                format!("!{command_name}: {}", body.as_words_string())
-                            },
-                        )
-                        .await;
-                    } else {
-                        bot.reply(&msg, format!("Unknown command {command_name:?}."))
-                            .await;
+    let commands: [std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>; 4] = [
+        // Builtin and custom command executor
+        Box::pin(bot.on_chat_message_concurrent(
+            HandlerOptions::new("commands"),
+            move |msg, bot| {
+                let data = data_cmd.clone();
+                async move {
+                    let msg = resolve_alias(msg, &data).await;
+                    if router::route(msg.clone(), bot.clone(), data.clone()).await {
+                        return;
                     }
-                } else if let Some(command_name) = msg.text.strip_prefix("!cmd:remove") {
-                    let command_name = command_name.trim();
-                    let mut data_write = data.write().await;
-                    if let Some((command_name, to_remove)) =
-                        data_write.commands.remove_entry(command_name)
-                    {
-                        if to_remove.is_builtin() {
-                            bot.reply(
-                                &msg,
-                                format!("Cannot remove a builtin cmd {command_name:?}."),
-                            )
-                            .await;
-                            data_write.commands.insert(command_name, to_remove);
-                            return;
-                        }
-
-                        drop(data_write);
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(&msg, format!("Unknown command {command_name:?}."))
-                            .await;
+                    if !data.read().await.options.features.custom_commands {
+                        return;
                     }
-                }
-            }
-        })),
-        // Counter commands
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_cnt.clone();
-            async move {
-                if !data.read().await.options.features.counters {
-                    return;
-                }
-                if !msg.user_is_super() {
-                    return;
-                }
 
-                if let Some(args) = msg.text.strip_prefix("!counter:set") {
-                    let args = args.trim();
-                    if let Some((counter_name, counter_value)) = args.split_once(' ') {
-                        if let Ok(value) = counter_value.parse() {
-                            data.write()
-                                .await
-                                .counters
-                                .insert(String::from(counter_name), value);
-                            io::spawn_io(data.clone(), io::refresh(data.clone()));
-                        } else {
-                            bot.reply(&msg, format!("{counter_value:?} is not an integer."))
-                                .await
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            String::from("Command \"counter:set\" expects at least 2 arguments."),
-                        )
-                        .await
-                    }
-                } else if let Some(counter_name) = msg.text.strip_prefix("!counter:remove") {
-                    let counter_name = counter_name.trim();
-                    if data.write().await.counters.remove(counter_name).is_some() {
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(&msg, format!("Unknown counter {counter_name:?}."))
-                            .await;
-                    }
-                } else if let Some(counter_name) = msg.text.strip_prefix("!counter:get") {
-                    let counter_name = counter_name.trim();
-                    if let Some(value) = data.read().await.counters.get(counter_name) {
-                        bot.reply(&msg, format!("Counter {counter_name:?}: {value}"))
-                            .await;
-                    } else {
-                        bot.reply(&msg, format!("Unknown counter {counter_name:?}."))
-                            .await
-                    }
-                } else if msg.text.starts_with("!counter:list") {
-                    let keys = data
-                        .read()
-                        .await
-                        .counters
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<_>>();
-
-                    bot.reply(
-                        &msg,
-                        if keys.is_empty() {
-                            String::from("No counters.")
-                        } else {
-                            format!("Counters: {}", keys.join(", "))
-                        },
-                    )
-                    .await;
-                }
-            }
-        })),
-        // Listener commands
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_lis.clone();
-            async move {
-                if !data.read().await.options.features.listeners {
-                    return;
-                }
-                if !msg.user_is_super() {
-                    return;
-                }
+                    if let Some(command) = msg.text.strip_prefix('!') {
+                        let words = command.trim().split(' ').collect::<Vec<_>>();
+                        let [cmd, args @ ..] = words.as_slice() else {
+                            return;
+                        };
 
-                if let Some(args) = msg.text.strip_prefix("!listen:exact") {
-                    if let Some((name, pattern, command)) = listener::Listener::parts(args.trim()) {
-                        match CommandRules::parse(&command) {
-                            Ok(body) => {
-                                data.write().await.listeners.insert(
-                                    name,
-                                    listener::Listener {
-                                        predicate: listener::Predicate::Exactly(pattern),
-                                        body,
-                                    },
-                                );
-                                io::spawn_io(data.clone(), io::refresh(data.clone()));
-                            }
-                            Err(err) => {
-                                bot.reply(&msg, format!("Could not create listener: {err}."))
-                                    .await;
-                            }
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            "Usage: !listen:exact listenname listenpatern/listencommand",
-                        )
-                        .await;
-                    }
-                } else if let Some(args) = msg.text.strip_prefix("!listen:has") {
-                    if let Some((name, pattern, command)) = listener::Listener::parts(args.trim()) {
-                        match CommandRules::parse(&command) {
-                            Ok(body) => {
-                                data.write().await.listeners.insert(
-                                    name,
-                                    listener::Listener {
-                                        predicate: listener::Predicate::Contains(pattern),
-                                        body,
-                                    },
-                                );
-                                io::spawn_io(data.clone(), io::refresh(data.clone()));
-                            }
-                            Err(err) => {
-                                bot.reply(&msg, format!("Could not create listener: {err}."))
-                                    .await;
-                            }
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            "Usage: !listen:has listenname listenpatern/listencommand",
-                        )
-                        .await;
-                    }
-                } else if let Some(args) = msg.text.strip_prefix("!listen:regex") {
-                    if let Some((name, pattern, command)) = listener::Listener::parts(args.trim()) {
-                        let regex = match Regex::new(&pattern) {
-                            Ok(it) => it,
-                            Err(_err) => {
-                                // FIXME: Report regex errors
-                                bot.reply(&msg, "Regex error.").await;
+                        let command = data.read().await.commands.get(*cmd).cloned();
+                        if let Some(command) = command {
+                            if !command.can_run(&msg, &bot).await {
                                 return;
                             }
-                        };
-                        match CommandRules::parse(&command) {
-                            Ok(body) => {
-                                data.write().await.listeners.insert(
-                                    name,
-                                    listener::Listener {
-                                        predicate: listener::Predicate::Regex(regex),
-                                        body,
-                                    },
-                                );
-                                io::spawn_io(data.clone(), io::refresh(data.clone()));
-                            }
-                            Err(err) => {
-                                bot.reply(&msg, format!("Could not create listener: {err}."))
-                                    .await;
-                            }
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            "Usage: !listen:regex listenname listenpatern/listencommand",
-                        )
-                        .await;
-                    }
-                } else if let Some(name) = msg.text.strip_prefix("!listen:info") {
-                    let name = name.trim();
-                    if let Some(listener) = data.read().await.listeners.get(name) {
-                        bot.reply(
-                            &msg,
-                            format!(
-                                "Listener {name} {}/{}",
-                                match &listener.predicate {
-                                    listener::Predicate::Exactly(pat) => format!("(exact): {pat}"),
-                                    listener::Predicate::Contains(pat) =>
-                                        format!("(contains): {pat}"),
-                                    listener::Predicate::Regex(pat) => format!("(regex): {pat}"),
-                                },
-                                listener.body.as_words_string(),
-                            ),
-                        )
-                        .await;
-                    } else {
-                        bot.reply(&msg, format!("Unknown listener {name}.")).await;
-                    }
-                } else if let Some(name) = msg.text.strip_prefix("!listen:remove") {
-                    let name = name.trim();
-                    if data.write().await.listeners.remove(name).is_some() {
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(&msg, format!("Unknown listener {name}.")).await;
-                    }
-                } else if msg.text.starts_with("!listen:list") {
-                    let keys = data
-                        .read()
-                        .await
-                        .listeners
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<_>>();
-
-                    bot.reply(
-                        &msg,
-                        if keys.is_empty() {
-                            String::from("No listeners.")
-                        } else {
-                            format!("Listeners: {}", keys.join(", "))
-                        },
-                    )
-                    .await;
-                }
-            }
-        })),
-        // Custom command executor
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_cus.clone();
-            async move {
-                if !data.read().await.options.features.custom_commands {
-                    return;
-                }
 
-                if let Some(command) = msg.text.strip_prefix('!') {
-                    let words = command.trim().split(' ').collect::<Vec<_>>();
-                    let [cmd, args @ ..] = words.as_slice() else { return; };
-
-                    let data_read = data.read().await;
-                    if let Some(command) = data_read.commands.get(*cmd).cloned() {
-                        if !command.can_run(&msg, &bot) || command.is_builtin() {
-                            return;
+                            if !cooldown::check(cmd, command.cooldown(), &msg, &bot, &data).await {
+                                return;
+                            }
+                            crate::metrics::METRICS.commands.inc(cmd);
+                            data.write().await.record_command_use(cmd, &msg);
+                            io::spawn_io(data.clone(), io::refresh_command_stats(data.clone()));
+                            command
+                                .execute(
+                                    args.iter().copied().map(String::from).collect(),
+                                    &msg,
+                                    &bot,
+                                    data.clone(),
+                                )
+                                .await;
                         }
-
-                        drop(data_read);
-                        command
-                            .execute(
-                                args.iter().copied().map(String::from).collect(),
-                                &msg,
-                                &bot,
-                                data.clone(),
-                            )
-                            .await;
                     }
                 }
-            }
-        })),
+            },
+        )),
         // Listener executor
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_lse.clone();
-            async move {
-                if !data.read().await.options.features.listeners {
-                    return;
-                }
-
-                for listener in data.read().await.listeners.values() {
-                    listener.execute(&msg, &bot, data.clone()).await;
-                }
-            }
-        })),
-        // Common commands
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_cmn.clone();
-            async move {
-                if msg.text.starts_with("!commands") {
-                    let mut commands = data
-                        .read()
-                        .await
-                        .commands
-                        .iter()
-                        .filter_map(|(k, v)| v.can_run(&msg, &bot).then_some(k))
-                        .cloned()
-                        .collect::<Vec<_>>();
-                    commands.sort_unstable();
-                    bot.reply(&msg, format!("Commands: {}", commands.join(", ")))
-                        .await;
-                }
-            }
-        })),
-        // aliases
-        Box::pin(bot.on_chat_message(move |msg, bot| {
-            let data = data_als.clone();
-            async move {
-                if msg.text.starts_with("!help") {
-                    bot.mock_message(&msg, "!commands");
-                }
-                if data.read().await.options.features.custom_commands {
-                    if let Some(alias_args) = msg.text.strip_prefix("!cmd:add") {
-                        bot.mock_message(&msg, format!("!cmd:set{alias_args}"))
-                    }
-                }
-            }
+        Box::pin(bot.on_chat_message_concurrent(
+            HandlerOptions::new("listeners"),
+            move |msg, bot| {
+                let data = data_lse.clone();
+                async move { run_listeners(&msg, &bot, &data).await }
+            },
+        )),
+        // Chat line counter, for timers, and present chatters, for %chatter
+        Box::pin(bot.on_chat_message(move |msg, _| {
+            let data = data_lns.clone();
+            async move { count_line(&msg, &data).await }
         })),
+        // Timer executor
+        Box::pin(timer::run(store.0.clone(), bot.interface(), bot.shutdown())),
     ];
 
     let data = store.0.clone();
     async move {
         let mut data = data.write().await;
-        let mut builtins = vec![("shutdown", true), ("ping", true), ("commands", false)];
-
-        if data.options.features.custom_commands {
-            builtins.append(&mut vec![
-                ("cmd:set", true),
-                ("cmd:info", true),
-                ("cmd:remove", true),
-            ]);
-        }
-        if data.options.features.counters {
-            builtins.append(&mut vec![
-                ("counter:set", true),
-                ("counter:get", true),
-                ("counter:remove", true),
-                ("counter:list", true),
-            ]);
-        }
-
-        if data.options.features.listeners {
-            builtins.append(&mut vec![
-                ("listen:exact", true),
-                ("listen:has", true),
-                ("listen:regex", true),
-                ("listen:list", true),
-                ("listen:info", true),
-                ("listen:remove", true),
-            ]);
-        }
-
-        for (builtin, is_super) in builtins {
-            data.commands.insert(
-                String::from(builtin),
-                Arc::new(super::command::CommandRules::empty_builtin(is_super)),
-            );
+        let features = data.options.features;
+
+        let mut builtins = misc_commands();
+        if features.custom_commands {
+            builtins.append(&mut custom_command_commands());
+            builtins.append(&mut alias_commands());
+        }
+        if features.counters {
+            builtins.append(&mut counter_commands());
+        }
+        if features.listeners {
+            builtins.append(&mut listener_commands());
+        }
+        if features.timers {
+            builtins.append(&mut timer_commands());
+        }
+        for builtin in builtins {
+            data.router.add(builtin);
         }
         drop(data);
 
@@ -460,218 +132,1540 @@ pub fn register_base_commands(
     }
 }
 
-pub fn register_comet_commands(
+/// Thanks raiders in chat.
+pub fn register_raid_thanks(
     store: &super::Store,
     bot: &crate::bot::Bot,
-    comet_server: &comet::Server,
 ) -> impl std::future::Future<Output = ()> + 'static {
     let data = store.0.clone();
-    let command_future = bot.on_chat_message_comet(comet_server, move |msg, bot, cmt| {
-        let data = data.clone();
+    let thanks = Arc::new(CommandRules::parse(RAID_THANKS).expect("The raid thank-you is valid"));
+
+    bot.on_event::<eventsub::event::Raid, _>(move |notif, bot| {
+        let (data, thanks) = (data.clone(), thanks.clone());
         async move {
-            if !msg.user_is_super() {
-                return;
+            let raid = notif.payload.event;
+            let mut args = vec![raid.from_broadcaster_user_name];
+            match crate::twitch::stream_from_user_id(
+                &raid.from_broadcaster_user_id,
+                bot.helix_auth(),
+            )
+            .await
+            {
+                Ok(Some(stream)) => args.extend([
+                    stream.game_name,
+                    format!(
+                        "{} viewer{}",
+                        stream.viewer_count,
+                        if stream.viewer_count == 1 { "" } else { "s" }
+                    ),
+                ]),
+                Ok(None) => (),
+                Err(err) => log::warn(
+                    Target::Eye,
+                    format!("Could not get the raiding stream: {err}"),
+                ),
             }
+            thanks
+                .execute(args, &ChatMessage::default(), &bot, data)
+                .await;
+        }
+    })
+}
 
-            if let Some(arg) = msg.text.strip_prefix("!comet:get") {
-                let component_type = match arg.trim() {
-                    "audio" => comet::component::Type::Audio,
-                    arg => {
-                        bot.reply(&msg, format!("Unknown component type {arg:?}."))
-                            .await;
-                        return;
+fn misc_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "shutdown",
+            "Gracefully shuts down the bot.",
+            |inv| async move {
+                inv.bot.shutdown().await;
+            },
+        )
+        .permission(Permission::Moderator),
+        Command::new("ping", "Replies \"Pong!\"", |inv| async move {
+            inv.reply("Pong!").await;
+        })
+        .permission(Permission::Moderator),
+        Command::new(
+            "commands",
+            "Lists every command that you can use.",
+            |inv| async move {
+                let commands = list_commands(&inv).await;
+                inv.reply(commands).await;
+            },
+        )
+        .cooldown(Duration::from_secs(5)),
+        Command::new(
+            "help",
+            "Shows how to use a command, or lists every command that you can use.",
+            |inv| async move {
+                let Some(name) = inv.args.get(0) else {
+                    let commands = list_commands(&inv).await;
+                    inv.reply(commands).await;
+                    return;
+                };
+                let name = name.trim_start_matches('!');
+
+                let data = inv.data.read().await;
+                let help = if let Some(command) = data.router.get(name) {
+                    command.help_string()
+                } else if let Some(command) = data.commands.get(name) {
+                    match command.permission() {
+                        Permission::Everyone => format!("!{name} is a custom command."),
+                        permission => format!("!{name} is a custom command ({permission} only)."),
                     }
+                } else if let Some(target) = data.aliases.get(name) {
+                    format!("!{name} is an alias for !{target}.")
+                } else {
+                    format!("Unknown command {name:?}.")
                 };
+                drop(data);
 
-                let get_response = match cmt
-                    .send_message(comet::Message::GetComponents { component_type })
-                    .await
+                inv.reply(help).await;
+            },
+        )
+        .arg(Arg::word("command-name").optional())
+        .user_cooldown(Duration::from_secs(5)),
+        Command::new(
+            "eventsub:list",
+            "Lists the bot's EventSub subscriptions and their total cost.",
+            |inv| async move {
+                let Some(access_token) = eventsub_access_token(&inv).await else {
+                    return;
+                };
+                match eventsub::manage::list_subscriptions(
+                    &inv.bot.eventsub_urls().subscriptions,
+                    &inv.bot.helix_auth().client_id,
+                    &access_token,
+                )
+                .await
                 {
-                    Some(it) => it,
-                    None => {
-                        data.read()
+                    Ok(list) => {
+                        let subscriptions = list
+                            .subscriptions
+                            .iter()
+                            .map(|info| {
+                                format!("{} ({})", info.subscription.subscription_type, info.status)
+                            })
+                            .collect::<Vec<_>>();
+                        inv.reply(if subscriptions.is_empty() {
+                            format!("{}.", list.cost)
+                        } else {
+                            join_within(format!("{}: ", list.cost), &subscriptions, ", ")
+                        })
+                        .await;
+                    }
+                    Err(err) => {
+                        inv.reply(format!("Could not list subscriptions: {err}"))
                             .await
-                            .options
-                            .debug("Builtin: Comet client disconnected before response");
-                        return;
                     }
+                }
+            },
+        )
+        .permission(Permission::Moderator),
+        Command::new(
+            "eventsub:prune",
+            "Deletes every EventSub subscription that failed or was revoked or disconnected.",
+            |inv| async move {
+                let Some(access_token) = eventsub_access_token(&inv).await else {
+                    return;
                 };
-
-                match get_response {
-                    comet::ResponseData::Ok => {
-                        unreachable!("Expected data from GetComponents")
+                match eventsub::manage::prune_subscriptions(
+                    &inv.bot.eventsub_urls().subscriptions,
+                    &inv.bot.helix_auth().client_id,
+                    &access_token,
+                )
+                .await
+                {
+                    Ok(result) => inv.reply(result.to_string()).await,
+                    Err(err) => {
+                        inv.reply(format!("Could not prune subscriptions: {err}"))
+                            .await
                     }
-                    comet::ResponseData::Data { payload } => bot.reply(&msg, payload).await,
-                    comet::ResponseData::Error {
-                        is_internal,
-                        message,
-                    } => {
-                        bot.reply(
-                            &msg,
-                            format!(
-                                "{}Comet error: {message}",
-                                if is_internal { "Internal " } else { "" }
-                            ),
+                }
+            },
+        )
+        .permission(Permission::Moderator),
+        Command::new(
+            "handlers",
+            "Shows how long the bot's handlers take to run.",
+            |inv| async move {
+                let timings = inv
+                    .bot
+                    .handler_timings()
+                    .list()
+                    .into_iter()
+                    .map(|(name, timing)| {
+                        format!(
+                            "{name}: {} calls, {}ms average, {}ms max, {} slow",
+                            timing.calls,
+                            timing.average().as_millis(),
+                            timing.max.as_millis(),
+                            timing.slow_calls
                         )
+                    })
+                    .collect::<Vec<_>>();
+                inv.reply(if timings.is_empty() {
+                    String::from("No handlers have run yet.")
+                } else {
+                    timings.join("; ")
+                })
+                .await;
+            },
+        )
+        .permission(Permission::Moderator),
+        Command::new(
+            "plugin:list",
+            "Lists the bot's plugins and whether they are enabled.",
+            |inv| async move {
+                let plugins = inv
+                    .bot
+                    .plugins()
+                    .list()
+                    .await
+                    .into_iter()
+                    .map(|(name, is_enabled)| {
+                        format!("{name}{}", if is_enabled { "" } else { " (disabled)" })
+                    })
+                    .collect::<Vec<_>>();
+                inv.reply(if plugins.is_empty() {
+                    String::from("No plugins.")
+                } else {
+                    format!("Plugins: {}", plugins.join(", "))
+                })
+                .await;
+            },
+        )
+        .permission(Permission::Moderator),
+        Command::new(
+            "plugin:enable",
+            "Enables a plugin until the bot restarts.",
+            |inv| set_plugin_enabled(inv, true),
+        )
+        .arg(Arg::word("plugin-name"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "plugin:disable",
+            "Disables a plugin until the bot restarts.",
+            |inv| set_plugin_enabled(inv, false),
+        )
+        .arg(Arg::word("plugin-name"))
+        .permission(Permission::Moderator),
+    ]
+}
+
+async fn list_commands(inv: &Invocation) -> String {
+    // only ask Twitch if the chatter is a follower when it matters
+    let needs_follower = {
+        let data = inv.data.read().await;
+        let needs_follower = data
+            .router
+            .commands()
+            .map(|command| command.permission)
+            .chain(data.commands.values().map(|command| command.permission()))
+            .any(|permission| permission == Permission::Follower);
+        needs_follower
+    };
+    let permission = if needs_follower {
+        Permission::fetch(&inv.msg, &inv.bot).await
+    } else {
+        Permission::of(&inv.msg)
+    };
+
+    let data = inv.data.read().await;
+    // whether the chatter can run the command that an alias stands for
+    let can_run = |name: &str| {
+        if let Some(command) = data.router.get(name) {
+            permission >= command.permission
+        } else {
+            data.commands
+                .get(name)
+                .is_some_and(|command| command.can_run_as(&inv.msg, permission))
+        }
+    };
+    let mut commands = data
+        .router
+        .commands()
+        .filter(|command| permission >= command.permission)
+        .map(|command| String::from(command.name))
+        .chain(
+            data.commands
+                .iter()
+                .filter(|(_, command)| command.can_run_as(&inv.msg, permission))
+                .map(|(name, _)| name.clone()),
+        )
+        .chain(
+            data.aliases
+                .iter()
+                .filter(|(_, target)| can_run(alias_target_name(target)))
+                .map(|(name, _)| name.clone()),
+        )
+        .collect::<Vec<_>>();
+    drop(data);
+
+    commands.sort_unstable();
+    format!("Commands: {}", commands.join(", "))
+}
+
+/// Appends as many of `items` to `prefix` as fit in one chat message, and
+/// says how many were left out.
+fn join_within(prefix: String, items: &[String], separator: &str) -> String {
+    let mut message = prefix;
+    let mut length = message.chars().count();
+    for (i, item) in items.iter().enumerate() {
+        let rest = format!(" (and {} more)", items.len() - i);
+        let added = if i == 0 { 0 } else { separator.chars().count() } + item.chars().count();
+        let reserved = if i + 1 < items.len() {
+            rest.chars().count()
+        } else {
+            0
+        };
+        if length + added + reserved > crate::twitch::MAX_MESSAGE_LENGTH {
+            message.push_str(&rest);
+            return message;
+        }
+        if i > 0 {
+            message.push_str(separator);
+        }
+        message.push_str(item);
+        length += added;
+    }
+    message
+}
+
+async fn eventsub_access_token(inv: &Invocation) -> Option<String> {
+    match inv.bot.helix_auth().access.get_credentials().await {
+        Ok(credentials) => Some(credentials.access_token),
+        Err(err) => {
+            inv.reply(format!("Could not get an Access Token: {err}"))
+                .await;
+            None
+        }
+    }
+}
+
+async fn set_plugin_enabled(inv: Invocation, is_enabled: bool) {
+    let name = inv.args.word(0);
+    if inv.bot.plugins().set_enabled(name, is_enabled).await {
+        inv.reply(format!(
+            "{} plugin {name:?}.",
+            if is_enabled { "Enabled" } else { "Disabled" }
+        ))
+        .await;
+    } else {
+        inv.reply(format!("Plugin {name:?} not found.")).await;
+    }
+}
+
+fn custom_command_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "cmd:set",
+            "Creates or redefines a custom command.",
+            |inv| async move {
+                let command_name = inv.args.word(0);
+                let data = inv.data.read().await;
+                let error = if data.router.get(command_name).is_some() {
+                    Some(format!("Cannot set a builtin cmd {command_name:?}."))
+                } else {
+                    data.aliases
+                        .get(command_name)
+                        .map(|target| format!("!{command_name} is already an alias for !{target}."))
+                };
+                drop(data);
+                if let Some(error) = error {
+                    inv.reply(error).await;
+                    return;
+                }
+                let result = history::Kind::Command.apply(
+                    &mut *inv.data.write().await,
+                    command_name,
+                    &inv.msg.display_name,
+                    Some(inv.args.word(1)),
+                );
+                match result {
+                    Ok(()) => io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone())),
+                    Err(err) => inv.reply(format!("Could not create command: {err}")).await,
+                }
+            },
+        )
+        .alias("cmd:add")
+        .arg(Arg::word("command-name"))
+        .arg(Arg::text("command"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:info",
+            "Shows a command in its raw form.",
+            |inv| async move {
+                let command_name = inv.args.word(0);
+                let data = inv.data.read().await;
+                let info = if data.router.get(command_name).is_some() {
+                    format!("!{command_name} is a builtin command")
+                } else if let Some(body) = data.commands.get(command_name) {
+                    format!("!{command_name}: {}", body.as_words_string())
+                } else {
+                    format!("Unknown command {command_name:?}.")
+                };
+                drop(data);
+
+                inv.reply(info).await;
+            },
+        )
+        .arg(Arg::word("command-name"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:perm",
+            "Shows or sets who can use a custom command. <setting> is a permission \
+            (everyone, follower, sub, vip, mod, or broadcaster), or allow, deny, or \
+            reset followed by a user.",
+            command_permission,
+        )
+        .arg(Arg::word("command-name"))
+        .arg(Arg::word("setting").optional())
+        .arg(Arg::word("user").optional())
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:remove",
+            "Deletes a custom command.",
+            |inv| async move {
+                let command_name = inv.args.word(0);
+                let mut data = inv.data.write().await;
+                if data.router.get(command_name).is_some() {
+                    drop(data);
+                    inv.reply(format!("Cannot remove a builtin cmd {command_name:?}."))
+                        .await;
+                } else if data.commands.contains_key(command_name) {
+                    history::Kind::Command
+                        .apply(&mut data, command_name, &inv.msg.display_name, None)
+                        .expect("Removing a command always succeeds");
+                    drop(data);
+                    io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+                } else {
+                    drop(data);
+                    inv.reply(format!("Unknown command {command_name:?}."))
                         .await;
-                    }
                 }
-            }
+            },
+        )
+        .arg(Arg::word("command-name"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:stats",
+            "Shows how often, when, and by whom a custom command was last used. \
+            Without a command name, lists the least used custom commands.",
+            command_stats,
+        )
+        .arg(Arg::word("command-name").optional())
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:history",
+            "Lists the kept revisions of a custom command, or shows one of them.",
+            |inv| show_history(inv, history::Kind::Command),
+        )
+        .arg(Arg::word("command-name"))
+        .arg(Arg::integer("revision").optional())
+        .permission(Permission::Moderator),
+        Command::new(
+            "cmd:revert",
+            "Restores a revision of a custom command, by default the one before \
+            the latest change.",
+            |inv| revert(inv, history::Kind::Command),
+        )
+        .arg(Arg::word("command-name"))
+        .arg(Arg::integer("revision").optional())
+        .permission(Permission::Moderator),
+    ]
+}
 
-            if let Some(body) = msg.text.strip_prefix("!comet:play-audio") {
-                let mut sounds = super::comet::component::Sound::parse(body);
-                sounds.truncate(10);
+async fn show_history(inv: Invocation, kind: history::Kind) {
+    let name = inv.args.word(0);
+    let data = inv.data.read().await;
+    let Some(revisions) = kind.history(&data).revisions(name) else {
+        drop(data);
+        inv.reply(format!("No history of {} {name:?}.", kind.noun()))
+            .await;
+        return;
+    };
 
-                let play_response = match cmt
-                    .send_message(comet::Message::PlayAudio { data: sounds })
-                    .await
+    let reply = if let Some(number) = inv.args.get_integer(1) {
+        match revisions
+            .iter()
+            .find(|revision| i64::try_from(revision.number) == Ok(number))
+        {
+            Some(history::Revision {
+                body: Some(body), ..
+            }) => format!("{name} #{number}: {body}"),
+            Some(_) => format!("{name} #{number} removed it."),
+            None => format!("Revision #{number} of {name:?} isn't kept."),
+        }
+    } else {
+        let now = super::unix_now();
+        let revisions = revisions
+            .iter()
+            .rev()
+            .map(|revision| {
+                let change = if revision.body.is_some() {
+                    "set"
+                } else {
+                    "removed"
+                };
+                match &revision.editor {
+                    Some(editor) => format!(
+                        "#{} {change} by {editor} {} ago",
+                        revision.number,
+                        super::stream::format_elapsed(Duration::from_secs(
+                            now.saturating_sub(revision.time)
+                        )),
+                    ),
+                    None => format!("#{} from before history was kept", revision.number),
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{name}: {}", revisions.join(", "))
+    };
+    drop(data);
+
+    inv.reply(reply).await;
+}
+
+async fn revert(inv: Invocation, kind: history::Kind) {
+    let name = inv.args.word(0);
+    let number = match inv.args.get_integer(1).map(u64::try_from).transpose() {
+        Ok(number) => number,
+        Err(_) => {
+            inv.reply(String::from("Revisions are numbered from 1."))
+                .await;
+            return;
+        }
+    };
+
+    let mut data = inv.data.write().await;
+    let target = match kind.history(&data).target(name, number) {
+        Ok(revision) => revision.clone(),
+        Err(err) => {
+            drop(data);
+            inv.reply(format!("Could not revert {} {name:?}: {err}", kind.noun()))
+                .await;
+            return;
+        }
+    };
+    let result = kind.apply(
+        &mut data,
+        name,
+        &inv.msg.display_name,
+        target.body.as_deref(),
+    );
+    drop(data);
+
+    match result {
+        Ok(()) => {
+            io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            inv.reply(format!(
+                "Reverted {} {name:?} to revision #{}.",
+                kind.noun(),
+                target.number
+            ))
+            .await;
+        }
+        Err(err) => {
+            inv.reply(format!("Could not revert {} {name:?}: {err}", kind.noun()))
+                .await;
+        }
+    }
+}
+
+async fn command_stats(inv: Invocation) {
+    let now = super::unix_now();
+    let data = inv.data.read().await;
+
+    let reply = if let Some(command_name) = inv.args.get(0) {
+        command_use(&data, command_name, now)
+    } else {
+        let mut uses = data
+            .commands
+            .keys()
+            .map(|name| {
+                let stats = data.command_stats.get(name);
+                (stats.map_or(0, |stats| stats.uses), name)
+            })
+            .collect::<Vec<_>>();
+        uses.sort();
+        let least_used = uses
+            .iter()
+            .take(STATS_LIST_LENGTH)
+            .map(|(uses, name)| format!("!{name} ({uses})"))
+            .collect::<Vec<_>>();
+        if least_used.is_empty() {
+            String::from("No custom commands.")
+        } else {
+            format!("Least used: {}", least_used.join(", "))
+        }
+    };
+    drop(data);
+
+    inv.reply(reply).await;
+}
+
+/// How often, when, and by whom the custom command `command_name` was last
+/// used, `now` being the time in seconds since the Unix epoch.
+fn command_use(data: &super::StoreData, command_name: &str, now: u64) -> String {
+    if !data.commands.contains_key(command_name) {
+        return format!("Unknown command {command_name:?}.");
+    }
+    let Some(stats) = data.command_stats.get(command_name) else {
+        return format!("!{command_name} has never been used.");
+    };
+    let ago = Duration::from_secs(now.saturating_sub(stats.last_used));
+    format!(
+        "!{command_name} was used {} times, last {} ago by {}.",
+        stats.uses,
+        super::stream::format_elapsed(ago),
+        stats.last_user,
+    )
+}
+
+fn alias_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "alias:set",
+            "Makes !<alias-name> run a command, with any arguments after the alias \
+            added to the end.",
+            |inv| async move {
+                let alias_name = inv.args.word(0).trim_start_matches('!');
+                let target = inv.args.word(1).trim_start_matches('!');
+                let target_name = alias_target_name(target);
+
+                let mut data = inv.data.write().await;
+                let error = if data.router.get(alias_name).is_some()
+                    || data.commands.contains_key(alias_name)
                 {
-                    Some(it) => it,
-                    None => {
-                        data.read()
-                            .await
-                            .options
-                            .debug("Builtin: Comet client disconnected before response");
-                        return;
-                    }
+                    Some(format!("!{alias_name} is already a command."))
+                } else if data.aliases.contains_key(target_name) {
+                    Some(format!(
+                        "!{target_name} is an alias, which can't be aliased."
+                    ))
+                } else if data.router.get(target_name).is_none()
+                    && !data.commands.contains_key(target_name)
+                {
+                    Some(format!("Unknown command {target_name:?}."))
+                } else {
+                    data.aliases
+                        .insert(String::from(alias_name), String::from(target));
+                    None
                 };
+                drop(data);
 
-                match play_response {
-                    comet::ResponseData::Ok => (),
-                    comet::ResponseData::Data { payload: _ } => unreachable!(),
-                    comet::ResponseData::Error {
-                        is_internal,
-                        message,
-                    } => {
-                        bot.reply(
-                            &msg,
-                            format!(
-                                "{}Comet error: {message}",
-                                if is_internal { "Internal " } else { "" }
-                            ),
-                        )
-                        .await;
-                    }
+                if let Some(error) = error {
+                    inv.reply(error).await;
+                } else {
+                    io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
                 }
-            } else if let Some(body) = msg.text.strip_prefix("!comet:volume") {
-                if let Some((name, value)) = body.trim().split_once(' ') {
-                    if let Ok(value) = value.parse() {
-                        let response = match cmt
-                            .send_message(comet::Message::AudioVolume {
-                                name: String::from(name),
-                                value,
-                            })
-                            .await
-                        {
-                            Some(it) => it,
-                            None => {
-                                data.read()
-                                    .await
-                                    .options
-                                    .debug("Builtin: Comet client disconnected before response");
-                                return;
-                            }
-                        };
-                        match response {
-                            comet::ResponseData::Ok => (),
-                            comet::ResponseData::Data { payload: _ } => unreachable!(),
-                            comet::ResponseData::Error {
-                                is_internal,
-                                message,
-                            } => {
-                                bot.reply(
-                                    &msg,
-                                    format!(
-                                        "{}Comet error: {message}",
-                                        if is_internal { "Internal " } else { "" }
-                                    ),
-                                )
-                                .await;
-                            }
-                        }
-                    } else {
-                        bot.reply(
-                            &msg,
-                            // cargo fmt doesn't format huge strings
-                            String::from("Command \"comet:volume\" expects a number ")
-                                + &format!("as its 2nd argument, got {value:?}"),
-                        )
+            },
+        )
+        .arg(Arg::word("alias-name"))
+        .arg(Arg::text("command"))
+        .permission(Permission::Moderator),
+        Command::new("alias:remove", "Deletes an alias.", |inv| async move {
+            let alias_name = inv.args.word(0).trim_start_matches('!');
+            let was_removed = inv.data.write().await.aliases.remove(alias_name).is_some();
+            if was_removed {
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            } else {
+                inv.reply(format!("Unknown alias {alias_name:?}.")).await;
+            }
+        })
+        .arg(Arg::word("alias-name"))
+        .permission(Permission::Moderator),
+        Command::new("alias:list", "Lists every alias.", |inv| async move {
+            let mut aliases = inv
+                .data
+                .read()
+                .await
+                .aliases
+                .iter()
+                .map(|(name, target)| format!("!{name} -> !{target}"))
+                .collect::<Vec<_>>();
+            aliases.sort_unstable();
+
+            inv.reply(if aliases.is_empty() {
+                String::from("No aliases.")
+            } else {
+                format!("Aliases: {}", aliases.join(", "))
+            })
+            .await;
+        })
+        .permission(Permission::Moderator),
+    ]
+}
+
+/// Runs every listener that matches `msg`. The listeners are collected first,
+/// since running one can write to the store.
+async fn run_listeners(
+    msg: &ChatMessage,
+    bot: &crate::bot::interface::BotInterface,
+    data: &super::StoreInner,
+) {
+    let listeners = {
+        let data = data.read().await;
+        if !data.options.features.listeners {
+            return;
+        }
+        data.listeners
+            .iter()
+            .map(|(name, listener)| (name.clone(), listener.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    for (name, listener) in listeners {
+        if listener.execute(msg, bot, data.clone()).await {
+            crate::metrics::METRICS.listener_matches.inc(&name);
+        }
+    }
+}
+
+async fn count_line(msg: &ChatMessage, data: &super::StoreInner) {
+    let mut data = data.write().await;
+    data.chat_lines += 1;
+    data.saw_chatter(msg);
+}
+
+/// The name of the command that an alias runs, e.g. `comet:get` for
+/// `comet:get audio`.
+fn alias_target_name(target: &str) -> &str {
+    target.split_once(' ').map_or(target, |(name, _)| name)
+}
+
+/// If `msg` calls an alias, rewrites it to call the alias's command instead,
+/// with any arguments after the alias added to the end. Commands with the
+/// same name as an alias take precedence.
+async fn resolve_alias(msg: ChatMessage, data: &super::StoreInner) -> ChatMessage {
+    let Some(text) = msg.text.strip_prefix('!') else {
+        return msg;
+    };
+    let text = text.trim();
+    let (name, args) = text.split_once(' ').unwrap_or((text, ""));
+
+    let data = data.read().await;
+    if data.router.get(name).is_some() || data.commands.contains_key(name) {
+        return msg;
+    }
+    let Some(target) = data.aliases.get(name) else {
+        return msg;
+    };
+    let text = format!("!{target} {args}").trim_end().to_owned();
+    drop(data);
+
+    ChatMessage { text, ..msg }
+}
+
+async fn command_permission(inv: Invocation) {
+    enum Setting<'a> {
+        Permission(Permission),
+        /// A user, and whether they're allowed, denied, or neither.
+        Exception(&'a str, Option<bool>),
+    }
+
+    let command_name = inv.args.word(0);
+    let Some(setting) = inv.args.get(1) else {
+        let Some(command) = inv.data.read().await.commands.get(command_name).cloned() else {
+            inv.reply(format!("Unknown custom command {command_name:?}."))
+                .await;
+            return;
+        };
+        let exceptions = command.exceptions();
+        let mut info = format!("!{command_name}: {}", command.permission());
+        if !exceptions.allow.is_empty() {
+            info += &format!(", allowed: {}", exceptions.allow.join(", "));
+        }
+        if !exceptions.deny.is_empty() {
+            info += &format!(", denied: {}", exceptions.deny.join(", "));
+        }
+        inv.reply(info).await;
+        return;
+    };
+
+    let setting = if let Some(permission) = Permission::from_name(setting) {
+        Setting::Permission(permission)
+    } else {
+        let is_allowed = match setting {
+            "allow" => Some(true),
+            "deny" => Some(false),
+            "reset" => None,
+            _ => {
+                inv.reply(format!(
+                    "Unknown setting {setting:?}. Usage: {}",
+                    inv.usage()
+                ))
+                .await;
+                return;
+            }
+        };
+        let Some(user) = inv.args.get(2) else {
+            inv.reply(format!("Missing <user>. Usage: {}", inv.usage()))
+                .await;
+            return;
+        };
+        Setting::Exception(user.trim_start_matches('@'), is_allowed)
+    };
+
+    let mut data = inv.data.write().await;
+    let Some(command) = data.commands.get(command_name) else {
+        drop(data);
+        inv.reply(format!("Unknown custom command {command_name:?}."))
+            .await;
+        return;
+    };
+    let mut command = CommandRules::clone(command);
+    let reply = match setting {
+        Setting::Permission(permission) => {
+            command.set_permission(permission);
+            format!("!{command_name} can now be used by {permission}.")
+        }
+        Setting::Exception(user, is_allowed) => {
+            command.set_exception(user, is_allowed);
+            match is_allowed {
+                Some(true) => format!("{user} can now always use !{command_name}."),
+                Some(false) => format!("{user} can no longer use !{command_name}."),
+                None => format!("Removed {user} from !{command_name}'s allow and deny lists."),
+            }
+        }
+    };
+    let result = history::Kind::Command.apply(
+        &mut data,
+        command_name,
+        &inv.msg.display_name,
+        Some(&command.as_words_string()),
+    );
+    drop(data);
+
+    match result {
+        Ok(()) => {
+            io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            inv.reply(reply).await;
+        }
+        Err(err) => {
+            inv.reply(format!("Could not change !{command_name}: {err}"))
+                .await;
+        }
+    }
+}
+
+fn counter_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "counter:set",
+            "Creates a counter or sets its value.",
+            |inv| async move {
+                inv.data
+                    .write()
+                    .await
+                    .counters
+                    .insert(String::from(inv.args.word(0)), inv.args.integer(1));
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            },
+        )
+        .arg(Arg::word("counter-name"))
+        .arg(Arg::integer("value"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "counter:get",
+            "Shows the value of a counter.",
+            |inv| async move {
+                let counter_name = inv.args.word(0);
+                let value = inv.data.read().await.counters.get(counter_name).copied();
+                if let Some(value) = value {
+                    inv.reply(format!("Counter {counter_name:?}: {value}"))
                         .await;
-                    }
                 } else {
-                    bot.reply(&msg, "Command \"comet:volume\" expects 2 arguments")
+                    inv.reply(format!("Unknown counter {counter_name:?}."))
+                        .await
+                }
+            },
+        )
+        .arg(Arg::word("counter-name"))
+        .permission(Permission::Moderator),
+        Command::new("counter:remove", "Deletes a counter.", |inv| async move {
+            let counter_name = inv.args.word(0);
+            let mut data = inv.data.write().await;
+            data.user_counters.remove(counter_name);
+            let was_removed = data.counters.remove(counter_name).is_some();
+            drop(data);
+            if was_removed {
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            } else {
+                inv.reply(format!("Unknown counter {counter_name:?}."))
+                    .await;
+            }
+        })
+        .arg(Arg::word("counter-name"))
+        .permission(Permission::Moderator),
+        Command::new("counter:list", "Lists every counter.", |inv| async move {
+            let keys = inv
+                .data
+                .read()
+                .await
+                .counters
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            inv.reply(if keys.is_empty() {
+                String::from("No counters.")
+            } else {
+                format!("Counters: {}", keys.join(", "))
+            })
+            .await;
+        })
+        .permission(Permission::Moderator),
+        Command::new(
+            "counter:top",
+            "Shows the chatters with the highest values of a per-user counter.",
+            |inv| async move {
+                let counter_name = inv.args.word(0);
+                let data = inv.data.read().await;
+                if !data.counters.contains_key(counter_name) {
+                    drop(data);
+                    inv.reply(format!("Unknown counter {counter_name:?}."))
                         .await;
+                    return;
                 }
-            } else if msg.text.starts_with("!comet:ping") {
-                bot.reply(
-                    &msg,
-                    if cmt.has_client().await {
-                        "Pong!"
-                    } else {
-                        "No Comet client."
-                    },
-                )
+                let mut counts = data
+                    .user_counters
+                    .get(counter_name)
+                    .map(|values| values.values().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                counts.sort_by(|a, b| {
+                    b.value
+                        .cmp(&a.value)
+                        .then(a.display_name.cmp(&b.display_name))
+                });
+                let leaders = counts
+                    .iter()
+                    .take(COUNTER_TOP_LENGTH)
+                    .enumerate()
+                    .map(|(index, count)| {
+                        format!("{}. {} ({})", index + 1, count.display_name, count.value)
+                    })
+                    .collect::<Vec<_>>();
+                drop(data);
+
+                inv.reply(if leaders.is_empty() {
+                    format!("Nobody has a value for {counter_name:?} yet.")
+                } else {
+                    format!("Top {counter_name}: {}", leaders.join(", "))
+                })
                 .await;
-            } else if msg.text.starts_with("!comet:clear-audio") {
-                let response = match cmt.send_message(comet::Message::AudioClear {}).await {
-                    Some(it) => it,
-                    None => {
-                        data.read()
-                            .await
-                            .options
-                            .debug("Builtin: Comet client disconnected before response");
+            },
+        )
+        .arg(Arg::word("counter-name"))
+        .cooldown(Duration::from_secs(5)),
+    ]
+}
+
+fn listener_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "listen:exact",
+            "Creates a listener that runs when a message is exactly its pattern.",
+            |inv| set_listener(inv, |pattern| Ok(listener::Predicate::Exactly(pattern))),
+        )
+        .arg(Arg::word("listener-name"))
+        .arg(Arg::text("pattern/command"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "listen:has",
+            "Creates a listener that runs when a message contains its pattern.",
+            |inv| set_listener(inv, |pattern| Ok(listener::Predicate::Contains(pattern))),
+        )
+        .arg(Arg::word("listener-name"))
+        .arg(Arg::text("pattern/command"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "listen:regex",
+            "Creates a listener that runs when a message matches its regex pattern.",
+            |inv| {
+                set_listener(inv, |pattern| {
+                    // FIXME: Report regex errors
+                    Regex::new(&pattern)
+                        .map(listener::Predicate::Regex)
+                        .map_err(|_| String::from("Regex error."))
+                })
+            },
+        )
+        .arg(Arg::word("listener-name"))
+        .arg(Arg::text("pattern/command"))
+        .permission(Permission::Moderator),
+        Command::new(
+            "listen:info",
+            "Shows a listener in its raw form.",
+            |inv| async move {
+                let name = inv.args.word(0);
+                let data = inv.data.read().await;
+                let info = if let Some(listener) = data.listeners.get(name) {
+                    format!(
+                        "Listener {name} {}/{}",
+                        match &listener.predicate {
+                            listener::Predicate::Exactly(pat) => format!("(exact): {pat}"),
+                            listener::Predicate::Contains(pat) => format!("(contains): {pat}"),
+                            listener::Predicate::Regex(pat) => format!("(regex): {pat}"),
+                        },
+                        listener.body.as_words_string(),
+                    )
+                } else {
+                    format!("Unknown listener {name}.")
+                };
+                drop(data);
+
+                inv.reply(info).await;
+            },
+        )
+        .arg(Arg::word("listener-name"))
+        .permission(Permission::Moderator),
+        Command::new("listen:remove", "Deletes a listener.", |inv| async move {
+            let name = inv.args.word(0);
+            let mut data = inv.data.write().await;
+            if data.listeners.contains_key(name) {
+                history::Kind::Listener
+                    .apply(&mut data, name, &inv.msg.display_name, None)
+                    .expect("Removing a listener always succeeds");
+                drop(data);
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            } else {
+                drop(data);
+                inv.reply(format!("Unknown listener {name}.")).await;
+            }
+        })
+        .arg(Arg::word("listener-name"))
+        .permission(Permission::Moderator),
+        Command::new("listen:list", "Lists every listener.", |inv| async move {
+            let keys = inv
+                .data
+                .read()
+                .await
+                .listeners
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            inv.reply(if keys.is_empty() {
+                String::from("No listeners.")
+            } else {
+                format!("Listeners: {}", keys.join(", "))
+            })
+            .await;
+        })
+        .permission(Permission::Moderator),
+        Command::new(
+            "listen:history",
+            "Lists the kept revisions of a listener, or shows one of them.",
+            |inv| show_history(inv, history::Kind::Listener),
+        )
+        .arg(Arg::word("listener-name"))
+        .arg(Arg::integer("revision").optional())
+        .permission(Permission::Moderator),
+        Command::new(
+            "listen:revert",
+            "Restores a revision of a listener, by default the one before the \
+            latest change.",
+            |inv| revert(inv, history::Kind::Listener),
+        )
+        .arg(Arg::word("listener-name"))
+        .arg(Arg::integer("revision").optional())
+        .permission(Permission::Moderator),
+    ]
+}
+
+async fn set_listener(
+    inv: Invocation,
+    predicate: impl FnOnce(String) -> Result<listener::Predicate, String>,
+) {
+    let Some((name, pattern, command)) =
+        listener::Listener::parts(&format!("{} {}", inv.args.word(0), inv.args.word(1)))
+    else {
+        inv.reply(format!(
+            "Expected a pattern and a command separated by a \"/\". Usage: {}",
+            inv.usage()
+        ))
+        .await;
+        return;
+    };
+
+    let predicate = match predicate(pattern) {
+        Ok(predicate) => predicate,
+        Err(err) => {
+            inv.reply(err).await;
+            return;
+        }
+    };
+    match CommandRules::parse(&command) {
+        Ok(body) => {
+            let definition = listener::Listener { predicate, body }.definition();
+            let result = history::Kind::Listener.apply(
+                &mut *inv.data.write().await,
+                &name,
+                &inv.msg.display_name,
+                Some(&definition),
+            );
+            match result {
+                Ok(()) => io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone())),
+                Err(err) => {
+                    inv.reply(format!("Could not create listener: {err}")).await;
+                }
+            }
+        }
+        Err(err) => {
+            inv.reply(format!("Could not create listener: {err}")).await;
+        }
+    }
+}
+
+fn timer_commands() -> Vec<Command> {
+    vec![
+        Command::new(
+            "timer:set",
+            "Creates or redefines a timer, which posts its command every <interval> \
+            (e.g. 15m) while the stream is online, if at least <min-lines> chat \
+            messages were sent since it last posted.",
+            |inv| async move {
+                let name = inv.args.word(0);
+                let Some(interval) = cooldown::parse_duration(inv.args.word(1)) else {
+                    inv.reply(format!(
+                        "Expected a duration like 15m for <interval>, got {:?}. Usage: {}",
+                        inv.args.word(1),
+                        inv.usage()
+                    ))
+                    .await;
+                    return;
+                };
+                if interval < timer::MIN_INTERVAL {
+                    inv.reply(format!(
+                        "Timers can't post more than once every {}.",
+                        cooldown::format_duration(timer::MIN_INTERVAL)
+                    ))
+                    .await;
+                    return;
+                }
+                let Ok(min_lines) = u64::try_from(inv.args.integer(2)) else {
+                    inv.reply(format!(
+                        "<min-lines> can't be negative. Usage: {}",
+                        inv.usage()
+                    ))
+                    .await;
+                    return;
+                };
+
+                let body = match CommandRules::parse(inv.args.word(3)) {
+                    Ok(body) => body,
+                    Err(err) => {
+                        inv.reply(format!("Could not create timer: {err}")).await;
                         return;
                     }
                 };
-                match response {
-                    comet::ResponseData::Ok => (),
-                    comet::ResponseData::Data { .. } => unreachable!(),
-                    comet::ResponseData::Error {
-                        is_internal,
-                        message,
-                    } => {
-                        bot.reply(
-                            &msg,
-                            format!(
-                                "{}Comet error: {message}",
-                                if is_internal { "Internal " } else { "" }
-                            ),
-                        )
+                if let Some(part) = body.caller_only_part() {
+                    inv.reply(format!(
+                        "Could not create timer: Timers can't use {part}, since nobody calls them."
+                    ))
+                    .await;
+                    return;
+                }
+
+                let mut data = inv.data.write().await;
+                let timer = timer::Timer::new(interval, min_lines, body, data.chat_lines);
+                data.timers.insert(String::from(name), timer);
+                drop(data);
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            },
+        )
+        .arg(Arg::word("timer-name"))
+        .arg(Arg::word("interval"))
+        .arg(Arg::integer("min-lines"))
+        .arg(Arg::text("command"))
+        .permission(Permission::Moderator),
+        Command::new("timer:list", "Lists every timer.", |inv| async move {
+            let data = inv.data.read().await;
+            let mut timers = data
+                .timers
+                .iter()
+                .map(|(name, timer)| {
+                    format!(
+                        "{name} (every {}, {} lines)",
+                        cooldown::format_duration(timer.interval),
+                        timer.min_lines
+                    )
+                })
+                .collect::<Vec<_>>();
+            drop(data);
+            timers.sort_unstable();
+
+            inv.reply(if timers.is_empty() {
+                String::from("No timers.")
+            } else {
+                format!("Timers: {}", timers.join(", "))
+            })
+            .await;
+        })
+        .permission(Permission::Moderator),
+        Command::new("timer:remove", "Deletes a timer.", |inv| async move {
+            let name = inv.args.word(0);
+            if inv.data.write().await.timers.remove(name).is_some() {
+                io::spawn_io(inv.data.clone(), io::refresh(inv.data.clone()));
+            } else {
+                inv.reply(format!("Unknown timer {name}.")).await;
+            }
+        })
+        .arg(Arg::word("timer-name"))
+        .permission(Permission::Moderator),
+    ]
+}
+
+pub fn register_comet_commands(
+    store: &super::Store,
+    comet_server: &comet::Server,
+) -> impl std::future::Future<Output = ()> + 'static {
+    let cmt = comet_server.interface();
+    let data = store.0.clone();
+
+    async move {
+        let builtins = [
+            Command::new(
+                "comet:get",
+                "Lists the Comet client's components of a type, e.g. \"audio\".",
+                {
+                    let cmt = cmt.clone();
+                    move |inv| get_components(inv, cmt.clone())
+                },
+            )
+            .arg(Arg::word("component-type"))
+            .permission(Permission::Moderator),
+            Command::new("sounds", "Lists every sound that can be played.", {
+                let cmt = cmt.clone();
+                move |inv| get_components(inv, cmt.clone())
+            })
+            .cooldown(Duration::from_secs(5)),
+            Command::new("comet:play-audio", "Plays sounds on the Comet client.", {
+                let cmt = cmt.clone();
+                move |inv| {
+                    let cmt = cmt.clone();
+                    async move {
+                        let mut sounds = comet::component::Sound::parse(inv.args.word(0));
+                        sounds.truncate(10);
+
+                        send_comet(&inv, &cmt, comet::Message::PlayAudio { data: sounds }).await;
+                    }
+                }
+            })
+            .arg(Arg::text("sounds"))
+            .permission(Permission::Moderator),
+            Command::new(
+                "comet:volume",
+                "Sets the volume of a sound on the Comet client.",
+                {
+                    let cmt = cmt.clone();
+                    move |inv| {
+                        let cmt = cmt.clone();
+                        async move {
+                            let value = inv.args.word(1);
+                            let Ok(value) = value.parse() else {
+                                inv.reply(format!(
+                                    "Expected a number for <volume>, got {value:?}. Usage: {}",
+                                    inv.usage()
+                                ))
+                                .await;
+                                return;
+                            };
+
+                            send_comet(
+                                &inv,
+                                &cmt,
+                                comet::Message::AudioVolume {
+                                    name: String::from(inv.args.word(0)),
+                                    value,
+                                },
+                            )
+                            .await;
+                        }
+                    }
+                },
+            )
+            .arg(Arg::word("sound-name"))
+            .arg(Arg::word("volume"))
+            .permission(Permission::Moderator),
+            Command::new("comet:ping", "Checks for a Comet client.", {
+                let cmt = cmt.clone();
+                move |inv| {
+                    let cmt = cmt.clone();
+                    async move {
+                        inv.reply(if cmt.has_client().await {
+                            "Pong!"
+                        } else {
+                            "No Comet client."
+                        })
                         .await;
                     }
                 }
-            }
+            })
+            .permission(Permission::Moderator),
+            Command::new(
+                "comet:clear-audio",
+                "Stops every sound on the Comet client.",
+                {
+                    let cmt = cmt.clone();
+                    move |inv| {
+                        let cmt = cmt.clone();
+                        async move {
+                            send_comet(&inv, &cmt, comet::Message::AudioClear {}).await;
+                        }
+                    }
+                },
+            )
+            .permission(Permission::Moderator),
+        ];
+
+        let mut data = data.write().await;
+        for builtin in builtins {
+            data.router.add(builtin);
         }
-    });
+    }
+}
 
-    // aliases
-    let data = store.0.clone();
-    let alias_future = bot.on_chat_message(move |msg, bot| {
-        let _data = data.clone();
-        async move {
-            if msg.text.starts_with("!sounds") {
-                bot.mock_message(
-                    &crate::chat::data::ChatMessage {
-                        is_moderator: true,
-                        ..msg
-                    },
-                    "!comet:get audio",
-                )
+async fn get_components(inv: Invocation, cmt: comet::CometInterface) {
+    // `!sounds` has no arguments
+    let component_type = match inv.args.get(0).unwrap_or("audio") {
+        "audio" => comet::component::Type::Audio,
+        arg => {
+            inv.reply(format!("Unknown component type {arg:?}.")).await;
+            return;
+        }
+    };
+
+    if let Some(payload) =
+        send_comet(&inv, &cmt, comet::Message::GetComponents { component_type }).await
+    {
+        inv.reply(payload).await;
+    }
+}
+
+/// Sends `message` to the Comet client, replying with any error. Returns the
+/// response's payload if it has one.
+async fn send_comet(
+    inv: &Invocation,
+    cmt: &comet::CometInterface,
+    message: comet::Message,
+) -> Option<String> {
+    let Some(response) = cmt.send_message(message).await else {
+        log::debug(Target::Eye, "Comet client disconnected before response");
+        return None;
+    };
+
+    match response {
+        comet::ResponseData::Ok => None,
+        comet::ResponseData::Data { payload } => Some(payload),
+        comet::ResponseData::Error {
+            is_internal,
+            message,
+        } => {
+            inv.reply(format!(
+                "{}Comet error: {message}",
+                if is_internal { "Internal " } else { "" }
+            ))
+            .await;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raid_thanks_only_use_the_raid() {
+        let thanks = CommandRules::parse(RAID_THANKS).unwrap();
+        assert_eq!(thanks.caller_only_part(), None);
+    }
+
+    #[test]
+    fn builtin_names_are_unique() {
+        let builtins: Vec<_> = [
+            misc_commands(),
+            custom_command_commands(),
+            alias_commands(),
+            counter_commands(),
+            listener_commands(),
+            timer_commands(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let count = builtins.len();
+
+        // adding panics if an alias collides
+        let mut router = router::Router::default();
+        for builtin in builtins {
+            router.add(builtin);
+        }
+        assert_eq!(router.commands().count(), count, "No builtin is replaced");
+    }
+
+    /// A store with the custom command and alias builtins, `!hug` and an alias
+    /// `!h` for it.
+    async fn alias_store() -> super::super::StoreInner {
+        let data = super::super::StoreData::mock();
+        {
+            let mut data = data.write().await;
+            for builtin in custom_command_commands()
+                .into_iter()
+                .chain(alias_commands())
+            {
+                data.router.add(builtin);
             }
+            data.commands.insert(
+                String::from("hug"),
+                Arc::new(CommandRules::parse("hugs %0").unwrap()),
+            );
+            data.aliases.insert(String::from("h"), String::from("hug"));
         }
-    });
+        data
+    }
 
-    let data = store.0.clone();
-    async move {
-        let mut data = data.write().await;
-        for builtin in ["get", "play-audio", "clear-audio", "ping", "volume"] {
+    async fn run_as_mod(text: &str, data: &super::super::StoreInner) {
+        let msg = ChatMessage {
+            text: String::from(text),
+            is_moderator: true,
+            ..ChatMessage::default()
+        };
+        let bot = crate::bot::interface::BotInterface::mock().await;
+        assert!(router::route(msg, bot, data.clone()).await);
+    }
+
+    async fn resolved(text: &str, data: &super::super::StoreInner) -> String {
+        let msg = ChatMessage {
+            text: String::from(text),
+            ..ChatMessage::default()
+        };
+        resolve_alias(msg, data).await.text
+    }
+
+    #[tokio::test]
+    async fn aliases_pass_arguments_on() {
+        let data = alias_store().await;
+        data.write()
+            .await
+            .aliases
+            .insert(String::from("hh"), String::from("hug everyone"));
+
+        assert_eq!(resolved("!h", &data).await, "!hug");
+        assert_eq!(
+            resolved("!h @friend  twice ", &data).await,
+            "!hug @friend  twice"
+        );
+        assert_eq!(resolved("!hh", &data).await, "!hug everyone");
+        assert_eq!(resolved("!hh again", &data).await, "!hug everyone again");
+        assert_eq!(resolved("h", &data).await, "h");
+        assert_eq!(resolved("!unknown", &data).await, "!unknown");
+    }
+
+    #[tokio::test]
+    async fn commands_take_precedence_over_aliases() {
+        let data = alias_store().await;
+        {
+            let mut data = data.write().await;
             data.commands.insert(
-                format!("comet:{builtin}"),
-                Arc::new(super::command::CommandRules::empty_builtin(true)),
+                String::from("h"),
+                Arc::new(CommandRules::parse("hello").unwrap()),
             );
+            data.aliases
+                .insert(String::from("cmd:info"), String::from("hug"));
         }
-        drop(data);
 
-        tokio::join!(command_future, alias_future);
+        assert_eq!(resolved("!h", &data).await, "!h");
+        assert_eq!(resolved("!cmd:info hug", &data).await, "!cmd:info hug");
+    }
+
+    #[tokio::test]
+    async fn aliases_need_a_command_to_run() {
+        let data = alias_store().await;
+
+        run_as_mod("!alias:set hh !hug everyone", &data).await;
+        run_as_mod("!alias:set x unknown", &data).await;
+        run_as_mod("!alias:set y h", &data).await;
+        run_as_mod("!alias:set hug cmd:info", &data).await;
+        run_as_mod("!alias:set cmd:info hug", &data).await;
+
+        let data = data.read().await;
+        let mut aliases: Vec<_> = data.aliases.iter().collect();
+        aliases.sort_unstable();
+        assert_eq!(
+            aliases,
+            [
+                (&String::from("h"), &String::from("hug")),
+                (&String::from("hh"), &String::from("hug everyone")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_cannot_shadow_aliases() {
+        let data = alias_store().await;
+
+        run_as_mod("!cmd:set h Hello!", &data).await;
+        run_as_mod("!cmd:add wave Hi!", &data).await;
+
+        let data = data.read().await;
+        assert!(!data.commands.contains_key("h"));
+        assert!(data.commands.contains_key("wave"));
+        assert_eq!(data.aliases.get("h").map(String::as_str), Some("hug"));
+    }
+
+    #[test]
+    fn joined_lists_fit_in_a_message() {
+        let items = |count| (0..count).map(|i| format!("item{i}")).collect::<Vec<_>>();
+        assert_eq!(
+            join_within(String::from("Items: "), &items(3), ", "),
+            "Items: item0, item1, item2"
+        );
+
+        let message = join_within(String::from("Items: "), &items(200), ", ");
+        assert!(message.chars().count() <= crate::twitch::MAX_MESSAGE_LENGTH);
+        let (listed, rest) = message.split_once(" (and ").unwrap();
+        let listed = listed.trim_start_matches("Items: ").split(", ").count();
+        assert_eq!(rest, format!("{} more)", 200 - listed));
+    }
+
+    #[tokio::test]
+    async fn listeners_run_while_lines_are_counted() {
+        let data = super::super::StoreData::mock();
+        {
+            let mut data = data.write().await;
+            data.counters.insert(String::from("greetings"), 0);
+            data.listeners.insert(
+                String::from("greet"),
+                Arc::new(
+                    listener::Listener::parse("c hello/&C:INC=greetings Hi! %counter=greetings")
+                        .unwrap(),
+                ),
+            );
+        }
+        let bot = crate::bot::interface::BotInterface::mock().await;
+        let msg = ChatMessage {
+            text: String::from("hello chat"),
+            user_id: String::from("1234"),
+            display_name: String::from("Chatter"),
+            ..ChatMessage::default()
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                run_listeners(&msg, &bot, &data),
+                count_line(&msg, &data),
+                count_line(&msg, &data),
+            )
+        })
+        .await
+        .expect("Listeners don't deadlock with the line counter");
+
+        let data = data.read().await;
+        assert_eq!(data.counters["greetings"], 1);
+        assert_eq!(data.chat_lines, 2);
     }
 }
@@ -13,6 +13,8 @@ struct InterfaceData {
     features: Option<HashSet<Feature>>,
     message_sender: Option<mpsc::Sender<TaggedMessage>>,
     response_receiver: Option<watch::Receiver<super::message::Response>>,
+    /// The number of clients that have connected so far.
+    connections: watch::Sender<usize>,
 }
 
 impl CometInterface {
@@ -25,6 +27,7 @@ impl CometInterface {
             response_receiver: Some(response_receiver),
             state: Arc::new(RwLock::new(None)),
             features: None,
+            connections: watch::channel(0).0,
         })))
     }
 
@@ -88,14 +91,27 @@ impl CometInterface {
         self.0.read().await.state.read().await.is_some()
     }
 
+    /// Notified whenever a new client connects.
+    pub async fn subscribe_connections(&self) -> watch::Receiver<usize> {
+        self.0.read().await.connections.subscribe()
+    }
+
     pub(super) async fn set_state(&self, new_state: String) {
-        *self.0.read().await.state.write().await = Some(new_state);
+        let data = self.0.read().await;
+        *data.state.write().await = Some(new_state);
+        data.connections.send_modify(|connections| *connections += 1);
+        crate::metrics::METRICS
+            .comet_connected
+            .store(true, std::sync::atomic::Ordering::Relaxed);
     }
     pub(super) async fn set_disconnected(&self) {
         let mut interface = self.0.write().await;
         interface.message_sender.take();
         interface.response_receiver.take();
         *interface.state.write().await = None;
+        crate::metrics::METRICS
+            .comet_connected
+            .store(false, std::sync::atomic::Ordering::Relaxed);
     }
 
     pub(super) async fn set_features(&self, features: HashSet<Feature>) {
@@ -1,3 +1,4 @@
+use crate::log::{self, Target};
 use futures_util::{SinkExt, StreamExt};
 use ring::rand::SecureRandom;
 use std::sync::{Arc, Weak};
@@ -17,11 +18,13 @@ pub use message::{Message, Response, ResponseData};
 pub struct Server {
     server: tokio::net::TcpListener,
     error_reporter: mpsc::Sender<crate::bot::error::BotError>,
+    shutdown: crate::bot::shutdown::Shutdown,
+    /// Keeps the bot from exiting before the client is sent a close frame.
+    _shutdown_hold: crate::bot::shutdown::ShutdownHold,
     client: Option<Arc<Client>>,
     message_receiver: Arc<Mutex<mpsc::Receiver<message::TaggedMessage>>>,
     response_sender: Arc<watch::Sender<message::Response>>,
     interface: CometInterface,
-    options: crate::options::Options,
     streamer_username: String,
 }
 
@@ -66,14 +69,17 @@ impl Server {
         port: u16,
         streamer_username: S,
         error_reporter: mpsc::Sender<crate::bot::error::BotError>,
-        options: crate::options::Options,
+        shutdown: crate::bot::shutdown::Shutdown,
     ) -> std::io::Result<Self> {
         let server = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
 
-        options.debug(format!(
-            "Comet: Bound server to port {}",
-            server.local_addr().expect("Address should be set").port()
-        ));
+        log::debug(
+            Target::Comet,
+            format!(
+                "Bound server to port {}",
+                server.local_addr().expect("Address should be set").port()
+            ),
+        );
 
         // TODO: remove magic number
         let (message_sender, message_receiver) = mpsc::channel(16);
@@ -86,22 +92,27 @@ impl Server {
         Ok(Self {
             server,
             error_reporter,
+            _shutdown_hold: shutdown.hold(),
+            shutdown,
             client: None,
             interface: CometInterface::new(message_sender, response_receiver),
             message_receiver: Arc::new(Mutex::new(message_receiver)),
             response_sender: Arc::new(response_sender),
-            options,
             streamer_username: streamer_username.into(),
         })
     }
 
     pub async fn accept_connections(mut self) {
-        self.options.debug("Comet: Accepting connections!");
+        log::debug(Target::Comet, "Accepting connections!");
 
         loop {
-            let (connection, _) = match self.server.accept().await {
-                Ok(it) => it,
-                Err(err) => {
+            let (connection, _) = match self.shutdown.until_triggered(self.server.accept()).await {
+                Some(Ok(it)) => it,
+                None => {
+                    self.close().await;
+                    break;
+                }
+                Some(Err(err)) => {
                     let _ = self
                         .error_reporter
                         .send(crate::bot::error::BotError::IO(err))
@@ -124,13 +135,16 @@ impl Server {
                 }
             };
 
-            self.options.debug(format!(
-                "Comet: New connection @ {}",
-                socket
-                    .get_ref()
-                    .peer_addr()
-                    .expect("Connected socket should have peer address")
-            ));
+            log::debug(
+                Target::Comet,
+                format!(
+                    "New connection @ {}",
+                    socket
+                        .get_ref()
+                        .peer_addr()
+                        .expect("Connected socket should have peer address")
+                ),
+            );
 
             let (mut sender, receiver) = socket.split();
 
@@ -174,8 +188,7 @@ impl Server {
             }
             self.interface.set_state(state.clone()).await;
 
-            self.options
-                .debug(format!("Comet: Registered client (state: {state})"));
+            log::debug(Target::Comet, format!("Registered client (state: {state})"));
 
             let (close_sender, _) = broadcast::channel(1);
             let client = Arc::new(Client {
@@ -193,7 +206,6 @@ impl Server {
                 self.response_sender.clone(),
                 close_sender,
                 self.interface.clone(),
-                self.options,
                 self.streamer_username.clone(),
             ));
 
@@ -218,11 +230,31 @@ impl Server {
         }
     }
 
+    async fn close(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+
+        let _ = client
+            .sender
+            .lock()
+            .await
+            .send(SocketMessage::Close(Some(
+                tokio_tungstenite::tungstenite::protocol::CloseFrame {
+                    code: tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Away,
+                    reason: "Bot is shutting down".into(),
+                },
+            )))
+            .await;
+        log::debug(Target::Comet, "Closed the client connection");
+    }
+
     pub fn interface(&self) -> CometInterface {
         self.interface.clone()
     }
 
     // TODO: move these args into a struct
+    #[allow(clippy::too_many_arguments)]
     async fn handle_client(
         client: Weak<Client>,
         task_name: String,
@@ -231,7 +263,6 @@ impl Server {
         response_sender: Arc<watch::Sender<message::Response>>,
         close_sender: broadcast::Sender<()>,
         interface: CometInterface,
-        options: crate::options::Options,
         streamer_username: String,
     ) {
         tokio::join!(
@@ -239,37 +270,33 @@ impl Server {
                 client.clone(),
                 &task_name,
                 error_reporter.clone(),
-                close_sender.clone(),
-                options
+                close_sender.clone()
             ),
             Server::client_inbound(
                 client.clone(),
                 &task_name,
                 error_reporter.clone(),
                 close_sender.clone(),
-                response_sender,
-                options
+                response_sender
             ),
             Server::client_outbound(
                 client.clone(),
                 &task_name,
                 error_reporter.clone(),
                 close_sender.clone(),
-                message_receiver,
-                options
+                message_receiver
             ),
             Server::client_features(
                 &task_name,
                 error_reporter.clone(),
                 close_sender.clone(),
-                options,
                 interface.clone(),
                 streamer_username,
             ),
         );
 
         interface.set_disconnected().await;
-        options.debug(format!("Comet ({task_name}): Client threads closed!"))
+        log::debug(Target::Comet, format!("{task_name}: Client threads closed!"))
     }
 
     async fn client_outbound(
@@ -278,9 +305,11 @@ impl Server {
         error_reporter: mpsc::Sender<crate::bot::error::BotError>,
         close_sender: broadcast::Sender<()>,
         message_receiver: Arc<Mutex<mpsc::Receiver<message::TaggedMessage>>>,
-        options: crate::options::Options,
     ) {
-        options.debug(format!("Comet ({task_name}): Accepting outbound messages!"));
+        log::debug(
+            Target::Comet,
+            format!("{task_name}: Accepting outbound messages!"),
+        );
 
         loop {
             // FIXME: for some reason message_receiver.recv returns `None` after
@@ -291,10 +320,10 @@ impl Server {
 
             let Some(client) = client.upgrade() else { break; };
 
-            options.debug(format!(
-                "Comet ({task_name}): Outbound: {:?}",
-                message.message
-            ));
+            log::debug(
+                Target::Comet,
+                format!("{task_name}: Outbound: {:?}", message.message),
+            );
 
             let write_result = client
                 .sender
@@ -323,7 +352,7 @@ impl Server {
         }
 
         let _ = close_sender.send(());
-        println!("~out {task_name}")
+        log::trace(Target::Comet, format!("{task_name}: Outbound task finished"));
     }
 
     async fn client_inbound(
@@ -332,7 +361,6 @@ impl Server {
         error_reporter: mpsc::Sender<crate::bot::error::BotError>,
         close_sender: broadcast::Sender<()>,
         response_sender: Arc<watch::Sender<message::Response>>,
-        options: crate::options::Options,
     ) {
         loop {
             let Some(client) = client.upgrade() else { break; };
@@ -356,10 +384,10 @@ impl Server {
                                     break;
                                 }
 
-                                options.debug(format!(
-                                    "Comet ({task_name}): Inbound: {:?}",
-                                    response.data
-                                ));
+                                log::debug(
+                                    Target::Comet,
+                                    format!("{task_name}: Inbound: {:?}", response.data),
+                                );
 
                                 let _ = response_sender.send(response);
                             }
@@ -395,7 +423,10 @@ impl Server {
                         }
                     }
                     SocketMessage::Close(_) => {
-                        options.debug(format!("Comet ({task_name}): Client sent close message"));
+                        log::debug(
+                            Target::Comet,
+                            format!("{task_name}: Client sent close message"),
+                        );
                         let _ = client.close_sender.send(());
                         break;
                     }
@@ -425,7 +456,7 @@ impl Server {
             }; // semicolon is required for drop checker
         }
         let _ = close_sender.send(());
-        println!("~in {task_name}")
+        log::trace(Target::Comet, format!("{task_name}: Inbound task finished"));
     }
 
     async fn client_ping(
@@ -433,11 +464,10 @@ impl Server {
         task_name: &str,
         error_reporter: mpsc::Sender<crate::bot::error::BotError>,
         close_sender: broadcast::Sender<()>,
-        options: crate::options::Options,
     ) {
         let mut ping_interval = tokio::time::interval(std::time::Duration::from_secs(10));
 
-        options.debug(format!("Comet ({task_name}): Starting ping task"));
+        log::debug(Target::Comet, format!("{task_name}: Starting ping task"));
         loop {
             if wait_for(close_sender.subscribe(), ping_interval.tick())
                 .await
@@ -482,22 +512,20 @@ impl Server {
             }
         }
         let _ = close_sender.send(());
-        println!("~ping {task_name}")
+        log::trace(Target::Comet, format!("{task_name}: Ping task finished"));
     }
 
     async fn client_features(
         task_name: &str,
         error_reporter: mpsc::Sender<crate::bot::error::BotError>,
         close_sender: broadcast::Sender<()>,
-        options: crate::options::Options,
         interface: CometInterface,
         streamer_username: String,
     ) {
-        options.debug(format!("Comet ({task_name}): Initializing features..."));
+        log::debug(Target::Comet, format!("{task_name}: Initializing features..."));
 
         let Some(Some(features)) = wait_for(close_sender.subscribe(),feature::Feature::get_features(interface.clone())).await else { return };
 
-        println!("* init {features:?}");
         match feature::Feature::init(interface.clone(), features.clone(), streamer_username)
             .await
             .expect("Client should be connected")
@@ -513,10 +541,11 @@ impl Server {
             }
         }
 
-        options.debug(format!(
-            "Comet ({task_name}): Initialized features {features:?}"
-        ));
-        println!("~init {task_name}");
+        log::debug(
+            Target::Comet,
+            format!("{task_name}: Initialized features {features:?}"),
+        );
+        log::trace(Target::Comet, format!("{task_name}: Features task finished"));
     }
 
     fn create_state() -> Result<String, ring::error::Unspecified> {
@@ -1,111 +1,201 @@
+use super::cooldown::{self, Cooldown};
 use super::io;
+use super::permission::{Exceptions, Permission};
+use super::stream::{StreamVariable, TargetUser};
 use crate::bot::interface::BotInterface;
 use crate::chat::data::ChatMessage;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::Duration;
 
-// TODO: multiple chat messages?
-#[derive(Debug)]
+/// The longest `%{...}` expression, so that evaluating one stays cheap.
+const MAX_EXPRESSION_LENGTH: usize = 200;
+/// The longest `%wait`, so that a command doesn't hold on to its task forever.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CommandRules {
     pub body: Vec<CommandSection>,
     pub tags: HashSet<CommandTag>,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommandSection {
     Echo(String),
     ChatterName,
     WordIndex(usize),
-    Counter(String),
+    Counter(CounterName),
     AllWords,
+    /// `%if(condition){then}{otherwise}`, where `{otherwise}` is optional.
+    If {
+        condition: Box<CommandSection>,
+        then: Vec<CommandSection>,
+        otherwise: Vec<CommandSection>,
+    },
+    /// `%1|%name|{default}`: The first variable that has a value, or
+    /// `default` if none do.
+    Fallback {
+        options: Vec<CommandSection>,
+        default: Option<Vec<CommandSection>>,
+    },
+    /// `%choice{a}{b}{c}`: One of the options, picked at random.
+    Choice(Vec<Vec<CommandSection>>),
+    /// `%rand(min,max)`: A random integer from `min` to `max`, inclusive.
+    RandomInt {
+        min: i64,
+        max: i64,
+    },
+    /// `%chatter`: The display name of a random chatter who sent a message
+    /// recently.
+    RandomChatter,
+    /// `%game`, `%uptime`, etc.: A value from Helix.
+    Stream(StreamVariable),
+    /// `%touser`: The first argument without a leading `@`, or the caller's
+    /// display name.
+    ToUser,
+    /// `%{deaths * 2 + 1}`: An integer expression.
+    Expression(Expression),
+    /// `%;`: Ends a chat message, and starts the next one.
+    Split,
+    /// `%wait=2s`: Ends a chat message, and waits before starting the next
+    /// one.
+    Wait(Duration),
 }
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CommandTag {
     Reply,
-    Builtin,
-    Super,
+    Permission(Permission),
+    /// A login name that can always run the command.
+    Allow(String),
+    /// A login name that can never run the command, unless it's the
+    /// broadcaster.
+    Deny(String),
     Temporary,
-    CountInc(String),
-    CountDec(String),
-    CountReset(String),
+    CountInc(CounterName),
+    CountDec(CounterName),
+    CountReset(CounterName),
+    /// `&C:ADD=name:5`: Adds the value of an expression to a counter.
+    CountAdd(CounterName, Expression),
+    /// `&C:SET=name:%1`: Sets a counter to the value of an expression.
+    CountSet(CounterName, Expression),
     Alias,
+    Cooldown(Duration),
+    UserCooldown(Duration),
 }
 #[derive(Debug)]
 pub enum RulesError {
     BadVariable(String),
     BadTag(String),
+    InvalidDuration(String),
+    /// A `%if` or a fallback is missing a `)`, `{`, or `}`.
+    Expected(char),
+    /// A `%rand` range that isn't `min,max`.
+    InvalidRange(String),
+    InvalidExpression(String),
+    /// An expression longer than [`MAX_EXPRESSION_LENGTH`].
+    ExpressionTooLong,
+    /// A counter like `hugs@someone` whose owner isn't `caller` or `user`.
+    BadCounterOwner(String),
+    /// A `%wait` longer than [`MAX_WAIT`].
+    WaitTooLong,
+    /// A `%;` or `%wait` in an `&ALIAS` command, which can only send one
+    /// message.
+    AliasMessageBreak,
 }
 
-impl CommandRules {
-    pub fn parse(input: &str) -> Result<Self, RulesError> {
-        let mut output = CommandRules {
-            body: Vec::new(),
-            tags: HashSet::new(),
-        };
-        let mut current_word = String::new();
-        let mut escape = false;
+/// A [`RulesError`], and where it is in the input.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: RulesError,
+    /// The characters that the error is about, counted from 0. Empty if the
+    /// input ended too early.
+    pub span: Range<usize>,
+}
 
-        for chr in input.chars().chain([' ']) {
-            match chr {
-                ' ' => {
-                    if let Some(var_string) = current_word.strip_prefix('%') {
-                        output.body.push(CommandRules::var_from_string(var_string)?);
-                        current_word.clear();
-                    } else if let Some(tag_string) = current_word.strip_prefix('&') {
-                        output
-                            .tags
-                            .insert(CommandRules::tag_from_string(tag_string)?);
-                        current_word.clear();
-                    } else {
-                        output.body.push(CommandSection::Echo(
-                            if current_word.starts_with("\\%") || current_word.starts_with("\\&") {
-                                current_word.chars().skip(2).collect()
-                            } else {
-                                current_word
-                            } + " ",
-                        ));
-                        current_word = String::new();
-                    }
-                }
-                '%' if !escape => {
-                    output.body.push(CommandSection::Echo(current_word + ""));
-                    current_word = String::from("%");
-                }
-                '\\' => {
-                    if escape {
-                        current_word.push('\\');
-                    } else {
-                        escape = true;
-                        continue;
-                    }
-                }
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '=' | '_' | ':' if !escape => {
-                    current_word.push(chr)
-                }
-                misc_chr => {
-                    if let Some(var_string) = current_word.strip_prefix('%') {
-                        output.body.push(CommandRules::var_from_string(var_string)?);
-                        current_word.clear();
-                        current_word.push(misc_chr)
-                    } else {
-                        current_word.push(misc_chr)
-                    }
-                }
-            }
-            escape = false;
-        }
+/// A counter, or a chatter's value of a per-user counter, e.g. `hugs@caller`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CounterName {
+    pub name: String,
+    pub owner: Option<CounterOwner>,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CounterOwner {
+    /// `@caller`: The command caller.
+    Caller,
+    /// `@user`: The user named by the first argument, or the caller.
+    User,
+}
 
-        Ok(output)
-    }
+/// Integer arithmetic over counters, arguments, and numbers. Evaluating it
+/// has no value if an operation overflows or divides by zero.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expression {
+    Number(i64),
+    /// A counter's name, e.g. `deaths`.
+    Counter(String),
+    /// An argument that is a number, e.g. `%1`.
+    Argument(usize),
+    Negate(Box<Expression>),
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
 
-    #[must_use]
-    pub fn empty_builtin(is_super: bool) -> Self {
-        Self {
-            body: Vec::new(),
-            tags: HashSet::from_iter(if is_super {
-                vec![CommandTag::Builtin, CommandTag::Super]
-            } else {
-                vec![CommandTag::Builtin]
-            }),
+/// What variables are evaluated with.
+struct Context<'a> {
+    args: &'a [String],
+    chatter_name: Option<&'a str>,
+    counters: &'a HashMap<String, i64>,
+    user_counters: &'a HashMap<String, HashMap<String, super::UserCount>>,
+    owners: &'a CounterOwners,
+    /// The display names that `%chatter` picks from.
+    chatters: &'a [&'a str],
+    rng: &'a crate::random::Rng,
+    stream: &'a HashMap<StreamVariable, String>,
+}
+
+/// Who `@caller` and `@user` are, as a user id and a display name.
+#[derive(Debug, Default)]
+struct CounterOwners {
+    caller: Option<(String, String)>,
+    user: Option<(String, String)>,
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+    /// Where the first `%;` or `%wait` is.
+    first_break: Option<Range<usize>>,
+}
+
+impl CommandRules {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let mut tags = HashSet::new();
+        let mut parser = Parser {
+            input,
+            position: 0,
+            first_break: None,
+        };
+        let body = parser.sections(Some(&mut tags))?;
+        // an alias's messages are mocked, and only the last mocked message is
+        // sure to be handled
+        if let (true, Some(span)) = (tags.contains(&CommandTag::Alias), parser.first_break) {
+            return Err(ParseError {
+                kind: RulesError::AliasMessageBreak,
+                span,
+            });
         }
+
+        Ok(CommandRules { body, tags })
     }
 
     pub(super) async fn execute(
@@ -122,137 +212,309 @@ impl CommandRules {
         }
         let mut output_type = OutputType::Normal;
 
-        for tag in &self.tags {
-            // purposefully omitted a _ case to get get errors on adding a new CommandTag
-            match tag {
-                CommandTag::Reply => output_type = OutputType::Reply,
-                CommandTag::Builtin => (),
-                CommandTag::Super => (),
-                CommandTag::Temporary => (),
-                CommandTag::CountInc(name) => {
-                    if let Some(counter_value) = data.write().await.counters.get_mut(name) {
-                        *counter_value += 1;
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(msg, format!("Error: Counter {name:?} not found."))
+        let caller =
+            (!msg.user_id.is_empty()).then(|| (msg.user_id.clone(), msg.display_name.clone()));
+        let user = match args.first() {
+            Some(login) if self.uses_owner(CounterOwner::User) => {
+                let login = login.trim_start_matches('@');
+                let helix_cache = data.read().await.helix_cache.clone();
+                match helix_cache
+                    .user(Some(TargetUser::Login(login)), bot.helix_auth())
+                    .await
+                {
+                    Ok(Some(user)) => Some((user.id, user.display_name)),
+                    Ok(None) => {
+                        bot.reply(msg, format!("Error: User {login:?} not found."))
                             .await;
                         return;
                     }
-                }
-                CommandTag::CountDec(name) => {
-                    if let Some(counter_value) = data.write().await.counters.get_mut(name) {
-                        *counter_value -= 1;
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(msg, format!("Error: Counter {name:?} not found."))
+                    Err(err) => {
+                        bot.error(format!("Could not get user {login:?}: {err}"))
                             .await;
                         return;
                     }
                 }
-                CommandTag::CountReset(name) => {
-                    if let Some(counter_value) = data.write().await.counters.get_mut(name) {
-                        *counter_value = 0;
-                        io::spawn_io(data.clone(), io::refresh(data.clone()));
-                    } else {
-                        bot.reply(msg, format!("Error: Counter {name:?} not found."))
-                            .await;
+            }
+            Some(_) => None,
+            None => caller.clone(),
+        };
+        let owners = CounterOwners { caller, user };
+
+        for tag in &self.tags {
+            // purposefully omitted a _ case to get get errors on adding a new CommandTag
+            match tag {
+                CommandTag::Reply => output_type = OutputType::Reply,
+                CommandTag::Permission(_) => (),
+                CommandTag::Allow(_) => (),
+                CommandTag::Deny(_) => (),
+                CommandTag::Temporary => (),
+                CommandTag::CountInc(_)
+                | CommandTag::CountDec(_)
+                | CommandTag::CountReset(_)
+                | CommandTag::CountAdd(..)
+                | CommandTag::CountSet(..) => {
+                    let result = change_counter(tag, &args, &owners, &mut *data.write().await);
+                    if let Err(err) = result {
+                        bot.reply(msg, format!("Error: {err}")).await;
                         return;
                     }
+                    io::spawn_io(data.clone(), io::refresh(data.clone()));
                 }
                 CommandTag::Alias => output_type = OutputType::Alias,
+                CommandTag::Cooldown(_) => (),
+                CommandTag::UserCooldown(_) => (),
             }
         }
 
-        let mut chatter_name_cache: Option<String> = None;
-        let mut message = Vec::new();
+        let chatter_name = if any_section(&self.body, &|section| {
+            matches!(section, CommandSection::ChatterName)
+                || (args.is_empty() && matches!(section, CommandSection::ToUser))
+        }) {
+            let Ok(Some(user)) = crate::twitch::user_from_id(&msg.user_id, bot.helix_auth()).await
+            else {
+                bot.error(format!("Could not get username from id {:?}", msg.user_id))
+                    .await;
+                return;
+            };
+            Some(user.display_name)
+        } else {
+            None
+        };
 
-        for section in &self.body {
-            message.push(match section {
-                CommandSection::Echo(text) => String::from(text),
-                CommandSection::ChatterName => {
-                    if let Some(chatter_name) = &chatter_name_cache {
-                        chatter_name.clone()
-                    } else {
-                        let Ok(Some(user)) = crate::twitch::user_from_id(&msg.user_id, bot.helix_auth()).await else {
-                            bot.error(format!("Could not get username from id {:?}", msg.user_id)).await;
-                            return;
-                        };
-                        chatter_name_cache = Some(user.display_name.clone());
-                        user.display_name
+        let stream_variables: Vec<_> = StreamVariable::ALL
+            .into_iter()
+            .filter(|variable| {
+                any_section(&self.body, &|section| {
+                    *section == CommandSection::Stream(*variable)
+                })
+            })
+            .collect();
+        let stream = if stream_variables.is_empty() {
+            HashMap::new()
+        } else {
+            let target = match args.first() {
+                Some(login) => Some(TargetUser::Login(login.trim_start_matches('@'))),
+                None if !msg.user_id.is_empty() => Some(TargetUser::Id(&msg.user_id)),
+                None => None,
+            };
+            let helix_cache = data.read().await.helix_cache.clone();
+            helix_cache.values(&stream_variables, target, bot).await
+        };
+
+        let data_read = data.read().await;
+        let messages = render_messages(
+            &self.body,
+            &Context {
+                args: &args,
+                chatter_name: chatter_name.as_deref(),
+                counters: &data_read.counters,
+                user_counters: &data_read.user_counters,
+                owners: &owners,
+                chatters: &data_read.present_chatters(),
+                rng: &data_read.rng,
+                stream: &stream,
+            },
+        );
+        drop(data_read);
+
+        let has_waits = messages.iter().any(|(wait, _)| !wait.is_zero());
+        let send = {
+            let (bot, msg) = (bot.clone(), msg.clone());
+            let shutdown = bot.shutdown_coordinator().clone();
+            async move {
+                for (wait, message) in messages {
+                    // once the bot is shutting down, the rest are sent right
+                    // away, before it leaves chat
+                    if !wait.is_zero() {
+                        let _ = shutdown.until_triggered(tokio::time::sleep(wait)).await;
+                    }
+                    match output_type {
+                        OutputType::Normal => bot.say(message).await,
+                        OutputType::Reply => bot.reply(&msg, message).await,
+                        OutputType::Alias => bot.mock_message(&msg, message),
                     }
                 }
-                CommandSection::WordIndex(index) => {
-                    String::from(args.get(*index).unwrap_or(&index.to_string()))
-                },
-                CommandSection::Counter(name) => if let Some(counter_value) = data.read().await.counters.get(name) {
-                    counter_value.to_string()
-                } else {
-                    format!("%counter={name}")
-                },
-                CommandSection::AllWords => args.join(" "),
-            })
+            }
+        };
+        // waiting would hold up the chat handler that called the command
+        if has_waits {
+            let hold = bot.shutdown_coordinator().hold();
+            tokio::spawn(async move {
+                send.await;
+                drop(hold);
+            });
+        } else {
+            send.await;
         }
+    }
 
-        let message = message.join("");
-
-        match output_type {
-            OutputType::Normal => bot.say(message).await,
-            OutputType::Reply => bot.reply(msg, message).await,
-            OutputType::Alias => bot.mock_message(msg, message),
+    pub async fn can_run(&self, msg: &ChatMessage, bot: &BotInterface) -> bool {
+        match self.exceptions().check(msg) {
+            Some(can_run) => can_run,
+            None => self.permission().allows(msg, bot).await,
         }
     }
+    /// Like [`CommandRules::can_run`], for a chatter known to have
+    /// `permission`.
+    #[must_use]
+    pub fn can_run_as(&self, msg: &ChatMessage, permission: Permission) -> bool {
+        self.exceptions()
+            .check(msg)
+            .unwrap_or(permission >= self.permission())
+    }
 
+    /// The lowest permission needed to run the command, set by tags like
+    /// `&SUB` and `&SUPER`.
+    #[must_use]
+    pub fn permission(&self) -> Permission {
+        self.tags
+            .iter()
+            .filter_map(|tag| match tag {
+                CommandTag::Permission(permission) => Some(*permission),
+                _ => None,
+            })
+            .max()
+            .unwrap_or_default()
+    }
+    pub fn set_permission(&mut self, permission: Permission) {
+        self.tags
+            .retain(|tag| !matches!(tag, CommandTag::Permission(_)));
+        if permission != Permission::Everyone {
+            self.tags.insert(CommandTag::Permission(permission));
+        }
+    }
+    /// Allows (`Some(true)`) or denies (`Some(false)`) `login`, or removes
+    /// them from both lists (`None`).
+    pub fn set_exception(&mut self, login: &str, is_allowed: Option<bool>) {
+        let login = login.to_lowercase();
+        self.tags.retain(|tag| match tag {
+            CommandTag::Allow(existing) | CommandTag::Deny(existing) => existing != &login,
+            _ => true,
+        });
+        match is_allowed {
+            Some(true) => self.tags.insert(CommandTag::Allow(login)),
+            Some(false) => self.tags.insert(CommandTag::Deny(login)),
+            None => false,
+        };
+    }
+    /// The users set by `&ALLOW` and `&DENY`.
     #[must_use]
-    pub fn can_run(&self, msg: &ChatMessage, _bot: &BotInterface) -> bool {
-        if self.tags.contains(&CommandTag::Super) && !msg.user_is_super() {
-            return false;
+    pub fn exceptions(&self) -> Exceptions {
+        let mut exceptions = Exceptions::default();
+        for tag in &self.tags {
+            match tag {
+                CommandTag::Allow(login) => exceptions.allow.push(login.clone()),
+                CommandTag::Deny(login) => exceptions.deny.push(login.clone()),
+                _ => (),
+            }
         }
-        true
+        exceptions
     }
 
+    /// The command in its raw form. It's canonical: parsing it gives back an
+    /// equal command, and equal commands give the same string, so tags are
+    /// sorted.
     #[must_use]
     pub fn as_words_string(&self) -> String {
-        self.tags
+        let mut tags: Vec<String> = self
+            .tags
             .iter()
-            .filter_map(|tag| match tag {
-                CommandTag::Reply => Some(String::from("&REPLY")),
-                CommandTag::Builtin => None,
-                CommandTag::Super => Some(String::from("&SUPER")),
-                CommandTag::Temporary => Some(String::from("&TEMP")),
-                CommandTag::CountInc(name) => Some(format!("&C:INC={name}")),
-                CommandTag::CountDec(name) => Some(format!("&C:DEC={name}")),
-                CommandTag::CountReset(name) => Some(format!("&C:ZERO={name}")),
-                CommandTag::Alias => Some(String::from("&ALIAS")),
+            .map(|tag| match tag {
+                CommandTag::Reply => String::from("&REPLY"),
+                CommandTag::Permission(permission) => format!("&{}", permission.tag()),
+                CommandTag::Allow(login) => format!("&ALLOW={login}"),
+                CommandTag::Deny(login) => format!("&DENY={login}"),
+                CommandTag::Temporary => String::from("&TEMP"),
+                CommandTag::CountInc(name) => format!("&C:INC={name}"),
+                CommandTag::CountDec(name) => format!("&C:DEC={name}"),
+                CommandTag::CountReset(name) => format!("&C:ZERO={name}"),
+                CommandTag::CountAdd(name, expression) => format!("&C:ADD={name}:{expression}"),
+                CommandTag::CountSet(name, expression) => format!("&C:SET={name}:{expression}"),
+                CommandTag::Alias => String::from("&ALIAS"),
+                CommandTag::Cooldown(duration) => format!("&CD={}s", duration.as_secs()),
+                CommandTag::UserCooldown(duration) => format!("&UCD={}s", duration.as_secs()),
             })
-            .map(|tag| tag + " ")
-            .chain(self.body.iter().map(|sec| match sec {
-                CommandSection::Echo(txt) => String::from(txt),
-                CommandSection::ChatterName => String::from("%name"),
-                CommandSection::WordIndex(idx) => format!("%{idx}"),
-                CommandSection::Counter(name) => format!("%counter={name}"),
-                CommandSection::AllWords => String::from("%@"),
-            }))
-            .collect()
+            .collect();
+        tags.sort_unstable();
+
+        let body = sections_as_words_string(&self.body, false);
+        if tags.is_empty() {
+            body
+        } else if body.is_empty() {
+            tags.join(" ")
+        } else {
+            // the space after the last tag is part of it
+            format!("{} {body}", tags.join(" "))
+        }
     }
 
+    /// The cooldowns set by `&CD` and `&UCD`.
     #[must_use]
-    pub fn is_builtin(&self) -> bool {
-        self.tags.contains(&CommandTag::Builtin)
+    pub fn cooldown(&self) -> Cooldown {
+        let mut cooldown = Cooldown::default();
+        for tag in &self.tags {
+            match tag {
+                CommandTag::Cooldown(duration) => cooldown.global = Some(*duration),
+                CommandTag::UserCooldown(duration) => cooldown.user = Some(*duration),
+                _ => (),
+            }
+        }
+        cooldown
     }
+
     #[must_use]
     pub fn is_temporary(&self) -> bool {
         self.tags.contains(&CommandTag::Temporary)
     }
 
+    /// Whether any tag or variable uses a per-user counter of `owner`.
+    fn uses_owner(&self, owner: CounterOwner) -> bool {
+        self.tags
+            .iter()
+            .filter_map(CommandTag::counter)
+            .any(|counter| counter.owner == Some(owner))
+            || any_section(&self.body, &|section| match section {
+                CommandSection::Counter(counter) => counter.owner == Some(owner),
+                _ => false,
+            })
+    }
+
+    /// The first part of the command that acts on the chatter who called it,
+    /// e.g. `&REPLY`, or `None` if it has none. Timers have no caller, so they
+    /// can't use these.
+    pub(super) fn caller_only_part(&self) -> Option<&'static str> {
+        if self.tags.contains(&CommandTag::Reply) {
+            Some("&REPLY")
+        } else if self.tags.contains(&CommandTag::Alias) {
+            Some("&ALIAS")
+        } else if self.uses_owner(CounterOwner::Caller) || self.uses_owner(CounterOwner::User) {
+            Some("per-user counters")
+        } else if any_section(&self.body, &|section| {
+            matches!(section, CommandSection::ChatterName)
+        }) {
+            Some("%name")
+        } else if any_section(&self.body, &|section| {
+            matches!(section, CommandSection::ToUser)
+        }) {
+            Some("%touser")
+        } else {
+            None
+        }
+    }
+
     fn var_from_string(input: &str) -> Result<CommandSection, RulesError> {
         Ok(match input {
             "name" => CommandSection::ChatterName,
             "@" => CommandSection::AllWords,
+            "chatter" => CommandSection::RandomChatter,
+            "touser" => CommandSection::ToUser,
             input => {
-                if let Ok(idx) = input.parse() {
+                if let Some(variable) = StreamVariable::from_name(input) {
+                    CommandSection::Stream(variable)
+                } else if let Ok(idx) = input.parse() {
                     CommandSection::WordIndex(idx)
                 } else if let Some(counter_name) = input.strip_prefix("counter=") {
-                    CommandSection::Counter(String::from(counter_name))
+                    CommandSection::Counter(CounterName::parse(counter_name)?)
                 } else {
                     return Err(RulesError::BadVariable(String::from(input)));
                 }
@@ -262,16 +524,39 @@ impl CommandRules {
     fn tag_from_string(input: &str) -> Result<CommandTag, RulesError> {
         Ok(match input {
             "REPLY" => CommandTag::Reply,
-            "SUPER" => CommandTag::Super,
             "TEMP" => CommandTag::Temporary,
             "ALIAS" => CommandTag::Alias,
             input => {
-                if let Some((tag, val)) = input.split_once('=') {
+                if let Some(permission) = Permission::from_tag(input) {
+                    CommandTag::Permission(permission)
+                } else if let Some((tag, val)) = input.split_once('=') {
                     let val = String::from(val);
                     match tag {
-                        "C:INC" => CommandTag::CountInc(val),
-                        "C:DEC" => CommandTag::CountDec(val),
-                        "C:ZERO" => CommandTag::CountReset(val),
+                        "C:INC" => CommandTag::CountInc(CounterName::parse(&val)?),
+                        "C:DEC" => CommandTag::CountDec(CounterName::parse(&val)?),
+                        "C:ZERO" => CommandTag::CountReset(CounterName::parse(&val)?),
+                        "C:ADD" | "C:SET" => {
+                            let Some((name, expression)) = val.rsplit_once(':') else {
+                                return Err(RulesError::BadTag(format!("{tag}={val}")));
+                            };
+                            let name = CounterName::parse(name)?;
+                            let expression = Expression::parse(expression)?;
+                            if tag == "C:ADD" {
+                                CommandTag::CountAdd(name, expression)
+                            } else {
+                                CommandTag::CountSet(name, expression)
+                            }
+                        }
+                        "ALLOW" => CommandTag::Allow(val.to_lowercase()),
+                        "DENY" => CommandTag::Deny(val.to_lowercase()),
+                        "CD" => CommandTag::Cooldown(
+                            cooldown::parse_duration(&val)
+                                .ok_or(RulesError::InvalidDuration(val))?,
+                        ),
+                        "UCD" => CommandTag::UserCooldown(
+                            cooldown::parse_duration(&val)
+                                .ok_or(RulesError::InvalidDuration(val))?,
+                        ),
                         input => return Err(RulesError::BadTag(String::from(input))),
                     }
                 } else {
@@ -282,6 +567,724 @@ impl CommandRules {
     }
 }
 
+impl CommandSection {
+    /// The section's output, or `None` if it's a variable without a value,
+    /// e.g. an argument that wasn't given.
+    fn value(&self, context: &Context) -> Option<String> {
+        match self {
+            CommandSection::Echo(text) => Some(text.clone()),
+            CommandSection::ChatterName => context.chatter_name.map(String::from),
+            CommandSection::WordIndex(index) => context.args.get(*index).cloned(),
+            CommandSection::Counter(counter) => {
+                let Some(owner) = counter.owner else {
+                    return context.counters.get(&counter.name).map(i64::to_string);
+                };
+                let (owner_id, _) = context.owners.get(owner)?;
+                // per-user values start at 0, once the counter exists
+                context.counters.get(&counter.name)?;
+                let value = context
+                    .user_counters
+                    .get(&counter.name)
+                    .and_then(|values| values.get(owner_id))
+                    .map_or(0, |count| count.value);
+                Some(value.to_string())
+            }
+            CommandSection::AllWords => (!context.args.is_empty()).then(|| context.args.join(" ")),
+            CommandSection::If {
+                condition,
+                then,
+                otherwise,
+            } => Some(render(
+                if condition.is_true(context) {
+                    then
+                } else {
+                    otherwise
+                },
+                context,
+            )),
+            CommandSection::Fallback { options, default } => options
+                .iter()
+                .find_map(|option| option.value(context))
+                .or_else(|| default.as_ref().map(|default| render(default, context))),
+            CommandSection::Choice(options) => context
+                .rng
+                .choose(options)
+                .map(|option| render(option, context)),
+            CommandSection::RandomInt { min, max } => {
+                Some(context.rng.range(*min, *max).to_string())
+            }
+            CommandSection::RandomChatter => context
+                .rng
+                .choose(context.chatters)
+                .map(|chatter| String::from(*chatter)),
+            CommandSection::Stream(variable) => context.stream.get(variable).cloned(),
+            CommandSection::ToUser => context
+                .args
+                .first()
+                .map(|login| String::from(login.trim_start_matches('@')))
+                .or_else(|| context.chatter_name.map(String::from)),
+            CommandSection::Expression(expression) => expression
+                .evaluate(context.args, context.counters)
+                .map(|value| value.to_string()),
+            CommandSection::Split | CommandSection::Wait(_) => Some(String::new()),
+        }
+    }
+
+    /// Whether the section has a value that isn't empty or `0`.
+    fn is_true(&self, context: &Context) -> bool {
+        self.value(context)
+            .is_some_and(|value| !value.is_empty() && value != "0")
+    }
+
+    fn is_variable(&self) -> bool {
+        !matches!(self, CommandSection::Echo(_))
+    }
+
+    /// Whether the section's raw form ends with a counter, which an `@` after
+    /// it would give an owner.
+    fn ends_with_counter(&self) -> bool {
+        match self {
+            CommandSection::Counter(_) => true,
+            CommandSection::Fallback {
+                options,
+                default: None,
+            } => options
+                .last()
+                .is_some_and(CommandSection::ends_with_counter),
+            _ => false,
+        }
+    }
+
+    /// The variable in its raw form, without the leading `%`.
+    fn variable_name(&self) -> String {
+        match self {
+            CommandSection::Echo(text) => escape(text, false, false),
+            CommandSection::ChatterName => String::from("name"),
+            CommandSection::WordIndex(index) => index.to_string(),
+            CommandSection::Counter(counter) => format!("counter={counter}"),
+            CommandSection::AllWords => String::from("@"),
+            CommandSection::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let mut name = format!(
+                    "if({}){{{}}}",
+                    condition.variable_name(),
+                    sections_as_words_string(then, true)
+                );
+                if !otherwise.is_empty() {
+                    name += &format!("{{{}}}", sections_as_words_string(otherwise, true));
+                }
+                name
+            }
+            CommandSection::Fallback { options, default } => {
+                let mut name = options
+                    .iter()
+                    .map(CommandSection::variable_name)
+                    .collect::<Vec<_>>()
+                    .join("|%");
+                if let Some(default) = default {
+                    name += &format!("|{{{}}}", sections_as_words_string(default, true));
+                }
+                name
+            }
+            CommandSection::Choice(options) => options
+                .iter()
+                .map(|option| format!("{{{}}}", sections_as_words_string(option, true)))
+                .fold(String::from("choice"), |name, option| name + &option),
+            CommandSection::RandomInt { min, max } => format!("rand({min},{max})"),
+            CommandSection::RandomChatter => String::from("chatter"),
+            CommandSection::Stream(variable) => String::from(variable.name()),
+            CommandSection::ToUser => String::from("touser"),
+            CommandSection::Expression(expression) => format!("{{{expression}}}"),
+            CommandSection::Split => String::from(";"),
+            CommandSection::Wait(wait) => format!("wait={}", cooldown::format_duration(*wait)),
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+    fn next(&mut self) -> Option<char> {
+        let chr = self.peek()?;
+        self.position += chr.len_utf8();
+        Some(chr)
+    }
+    fn eat(&mut self, prefix: &str) -> bool {
+        let is_next = self.input[self.position..].starts_with(prefix);
+        if is_next {
+            self.position += prefix.len();
+        }
+        is_next
+    }
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while self.peek().is_some_and(&f) {
+            self.next();
+        }
+        &self.input[start..self.position]
+    }
+    /// `kind`, about the input from the byte `start` up to the current
+    /// position.
+    fn error(&self, start: usize, kind: RulesError) -> ParseError {
+        ParseError {
+            kind,
+            span: self.span(start),
+        }
+    }
+    /// The characters from the byte `start` up to the current position.
+    fn span(&self, start: usize) -> Range<usize> {
+        let characters = |end: usize| self.input[..end].chars().count();
+        characters(start)..characters(self.position)
+    }
+
+    /// Parses sections until the end of the input, or until the `}` that
+    /// closes a group. Tags are only parsed outside of groups, i.e. when
+    /// `tags` is `Some`.
+    fn sections(
+        &mut self,
+        mut tags: Option<&mut HashSet<CommandTag>>,
+    ) -> Result<Vec<CommandSection>, ParseError> {
+        let in_group = tags.is_none();
+        // the `{` that opens the group
+        let start = self.position.saturating_sub(1);
+        let mut sections = Vec::new();
+        let mut text = String::new();
+        let mut is_word_start = true;
+
+        loop {
+            let Some(chr) = self.peek() else {
+                if in_group {
+                    return Err(self.error(start, RulesError::Expected('}')));
+                }
+                break;
+            };
+
+            match chr {
+                '}' if in_group => {
+                    self.next();
+                    break;
+                }
+                '\\' => {
+                    self.next();
+                    text.push(self.next().unwrap_or('\\'));
+                }
+                '&' if is_word_start => {
+                    let Some(tags) = tags.as_deref_mut() else {
+                        self.next();
+                        text.push(chr);
+                        is_word_start = false;
+                        continue;
+                    };
+                    let start = self.position;
+                    self.next();
+                    let tag = CommandRules::tag_from_string(self.take_while(|chr| chr != ' '))
+                        .map_err(|kind| self.error(start, kind))?;
+                    tags.insert(tag);
+                    // the space after a tag is part of it
+                    self.eat(" ");
+                    continue;
+                }
+                '%' => {
+                    let start = self.position;
+                    self.next();
+                    let variable = match self.message_break(start, in_group)? {
+                        Some(message_break) => Some(message_break),
+                        None => self.variable(start)?,
+                    };
+                    if let Some(variable) = variable {
+                        if !text.is_empty() {
+                            sections.push(CommandSection::Echo(std::mem::take(&mut text)));
+                        }
+                        sections.push(variable);
+                    } else {
+                        text.push('%');
+                    }
+                }
+                chr => {
+                    self.next();
+                    text.push(chr);
+                }
+            }
+            is_word_start = chr == ' ';
+        }
+
+        if !text.is_empty() {
+            sections.push(CommandSection::Echo(text));
+        }
+        Ok(sections)
+    }
+
+    /// Parses a `;` or `wait=<duration>` after the `%` at `start`. They
+    /// aren't text, so they can't be in groups.
+    fn message_break(
+        &mut self,
+        start: usize,
+        in_group: bool,
+    ) -> Result<Option<CommandSection>, ParseError> {
+        if in_group {
+            return Ok(None);
+        }
+        let message_break = if self.eat(";") {
+            CommandSection::Split
+        } else if self.eat("wait=") {
+            let duration = self.take_while(is_variable_char);
+            let Some(wait) = cooldown::parse_duration(duration) else {
+                return Err(self.error(start, RulesError::InvalidDuration(String::from(duration))));
+            };
+            if wait > MAX_WAIT {
+                return Err(self.error(start, RulesError::WaitTooLong));
+            }
+            CommandSection::Wait(wait)
+        } else {
+            return Ok(None);
+        };
+        if self.first_break.is_none() {
+            self.first_break = Some(self.span(start));
+        }
+        Ok(Some(message_break))
+    }
+
+    /// Parses what follows the `%` at `start`, or returns `None` if it isn't
+    /// a variable, e.g. the `%` in `100%`.
+    fn variable(&mut self, start: usize) -> Result<Option<CommandSection>, ParseError> {
+        if self.eat("{") {
+            let expression = Expression::parse(self.take_while(|chr| chr != '}'))
+                .map_err(|kind| self.error(start, kind))?;
+            if !self.eat("}") {
+                return Err(self.error(start, RulesError::Expected('}')));
+            }
+            return Ok(Some(CommandSection::Expression(expression)));
+        }
+        if self.eat("if(") {
+            let name = self.take_while(|chr| chr != ')');
+            let condition = CommandRules::var_from_string(name.strip_prefix('%').unwrap_or(name))
+                .map_err(|kind| self.error(start, kind))?;
+            if !self.eat(")") {
+                return Err(self.error(start, RulesError::Expected(')')));
+            }
+            if !self.eat("{") {
+                return Err(self.error(start, RulesError::Expected('{')));
+            }
+            let then = self.sections(None)?;
+            let otherwise = if self.eat("{") {
+                self.sections(None)?
+            } else {
+                Vec::new()
+            };
+
+            return Ok(Some(CommandSection::If {
+                condition: Box::new(condition),
+                then,
+                otherwise,
+            }));
+        }
+
+        if self.eat("choice{") {
+            let mut options = vec![self.sections(None)?];
+            while self.eat("{") {
+                options.push(self.sections(None)?);
+            }
+            return Ok(Some(CommandSection::Choice(options)));
+        }
+        if self.eat("rand(") {
+            let range = self.take_while(|chr| chr != ')');
+            let Some((min, max)) = range
+                .split_once(',')
+                .and_then(|(min, max)| Some((min.trim().parse().ok()?, max.trim().parse().ok()?)))
+                .filter(|(min, max)| min <= max)
+            else {
+                return Err(self.error(start, RulesError::InvalidRange(String::from(range))));
+            };
+            if !self.eat(")") {
+                return Err(self.error(start, RulesError::Expected(')')));
+            }
+            return Ok(Some(CommandSection::RandomInt { min, max }));
+        }
+
+        let Some(first) = self.simple_variable(start)? else {
+            return Ok(None);
+        };
+        let mut options = vec![first];
+        let mut default = None;
+        loop {
+            let rest = &self.input[self.position..];
+            if rest.starts_with("|%")
+                && rest[2..].starts_with(|chr| is_variable_char(chr) || chr == '@')
+            {
+                let start = self.position + 1;
+                self.eat("|%");
+                if let Some(option) = self.simple_variable(start)? {
+                    options.push(option);
+                }
+            } else if self.eat("|{") {
+                default = Some(self.sections(None)?);
+                break;
+            } else {
+                break;
+            }
+        }
+
+        Ok(Some(if options.len() == 1 && default.is_none() {
+            options.remove(0)
+        } else {
+            CommandSection::Fallback { options, default }
+        }))
+    }
+
+    /// Parses a variable name like `1` or `counter=deaths`, after the `%` at
+    /// `start`.
+    fn simple_variable(&mut self, start: usize) -> Result<Option<CommandSection>, ParseError> {
+        if self.eat("@") {
+            return Ok(Some(CommandSection::AllWords));
+        }
+        let name_start = self.position;
+        self.take_while(is_variable_char);
+        if self.input[name_start..self.position].starts_with("counter=") {
+            // anything else after the `@` is text, e.g. an email address
+            for owner in ["@caller", "@user"] {
+                let rest = &self.input[self.position..];
+                if rest.starts_with(owner) && !rest[owner.len()..].starts_with(is_variable_char) {
+                    self.position += owner.len();
+                    break;
+                }
+            }
+        }
+        let name = &self.input[name_start..self.position];
+        if name.is_empty() {
+            return Ok(None);
+        }
+        CommandRules::var_from_string(name)
+            .map(Some)
+            .map_err(|kind| self.error(start, kind))
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(|chr| chr == ' ');
+    }
+    /// Parses a whole expression, e.g. `deaths * 2 + 1`.
+    fn expression(&mut self) -> Option<Expression> {
+        let expression = self.sum()?;
+        self.skip_spaces();
+        self.peek().is_none().then_some(expression)
+    }
+    fn sum(&mut self) -> Option<Expression> {
+        let mut left = self.product()?;
+        loop {
+            self.skip_spaces();
+            let operator = if self.eat("+") {
+                Operator::Add
+            } else if self.eat("-") {
+                Operator::Subtract
+            } else {
+                return Some(left);
+            };
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(self.product()?),
+            };
+        }
+    }
+    fn product(&mut self) -> Option<Expression> {
+        let mut left = self.operand()?;
+        loop {
+            self.skip_spaces();
+            let operator = if self.eat("*") {
+                Operator::Multiply
+            } else if self.eat("/") {
+                Operator::Divide
+            } else if self.eat("%") {
+                Operator::Remainder
+            } else {
+                return Some(left);
+            };
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(self.operand()?),
+            };
+        }
+    }
+    fn operand(&mut self) -> Option<Expression> {
+        self.skip_spaces();
+        if self.eat("-") {
+            return Some(Expression::Negate(Box::new(self.operand()?)));
+        }
+        if self.eat("(") {
+            let expression = self.sum()?;
+            self.skip_spaces();
+            return self.eat(")").then_some(expression);
+        }
+        if self.eat("%") {
+            let index = self.take_while(|chr| chr.is_ascii_digit());
+            return Some(Expression::Argument(index.parse().ok()?));
+        }
+
+        let chr = self.peek()?;
+        if chr.is_ascii_digit() {
+            let number = self.take_while(|chr| chr.is_ascii_digit());
+            Some(Expression::Number(number.parse().ok()?))
+        } else if chr.is_ascii_alphabetic() || chr == '_' {
+            let name = self.take_while(|chr| chr.is_ascii_alphanumeric() || chr == '_');
+            Some(Expression::Counter(String::from(name)))
+        } else {
+            None
+        }
+    }
+}
+
+impl CounterName {
+    /// Parses `name`, `name@caller`, or `name@user`.
+    pub fn parse(input: &str) -> Result<Self, RulesError> {
+        let Some((name, owner)) = input.split_once('@') else {
+            return Ok(CounterName {
+                name: String::from(input),
+                owner: None,
+            });
+        };
+        let owner = match owner {
+            "caller" => CounterOwner::Caller,
+            "user" => CounterOwner::User,
+            owner => return Err(RulesError::BadCounterOwner(String::from(owner))),
+        };
+        Ok(CounterName {
+            name: String::from(name),
+            owner: Some(owner),
+        })
+    }
+}
+
+impl CommandTag {
+    /// The counter that the tag changes, if it's a counter tag.
+    fn counter(&self) -> Option<&CounterName> {
+        match self {
+            CommandTag::CountInc(counter)
+            | CommandTag::CountDec(counter)
+            | CommandTag::CountReset(counter)
+            | CommandTag::CountAdd(counter, _)
+            | CommandTag::CountSet(counter, _) => Some(counter),
+            _ => None,
+        }
+    }
+}
+
+impl CounterOwners {
+    fn get(&self, owner: CounterOwner) -> Option<&(String, String)> {
+        match owner {
+            CounterOwner::Caller => self.caller.as_ref(),
+            CounterOwner::User => self.user.as_ref(),
+        }
+    }
+}
+
+/// Applies a counter tag, or returns why it couldn't be applied.
+fn change_counter(
+    tag: &CommandTag,
+    args: &[String],
+    owners: &CounterOwners,
+    data: &mut super::StoreData,
+) -> Result<(), String> {
+    let Some(counter) = tag.counter() else {
+        return Ok(());
+    };
+    let owner = match counter.owner {
+        Some(owner) => Some(
+            owners
+                .get(owner)
+                .ok_or_else(|| format!("Nobody to count \"{counter}\" for."))?,
+        ),
+        None => None,
+    };
+    let change = match tag {
+        CommandTag::CountAdd(_, expression) | CommandTag::CountSet(_, expression) => {
+            Some(expression.evaluate(args, &data.counters).ok_or_else(|| {
+                format!("Could not evaluate {expression} for counter \"{counter}\".")
+            })?)
+        }
+        _ => None,
+    };
+
+    let value = data
+        .counter_mut(&counter.name, owner)
+        .ok_or_else(|| format!("Counter {:?} not found.", counter.name))?;
+    *value = match tag {
+        CommandTag::CountInc(_) => value.checked_add(1),
+        CommandTag::CountDec(_) => value.checked_sub(1),
+        CommandTag::CountAdd(..) => change.and_then(|change| value.checked_add(change)),
+        CommandTag::CountSet(..) => change,
+        _ => Some(0),
+    }
+    .ok_or_else(|| format!("Counter \"{counter}\" is out of range."))?;
+    Ok(())
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Self, RulesError> {
+        if input.len() > MAX_EXPRESSION_LENGTH {
+            return Err(RulesError::ExpressionTooLong);
+        }
+        Parser {
+            input,
+            position: 0,
+            first_break: None,
+        }
+        .expression()
+        .ok_or_else(|| RulesError::InvalidExpression(String::from(input)))
+    }
+
+    /// The expression's value, or `None` if a counter or argument is missing,
+    /// or if the arithmetic overflows or divides by zero.
+    #[must_use]
+    pub fn evaluate(&self, args: &[String], counters: &HashMap<String, i64>) -> Option<i64> {
+        match self {
+            Expression::Number(number) => Some(*number),
+            Expression::Counter(name) => counters.get(name).copied(),
+            Expression::Argument(index) => args.get(*index)?.parse().ok(),
+            Expression::Negate(operand) => operand.evaluate(args, counters)?.checked_neg(),
+            Expression::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let left = left.evaluate(args, counters)?;
+                let right = right.evaluate(args, counters)?;
+                match operator {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Subtract => left.checked_sub(right),
+                    Operator::Multiply => left.checked_mul(right),
+                    Operator::Divide => left.checked_div(right),
+                    Operator::Remainder => left.checked_rem(right),
+                }
+            }
+        }
+    }
+}
+
+impl Operator {
+    fn symbol(self) -> char {
+        match self {
+            Operator::Add => '+',
+            Operator::Subtract => '-',
+            Operator::Multiply => '*',
+            Operator::Divide => '/',
+            Operator::Remainder => '%',
+        }
+    }
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 0,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 1,
+        }
+    }
+}
+
+fn is_variable_char(chr: char) -> bool {
+    matches!(chr, 'a'..='z' | 'A'..='Z' | '0'..='9' | '=' | '_' | ':')
+}
+
+/// Whether `f` is true for any section in `sections`, including the ones in
+/// `%if`s and fallbacks.
+fn any_section(sections: &[CommandSection], f: &impl Fn(&CommandSection) -> bool) -> bool {
+    sections.iter().any(|section| {
+        f(section)
+            || match section {
+                CommandSection::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => f(condition) || any_section(then, f) || any_section(otherwise, f),
+                CommandSection::Fallback { options, default } => {
+                    any_section(options, f) || default.as_ref().is_some_and(|d| any_section(d, f))
+                }
+                CommandSection::Choice(options) => {
+                    options.iter().any(|option| any_section(option, f))
+                }
+                _ => false,
+            }
+    })
+}
+
+/// Variables without a value are output as if they were text.
+fn render(sections: &[CommandSection], context: &Context) -> String {
+    sections
+        .iter()
+        .map(|section| {
+            section
+                .value(context)
+                .unwrap_or_else(|| format!("%{}", section.variable_name()))
+        })
+        .collect()
+}
+
+/// Splits the output at `%;`s and `%wait`s, into messages and how long to wait
+/// before each one. Empty messages are left out.
+fn render_messages(sections: &[CommandSection], context: &Context) -> Vec<(Duration, String)> {
+    let mut messages = vec![(Duration::ZERO, String::new())];
+    for section in sections {
+        match section {
+            CommandSection::Split => messages.push((Duration::ZERO, String::new())),
+            CommandSection::Wait(wait) => messages.push((*wait, String::new())),
+            section => {
+                let (_, message) = messages.last_mut().expect("There is always a message");
+                *message += &render(std::slice::from_ref(section), context);
+            }
+        }
+    }
+
+    // the wait before an empty message carries over to the next one
+    let mut wait = Duration::ZERO;
+    messages
+        .into_iter()
+        .filter_map(|(message_wait, message)| {
+            wait += message_wait;
+            let message = message.trim();
+            (!message.is_empty()).then(|| (std::mem::take(&mut wait), String::from(message)))
+        })
+        .collect()
+}
+
+/// `in_group` is whether `sections` are between `{` and `}`.
+fn sections_as_words_string(sections: &[CommandSection], in_group: bool) -> String {
+    let mut output = String::new();
+    let mut previous: Option<&CommandSection> = None;
+    for section in sections {
+        match section {
+            CommandSection::Echo(text) => {
+                // keeps text from continuing the variable before it
+                if previous.is_some_and(CommandSection::is_variable)
+                    && text.starts_with(|chr| is_variable_char(chr) || chr == '|' || chr == '{')
+                    || previous.is_some_and(CommandSection::ends_with_counter)
+                        && text.starts_with('@')
+                {
+                    output.push('\\');
+                }
+                output += &escape(text, output.is_empty() || output.ends_with(' '), in_group);
+            }
+            variable => output += &format!("%{}", variable.variable_name()),
+        }
+        previous = Some(section);
+    }
+    output
+}
+
+/// Escapes `%`, `\`, `&` at the start of words, and `}` in groups.
+/// `is_word_start` is whether `text` starts a word.
+fn escape(text: &str, mut is_word_start: bool, in_group: bool) -> String {
+    let mut output = String::with_capacity(text.len());
+    for chr in text.chars() {
+        if chr == '%' || chr == '\\' || (chr == '&' && is_word_start) || (chr == '}' && in_group) {
+            output.push('\\');
+        }
+        output.push(chr);
+        is_word_start = chr == ' ';
+    }
+    output
+}
+
 impl std::fmt::Display for RulesError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -289,6 +1292,620 @@ impl std::fmt::Display for RulesError {
                 f.write_fmt(format_args!("Unknown variable {name:?}."))
             }
             RulesError::BadTag(name) => f.write_fmt(format_args!("Unknown tag {name:?}.")),
+            RulesError::InvalidDuration(value) => f.write_fmt(format_args!(
+                "Invalid duration {value:?}, expected e.g. 30s, 2m, or 1h."
+            )),
+            RulesError::Expected(chr) => f.write_fmt(format_args!("Expected a '{chr}'.")),
+            RulesError::InvalidRange(range) => f.write_fmt(format_args!(
+                "Invalid range {range:?}, expected e.g. %rand(1,6)."
+            )),
+            RulesError::InvalidExpression(expression) => f.write_fmt(format_args!(
+                "Invalid expression {expression:?}, expected e.g. %{{deaths * 2 + 1}}."
+            )),
+            RulesError::ExpressionTooLong => f.write_fmt(format_args!(
+                "Expressions can be at most {MAX_EXPRESSION_LENGTH} characters long."
+            )),
+            RulesError::BadCounterOwner(owner) => f.write_fmt(format_args!(
+                "Unknown counter owner {owner:?}, expected @caller or @user."
+            )),
+            RulesError::WaitTooLong => f.write_fmt(format_args!(
+                "Waits can be at most {}.",
+                cooldown::format_duration(MAX_WAIT)
+            )),
+            RulesError::AliasMessageBreak => f.write_str(
+                "&ALIAS commands can only send one message, so they can't use %; or %wait.",
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Range { start, end } = self.span;
+        if end > start + 1 {
+            f.write_fmt(format_args!(
+                "Characters {}-{end}: {}",
+                start + 1,
+                self.kind
+            ))
+        } else {
+            f.write_fmt(format_args!("Character {}: {}", start + 1, self.kind))
+        }
+    }
+}
+
+impl std::fmt::Display for CounterName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.owner {
+            Some(CounterOwner::Caller) => f.write_fmt(format_args!("{}@caller", self.name)),
+            Some(CounterOwner::User) => f.write_fmt(format_args!("{}@user", self.name)),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// Without spaces, so that it also fits in a tag, e.g. `deaths*2+1`.
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Number(number) => f.write_fmt(format_args!("{number}")),
+            Expression::Counter(name) => f.write_str(name),
+            Expression::Argument(index) => f.write_fmt(format_args!("%{index}")),
+            Expression::Negate(operand) => {
+                if matches!(**operand, Expression::Binary { .. }) {
+                    f.write_fmt(format_args!("-({operand})"))
+                } else {
+                    f.write_fmt(format_args!("-{operand}"))
+                }
+            }
+            Expression::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                // operators of the same precedence are left-associative
+                let left_needs_parens = matches!(
+                    **left,
+                    Expression::Binary { operator: inner, .. }
+                        if inner.precedence() < operator.precedence()
+                );
+                let right_needs_parens = matches!(
+                    **right,
+                    Expression::Binary { operator: inner, .. }
+                        if inner.precedence() <= operator.precedence()
+                );
+                let parenthesize = |expression: &Expression, needs_parens: bool| {
+                    if needs_parens {
+                        format!("({expression})")
+                    } else {
+                        expression.to_string()
+                    }
+                };
+                f.write_fmt(format_args!(
+                    "{}{}{}",
+                    parenthesize(left, left_needs_parens),
+                    operator.symbol(),
+                    parenthesize(right, right_needs_parens)
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use std::collections::HashMap;
+
+    fn render_with(input: &str, rng: &Rng, chatters: &[&str]) -> String {
+        let rules = CommandRules::parse(input).unwrap();
+        let context = Context {
+            args: &[],
+            chatter_name: None,
+            counters: &HashMap::new(),
+            user_counters: &HashMap::new(),
+            owners: &CounterOwners::default(),
+            chatters,
+            rng,
+            stream: &HashMap::new(),
+        };
+        render(&rules.body, &context)
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let sample = |seed| {
+            let rng = Rng::seeded(seed);
+            (0..16)
+                .map(|_| render_with("%choice{a}{b}{c} %rand(1,100) %chatter", &rng, &["x", "y"]))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sample(42), sample(42));
+        assert_ne!(sample(42), sample(43));
+    }
+
+    #[test]
+    fn random_values_stay_in_range() {
+        let rng = Rng::seeded(7);
+        for _ in 0..256 {
+            let value: i64 = render_with("%rand(-3,3)", &rng, &[]).parse().unwrap();
+            assert!((-3..=3).contains(&value));
+
+            let choice = render_with("%choice{a}{%rand(5,5)}{}", &rng, &[]);
+            assert!(["a", "5", ""].contains(&choice.as_str()));
+
+            let chatter = render_with("%chatter", &rng, &["one", "two"]);
+            assert!(["one", "two"].contains(&chatter.as_str()));
+        }
+        assert_eq!(rng.range(i64::MIN, i64::MIN), i64::MIN);
+        let _ = rng.range(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn missing_chatter_falls_back() {
+        let rng = Rng::seeded(0);
+        assert_eq!(render_with("%chatter|{nobody}", &rng, &[]), "nobody");
+    }
+
+    #[test]
+    fn random_sections_round_trip() {
+        for input in ["%choice{a}{b %1}{} x", "%rand(-5,10)", "%chatter|%name"] {
+            let rules = CommandRules::parse(input).unwrap();
+            assert_eq!(rules.as_words_string(), input);
+            assert_eq!(
+                CommandRules::parse(&rules.as_words_string()).unwrap(),
+                rules
+            );
+        }
+        assert!(CommandRules::parse("%rand(6,1)").is_err());
+        assert!(CommandRules::parse("%rand(1)").is_err());
+    }
+
+    #[test]
+    fn per_user_counters() {
+        let counters = HashMap::from([(String::from("hugs"), 0)]);
+        let user_counters = HashMap::from([(
+            String::from("hugs"),
+            HashMap::from([(
+                String::from("1"),
+                crate::eye::UserCount {
+                    display_name: String::from("One"),
+                    value: 3,
+                },
+            )]),
+        )]);
+        let owners = CounterOwners {
+            caller: Some((String::from("1"), String::from("One"))),
+            user: Some((String::from("2"), String::from("Two"))),
+        };
+        let rules = CommandRules::parse("%counter=hugs@caller %counter=hugs@user").unwrap();
+        let context = Context {
+            args: &[],
+            chatter_name: None,
+            counters: &counters,
+            user_counters: &user_counters,
+            owners: &owners,
+            chatters: &[],
+            rng: &Rng::default(),
+            stream: &HashMap::new(),
+        };
+        assert_eq!(render(&rules.body, &context), "3 0");
+
+        for input in [
+            "&C:INC=hugs@caller %counter=hugs@user",
+            "%counter=hugs\\@caller",
+            "%counter=mail@example",
+        ] {
+            let rules = CommandRules::parse(input).unwrap();
+            assert_eq!(
+                CommandRules::parse(&rules.as_words_string()).unwrap(),
+                rules
+            );
+        }
+        assert_eq!(
+            CommandRules::parse("%counter=mail@example").unwrap().body[1],
+            CommandSection::Echo(String::from("@example"))
+        );
+        assert!(CommandRules::parse("&C:INC=hugs@someone").is_err());
+    }
+
+    #[test]
+    fn messages_split_at_breaks() {
+        let rules =
+            CommandRules::parse("thanks %0! %wait=2s %; %; they were playing %1 %wait=1s").unwrap();
+        let context = Context {
+            args: &[String::from("a"), String::from("b")],
+            chatter_name: None,
+            counters: &HashMap::new(),
+            user_counters: &HashMap::new(),
+            owners: &CounterOwners::default(),
+            chatters: &[],
+            rng: &Rng::default(),
+            stream: &HashMap::new(),
+        };
+        assert_eq!(
+            render_messages(&rules.body, &context),
+            [
+                (Duration::ZERO, String::from("thanks a!")),
+                (Duration::from_secs(2), String::from("they were playing b")),
+            ]
+        );
+
+        for input in ["a%;b", "a %wait=2s\\b"] {
+            let rules = CommandRules::parse(input).unwrap();
+            assert_eq!(
+                CommandRules::parse(&rules.as_words_string()).unwrap(),
+                rules
+            );
+        }
+        assert!(CommandRules::parse("%wait=1h").is_err());
+        assert!(CommandRules::parse("%if(0){a %wait=2s b}").is_err());
+    }
+
+    #[test]
+    fn expressions_evaluate() {
+        let counters = HashMap::from([(String::from("deaths"), 4)]);
+        let args = [String::from("3"), String::from("x")];
+        let evaluate = |input| Expression::parse(input).unwrap().evaluate(&args, &counters);
+
+        assert_eq!(evaluate("deaths * 2 + 1"), Some(9));
+        assert_eq!(evaluate("(deaths + %0) * -2"), Some(-14));
+        assert_eq!(evaluate("10 - 4 - 3"), Some(3));
+        assert_eq!(evaluate("deaths % %0"), Some(1));
+        assert_eq!(evaluate("1 / 0"), None);
+        assert_eq!(evaluate("9223372036854775807 + 1"), None);
+        assert_eq!(evaluate("-(-9223372036854775807 - 1)"), None);
+        assert_eq!(evaluate("missing + 1"), None);
+        assert_eq!(evaluate("%1 + 1"), None);
+
+        assert!(Expression::parse("1 +").is_err());
+        assert!(Expression::parse("(1").is_err());
+        assert!(Expression::parse(&"1+".repeat(MAX_EXPRESSION_LENGTH)).is_err());
+    }
+
+    #[test]
+    fn expressions_round_trip() {
+        for input in [
+            "%{deaths*2+1} deaths",
+            "%{10-(4-3)}",
+            "%{-(a+b)*-c}",
+            "%{a%%1/(b%2)}",
+            "&C:ADD=deaths:%1*2 hi",
+            "&C:SET=deaths:0 set",
+        ] {
+            let rules = CommandRules::parse(input).unwrap();
+            assert_eq!(rules.as_words_string(), input);
+        }
+        let rules = CommandRules::parse("%{ (a + b) + (c * d) }").unwrap();
+        assert_eq!(rules.as_words_string(), "%{a+b+c*d}");
+        assert_eq!(
+            CommandRules::parse(&rules.as_words_string()).unwrap(),
+            rules
+        );
+    }
+
+    fn render_args(input: &str, args: &[&str], chatter_name: Option<&str>) -> String {
+        let rules = CommandRules::parse(input).unwrap();
+        let args = args.iter().copied().map(String::from).collect::<Vec<_>>();
+        let context = Context {
+            args: &args,
+            chatter_name,
+            counters: &HashMap::from([(String::from("deaths"), 0)]),
+            user_counters: &HashMap::new(),
+            owners: &CounterOwners::default(),
+            chatters: &[],
+            rng: &Rng::default(),
+            stream: &HashMap::new(),
+        };
+        render(&rules.body, &context)
+    }
+
+    #[test]
+    fn parses_conditionals_and_fallbacks() {
+        assert_eq!(
+            CommandRules::parse("%if(0){hugs %0}{hugs everyone}!")
+                .unwrap()
+                .body,
+            [
+                CommandSection::If {
+                    condition: Box::new(CommandSection::WordIndex(0)),
+                    then: vec![
+                        CommandSection::Echo(String::from("hugs ")),
+                        CommandSection::WordIndex(0),
+                    ],
+                    otherwise: vec![CommandSection::Echo(String::from("hugs everyone"))],
+                },
+                CommandSection::Echo(String::from("!")),
+            ]
+        );
+        assert_eq!(
+            CommandRules::parse("%0|%name|{someone}").unwrap().body,
+            [CommandSection::Fallback {
+                options: vec![CommandSection::WordIndex(0), CommandSection::ChatterName],
+                default: Some(vec![CommandSection::Echo(String::from("someone"))]),
+            }]
+        );
+        // a `|` that isn't followed by a variable or default is text
+        assert_eq!(
+            CommandRules::parse("%0|x").unwrap().body,
+            [
+                CommandSection::WordIndex(0),
+                CommandSection::Echo(String::from("|x")),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditionals_and_fallbacks_render() {
+        let input = "%if(0){hugs %0|{?}}{%if(counter=deaths){x}{no deaths}}";
+        assert_eq!(render_args(input, &["Ann"], None), "hugs Ann");
+        assert_eq!(render_args(input, &[], None), "no deaths");
+        assert_eq!(render_args("%if(0){yes}", &["0"], None), "");
+
+        let input = "hi %0|%name|{someone}";
+        assert_eq!(render_args(input, &["Ann"], Some("Bob")), "hi Ann");
+        assert_eq!(render_args(input, &[], Some("Bob")), "hi Bob");
+        assert_eq!(render_args(input, &[], None), "hi someone");
+
+        // escaped braces are text, even inside groups
+        assert_eq!(render_args(r"%if(0){\{%0\}}{\}}", &["a"], None), "{a}");
+        assert_eq!(render_args(r"%if(0){\{%0\}}{\}}", &[], None), "}");
+    }
+
+    #[test]
+    fn missing_arguments_are_text() {
+        // so that a chatter can see which argument they left out
+        assert_eq!(
+            render_args("hugs %0 and %1", &["Ann"], None),
+            "hugs Ann and %1"
+        );
+        assert_eq!(render_args("%@", &[], None), "%@");
+        // unless the command gives a default
+        assert_eq!(
+            render_args("hugs %0 and %1|{}", &["Ann"], None),
+            "hugs Ann and "
+        );
+    }
+
+    #[test]
+    fn invalid_groups_are_errors() {
+        for input in [
+            "%if(0){unclosed",
+            "%if(0)",
+            "%if(0 {x}",
+            "%if(nope){x}",
+            "%0|{unclosed",
+            "%choice",
+        ] {
+            assert!(
+                CommandRules::parse(input).is_err(),
+                "{input:?} should not parse"
+            );
+        }
+        // tags inside a group, and a `}` outside of one, are text
+        assert_eq!(render_args("%if(0){&REPLY}", &["a"], None), "&REPLY");
+        assert_eq!(
+            CommandRules::parse("a } b").unwrap().body,
+            [CommandSection::Echo(String::from("a } b"))]
+        );
+    }
+
+    #[test]
+    fn finds_caller_only_parts() {
+        let part = |input| CommandRules::parse(input).unwrap().caller_only_part();
+        assert_eq!(part("&REPLY hi"), Some("&REPLY"));
+        assert_eq!(part("&ALIAS !hug"), Some("&ALIAS"));
+        assert_eq!(part("&C:INC=hugs@caller hi"), Some("per-user counters"));
+        assert_eq!(
+            part("%if(0){%counter=hugs@user}"),
+            Some("per-user counters")
+        );
+        assert_eq!(part("%0|%name"), Some("%name"));
+        assert_eq!(part("hi %touser"), Some("%touser"));
+        assert_eq!(
+            part("&C:INC=posts Post #%counter=posts %chatter %uptime"),
+            None
+        );
+    }
+
+    #[test]
+    fn errors_have_spans() {
+        let error = |input| CommandRules::parse(input).unwrap_err();
+        assert_eq!(error("hi %nope there").span, 3..8);
+        assert_eq!(error("&REPLY &NOPE hi").span, 7..12);
+        assert_eq!(error("&C:INC=a@x").span, 0..10);
+        assert_eq!(error("é %if(1){a").span, 8..10);
+        assert_eq!(error("%{1 +} x").span, 0..5);
+        assert_eq!(error("a %wait=1h").span, 2..10);
+        assert_eq!(error("%1|%nope").span, 3..8);
+        assert_eq!(error("&ALIAS !hug %; !wave").span, 12..14);
+        assert_eq!(error("!hug %wait=2s !wave &ALIAS").span, 5..13);
+        assert_eq!(
+            error("hi %nope there").to_string(),
+            "Characters 4-8: Unknown variable \"nope\"."
+        );
+    }
+
+    /// Text with every character that needs escaping somewhere, often at its
+    /// start, where it could continue the variable before it.
+    fn random_text(rng: &Rng) -> String {
+        const CHARACTERS: [char; 20] = [
+            'a', 'b', '1', ' ', ' ', '%', '\\', '&', '{', '}', '|', '@', '=', ':', ';', '(', ')',
+            '-', 'é', '/',
+        ];
+        const STARTS: [&str; 9] = ["@caller", "@user", "|", "{", "&", "%", "1", ":", " "];
+        let start = match rng.below(2) {
+            0 => *rng.choose(&STARTS).unwrap(),
+            _ => "",
+        };
+        let rest: String = (0..=rng.below(6))
+            .filter_map(|_| rng.choose(&CHARACTERS))
+            .collect();
+        format!("{start}{rest}")
+    }
+    fn random_name(rng: &Rng) -> String {
+        (0..=rng.below(3))
+            .filter_map(|_| rng.choose(&['a', 'b', 'z', '_']).copied())
+            .collect()
+    }
+    fn random_counter(rng: &Rng) -> CounterName {
+        CounterName {
+            name: random_name(rng),
+            owner: *rng
+                .choose(&[None, Some(CounterOwner::Caller), Some(CounterOwner::User)])
+                .unwrap(),
+        }
+    }
+    fn random_expression(rng: &Rng, depth: usize) -> Expression {
+        let operator = *rng
+            .choose(&[
+                Operator::Add,
+                Operator::Subtract,
+                Operator::Multiply,
+                Operator::Divide,
+                Operator::Remainder,
+            ])
+            .unwrap();
+        match rng.below(if depth == 0 { 3 } else { 5 }) {
+            // negative numbers are parsed as negations
+            0 => Expression::Number(rng.range(0, 1000)),
+            1 => Expression::Counter(random_name(rng)),
+            2 => Expression::Argument(rng.below(10) as usize),
+            3 => Expression::Negate(Box::new(random_expression(rng, depth - 1))),
+            _ => Expression::Binary {
+                operator,
+                left: Box::new(random_expression(rng, depth - 1)),
+                right: Box::new(random_expression(rng, depth - 1)),
+            },
+        }
+    }
+    fn random_simple_variable(rng: &Rng) -> CommandSection {
+        match rng.below(7) {
+            0 => CommandSection::ChatterName,
+            1 => CommandSection::WordIndex(rng.below(12) as usize),
+            2 => CommandSection::Counter(random_counter(rng)),
+            3 => CommandSection::AllWords,
+            4 => CommandSection::RandomChatter,
+            5 => CommandSection::Stream(*rng.choose(&StreamVariable::ALL).unwrap()),
+            _ => CommandSection::ToUser,
+        }
+    }
+    /// Sections like the ones `CommandRules::parse` gives, i.e. without
+    /// adjacent text sections, or message breaks in groups.
+    fn random_sections(rng: &Rng, depth: usize, in_group: bool) -> Vec<CommandSection> {
+        let mut sections: Vec<CommandSection> = Vec::new();
+        for _ in 0..rng.below(6) {
+            let section = match rng.below(if depth == 0 { 2 } else { 10 }) {
+                0 => CommandSection::Echo(random_text(rng)),
+                1 => random_simple_variable(rng),
+                2 => CommandSection::If {
+                    condition: Box::new(random_simple_variable(rng)),
+                    then: random_sections(rng, depth - 1, true),
+                    otherwise: random_sections(rng, depth - 1, true),
+                },
+                3 => {
+                    let options: Vec<_> = (0..=rng.below(3))
+                        .map(|_| random_simple_variable(rng))
+                        .collect();
+                    let default = (options.len() == 1 || rng.below(2) == 0)
+                        .then(|| random_sections(rng, depth - 1, true));
+                    CommandSection::Fallback { options, default }
+                }
+                4 => CommandSection::Choice(
+                    (0..=rng.below(3))
+                        .map(|_| random_sections(rng, depth - 1, true))
+                        .collect(),
+                ),
+                5 => {
+                    let min = rng.range(-50, 50);
+                    CommandSection::RandomInt {
+                        min,
+                        max: rng.range(min, 50),
+                    }
+                }
+                6 => CommandSection::Expression(random_expression(rng, 2)),
+                7 if !in_group => CommandSection::Split,
+                8 if !in_group => CommandSection::Wait(Duration::from_secs(rng.below(61))),
+                _ => CommandSection::Echo(random_text(rng)),
+            };
+            match (sections.last_mut(), section) {
+                (Some(CommandSection::Echo(text)), CommandSection::Echo(more)) => *text += &more,
+                (_, section) => sections.push(section),
+            }
+        }
+        sections
+    }
+    fn random_tags(rng: &Rng) -> HashSet<CommandTag> {
+        (0..rng.below(4))
+            .map(|_| match rng.below(12) {
+                0 => CommandTag::Reply,
+                1 => CommandTag::Permission(*rng.choose(&Permission::ALL).unwrap()),
+                2 => CommandTag::Allow(random_name(rng)),
+                3 => CommandTag::Deny(random_name(rng)),
+                4 => CommandTag::Temporary,
+                5 => CommandTag::CountInc(random_counter(rng)),
+                6 => CommandTag::CountDec(random_counter(rng)),
+                7 => CommandTag::CountReset(random_counter(rng)),
+                8 => CommandTag::CountAdd(random_counter(rng), random_expression(rng, 2)),
+                9 => CommandTag::CountSet(random_counter(rng), random_expression(rng, 2)),
+                10 => CommandTag::Cooldown(Duration::from_secs(rng.below(4000))),
+                _ => CommandTag::UserCooldown(Duration::from_secs(rng.below(4000))),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_inverts_as_words_string() {
+        let rng = Rng::seeded(2024);
+        for _ in 0..10_000 {
+            let rules = CommandRules {
+                body: random_sections(&rng, 3, false),
+                tags: random_tags(&rng),
+            };
+            let raw = rules.as_words_string();
+            let parsed = CommandRules::parse(&raw).unwrap_or_else(|err| panic!("{raw:?}: {err}"));
+            assert_eq!(parsed, rules, "{raw:?}");
+            assert_eq!(parsed.as_words_string(), raw);
+        }
+    }
+
+    #[test]
+    fn reparsing_keeps_meaning() {
+        const PIECES: [&str; 20] = [
+            "a",
+            " ",
+            "%",
+            "%1",
+            "%name",
+            "%counter=a",
+            "|%counter=b",
+            "@",
+            "@caller",
+            "|",
+            "{",
+            "}",
+            "\\",
+            "&REPLY",
+            "&",
+            "%if(1){",
+            "%choice{",
+            "%;",
+            "%{a+1}",
+            "%wait=2s",
+        ];
+        let rng = Rng::seeded(2025);
+        for _ in 0..10_000 {
+            let input: String = (0..rng.below(10))
+                .filter_map(|_| rng.choose(&PIECES).copied())
+                .collect();
+            let Ok(rules) = CommandRules::parse(&input) else {
+                continue;
+            };
+            let raw = rules.as_words_string();
+            let parsed = CommandRules::parse(&raw).unwrap_or_else(|err| panic!("{raw:?}: {err}"));
+            assert_eq!(parsed, rules, "{input:?} became {raw:?}");
         }
     }
 }
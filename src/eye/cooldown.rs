@@ -0,0 +1,268 @@
+//! Rate limits for builtin and custom commands. Cooldowns are kept in memory
+//! only, so they reset when the bot restarts.
+use crate::bot::interface::BotInterface;
+use crate::chat::data::ChatMessage;
+use crate::log::{self, Target};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often a command can be used, across all chatters and per chatter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cooldown {
+    pub global: Option<Duration>,
+    pub user: Option<Duration>,
+}
+
+/// When each command was last used, across all chatters and per chatter.
+#[derive(Debug, Default)]
+pub struct Cooldowns {
+    global: HashMap<String, Instant>,
+    user: HashMap<(String, String), Instant>,
+}
+
+impl Cooldown {
+    #[must_use]
+    pub fn is_none(&self) -> bool {
+        self.global.is_none() && self.user.is_none()
+    }
+}
+
+impl Cooldowns {
+    /// Records a use of `command` by `user_id`, unless it is still on
+    /// cooldown. Returns the time left if it is.
+    pub fn try_use(
+        &mut self,
+        command: &str,
+        user_id: &str,
+        cooldown: Cooldown,
+    ) -> Option<Duration> {
+        self.try_use_at(command, user_id, cooldown, Instant::now())
+    }
+    fn try_use_at(
+        &mut self,
+        command: &str,
+        user_id: &str,
+        cooldown: Cooldown,
+        now: Instant,
+    ) -> Option<Duration> {
+        let user_key = (String::from(command), String::from(user_id));
+
+        let remaining = [
+            cooldown.global.zip(self.global.get(command)),
+            cooldown.user.zip(self.user.get(&user_key)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|(duration, last_used)| (*last_used + duration).checked_duration_since(now))
+        .filter(|remaining| !remaining.is_zero())
+        .max();
+        if remaining.is_some() {
+            return remaining;
+        }
+
+        if cooldown.global.is_some() {
+            self.global.insert(String::from(command), now);
+        }
+        if cooldown.user.is_some() {
+            self.user.insert(user_key, now);
+        }
+        None
+    }
+}
+
+/// Whether `msg`'s author can use `command` now, recording the use if so.
+/// Moderators and the broadcaster are never on cooldown. Whispers the chatter
+/// how long is left if `bot.cooldown_whisper` is enabled.
+pub(super) async fn check(
+    command: &str,
+    cooldown: Cooldown,
+    msg: &ChatMessage,
+    bot: &BotInterface,
+    data: &super::StoreInner,
+) -> bool {
+    if cooldown.is_none() || msg.user_is_super() {
+        return true;
+    }
+
+    let mut data = data.write().await;
+    let Some(remaining) = data.cooldowns.try_use(command, &msg.user_id, cooldown) else {
+        return true;
+    };
+    let whisper = data.options.bot.cooldown_whisper;
+    drop(data);
+
+    log::debug(
+        Target::Eye,
+        format!(
+            "!{command} is on cooldown for {} ({})",
+            msg.user_id,
+            format_duration(remaining)
+        ),
+    );
+    if whisper {
+        bot.whisper(
+            &msg.user_id,
+            format!(
+                "!{command} is on cooldown for another {}.",
+                format_duration(remaining)
+            ),
+        )
+        .await;
+    }
+    false
+}
+
+/// Parses `30s`, `2m`, `1h`, or a plain number of seconds.
+#[must_use]
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let (number, unit) = match input.find(|chr: char| !chr.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number.checked_mul(60)?,
+        "h" => number.checked_mul(60 * 60)?,
+        _ => return None,
+    };
+    Some(Duration::from_secs(seconds))
+}
+
+/// Rounds up to the nearest second, e.g. `30s`.
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        seconds += 1;
+    }
+    format!("{seconds}s")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration, Cooldown, Cooldowns};
+    use std::time::{Duration, Instant};
+
+    const fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn global_cooldowns_apply_to_everyone() {
+        let start = Instant::now();
+        let cooldown = Cooldown {
+            global: Some(secs(10)),
+            user: None,
+        };
+        let mut cooldowns = Cooldowns::default();
+
+        assert_eq!(cooldowns.try_use_at("hug", "1", cooldown, start), None);
+        assert_eq!(
+            cooldowns.try_use_at("hug", "2", cooldown, start + secs(4)),
+            Some(secs(6))
+        );
+        // other commands have their own cooldowns
+        assert_eq!(cooldowns.try_use_at("wave", "2", cooldown, start), None);
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(10)),
+            None
+        );
+    }
+
+    #[test]
+    fn user_cooldowns_apply_to_each_chatter() {
+        let start = Instant::now();
+        let cooldown = Cooldown {
+            global: None,
+            user: Some(secs(10)),
+        };
+        let mut cooldowns = Cooldowns::default();
+
+        assert_eq!(cooldowns.try_use_at("hug", "1", cooldown, start), None);
+        assert_eq!(cooldowns.try_use_at("hug", "2", cooldown, start), None);
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(3)),
+            Some(secs(7))
+        );
+    }
+
+    #[test]
+    fn the_longest_cooldown_wins() {
+        let start = Instant::now();
+        let cooldown = Cooldown {
+            global: Some(secs(5)),
+            user: Some(secs(20)),
+        };
+        let mut cooldowns = Cooldowns::default();
+        assert_eq!(cooldowns.try_use_at("hug", "1", cooldown, start), None);
+
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(2)),
+            Some(secs(18))
+        );
+        assert_eq!(
+            cooldowns.try_use_at("hug", "2", cooldown, start + secs(2)),
+            Some(secs(3))
+        );
+        // only the per-user cooldown is left
+        assert_eq!(
+            cooldowns.try_use_at("hug", "2", cooldown, start + secs(5)),
+            None
+        );
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(6)),
+            Some(secs(14))
+        );
+    }
+
+    #[test]
+    fn uses_on_cooldown_are_not_recorded() {
+        let start = Instant::now();
+        let cooldown = Cooldown {
+            global: Some(secs(10)),
+            user: Some(secs(10)),
+        };
+        let mut cooldowns = Cooldowns::default();
+        assert_eq!(cooldowns.try_use_at("hug", "1", cooldown, start), None);
+
+        // a rejected use doesn't restart the cooldown
+        assert!(cooldowns
+            .try_use_at("hug", "1", cooldown, start + secs(9))
+            .is_some());
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(10)),
+            None
+        );
+        assert_eq!(
+            cooldowns.try_use_at("hug", "1", cooldown, start + secs(11)),
+            Some(secs(9))
+        );
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("30s"), Some(secs(30)));
+        assert_eq!(parse_duration("2m"), Some(secs(2 * 60)));
+        assert_eq!(parse_duration("1h"), Some(secs(60 * 60)));
+        assert_eq!(parse_duration("45"), Some(secs(45)));
+        assert_eq!(parse_duration("0"), Some(secs(0)));
+
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("5d"), None);
+        assert_eq!(parse_duration("5 s"), None);
+        assert_eq!(parse_duration("-5s"), None);
+        assert_eq!(parse_duration(&format!("{}h", u64::MAX / 60)), None);
+        assert_eq!(parse_duration(&format!("{}0", u64::MAX)), None);
+    }
+
+    #[test]
+    fn durations_are_rounded_up() {
+        assert_eq!(format_duration(secs(30)), "30s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(1)), "1s");
+        assert_eq!(format_duration(Duration::from_millis(29_001)), "30s");
+        assert_eq!(format_duration(Duration::from_nanos(30_000_000_001)), "31s");
+    }
+}
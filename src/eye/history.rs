@@ -0,0 +1,315 @@
+//! Revision histories of custom commands and listeners, so that an edit or a
+//! removal can be undone with `!cmd:revert` or `!listen:revert`.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// How many revisions are kept of each command or listener.
+pub const MAX_REVISIONS: usize = 10;
+
+/// Revisions by command or listener name, oldest first.
+#[derive(Debug, Default)]
+pub struct History(HashMap<String, VecDeque<Revision>>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    /// Counts up from 1, and isn't reused when old revisions are dropped.
+    pub number: u64,
+    /// When the revision was made, in seconds since the Unix epoch. 0 for
+    /// a definition from before its history was kept.
+    pub time: u64,
+    /// The display name of the chatter who made the revision, if known.
+    pub editor: Option<String>,
+    /// The definition, as it's stored on disk, or `None` if this revision
+    /// removed it.
+    pub body: Option<String>,
+}
+
+/// What a [`History`] is of.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Command,
+    Listener,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RevertError {
+    NoHistory,
+    NothingToRevert,
+    UnknownRevision(u64),
+}
+
+impl History {
+    /// Records that `editor` changed `name` to `body`, or removed it if
+    /// `body` is `None`. If `name` has no history yet, `previous`, its
+    /// definition before the change, is kept as its first revision.
+    pub fn record(
+        &mut self,
+        name: &str,
+        previous: Option<String>,
+        editor: &str,
+        body: Option<String>,
+    ) {
+        let revisions = self.0.entry(String::from(name)).or_default();
+        if revisions.is_empty() {
+            if let Some(previous) = previous {
+                revisions.push_back(Revision {
+                    number: 1,
+                    time: 0,
+                    editor: None,
+                    body: Some(previous),
+                });
+            }
+        }
+
+        let number = revisions.back().map_or(1, |revision| revision.number + 1);
+        revisions.push_back(Revision {
+            number,
+            time: super::unix_now(),
+            editor: Some(String::from(editor)),
+            body,
+        });
+        while revisions.len() > MAX_REVISIONS {
+            revisions.pop_front();
+        }
+    }
+
+    /// The revisions of `name`, oldest first.
+    #[must_use]
+    pub fn revisions(&self, name: &str) -> Option<&VecDeque<Revision>> {
+        self.0.get(name)
+    }
+
+    /// The revision numbered `number`, or the one before the latest if
+    /// `number` isn't given.
+    pub fn target(&self, name: &str, number: Option<u64>) -> Result<&Revision, RevertError> {
+        let revisions = self.0.get(name).ok_or(RevertError::NoHistory)?;
+        match number {
+            Some(number) => revisions
+                .iter()
+                .find(|revision| revision.number == number)
+                .ok_or(RevertError::UnknownRevision(number)),
+            None => revisions
+                .iter()
+                .rev()
+                .nth(1)
+                .ok_or(RevertError::NothingToRevert),
+        }
+    }
+
+    /// Every revision, one per line, to be read back by [`History::load_line`].
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|(name, revisions)| {
+                revisions.iter().map(move |revision| {
+                    format!(
+                        "{name} {} {} {} {}",
+                        revision.number,
+                        revision.time,
+                        revision.editor.as_deref().unwrap_or("-"),
+                        revision
+                            .body
+                            .as_ref()
+                            .map_or_else(|| String::from("-"), |body| format!("+{body}")),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Adds a revision written by [`History::lines`]. Invalid lines are
+    /// ignored.
+    pub fn load_line(&mut self, line: &str) {
+        let mut parts = line.trim_start().splitn(5, ' ');
+        let (Some(name), Some(number), Some(time), Some(editor), Some(body)) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return;
+        };
+        let (Ok(number), Ok(time)) = (number.parse(), time.parse()) else {
+            return;
+        };
+        let body = match body.strip_prefix('+') {
+            Some(body) => Some(String::from(body)),
+            None if body == "-" => None,
+            None => return,
+        };
+
+        let revisions = self.0.entry(String::from(name)).or_default();
+        revisions.push_back(Revision {
+            number,
+            time,
+            editor: (editor != "-").then(|| String::from(editor)),
+            body,
+        });
+        revisions
+            .make_contiguous()
+            .sort_by_key(|revision| revision.number);
+        while revisions.len() > MAX_REVISIONS {
+            revisions.pop_front();
+        }
+    }
+}
+
+impl Kind {
+    #[must_use]
+    pub fn noun(self) -> &'static str {
+        match self {
+            Kind::Command => "command",
+            Kind::Listener => "listener",
+        }
+    }
+
+    pub(super) fn history(self, data: &super::StoreData) -> &History {
+        match self {
+            Kind::Command => &data.command_history,
+            Kind::Listener => &data.listener_history,
+        }
+    }
+
+    /// The current definition of `name`, as it's stored on disk.
+    pub(super) fn current(self, data: &super::StoreData, name: &str) -> Option<String> {
+        match self {
+            Kind::Command => data.commands.get(name).map(|body| body.as_words_string()),
+            Kind::Listener => data
+                .listeners
+                .get(name)
+                .map(|listener| listener.definition()),
+        }
+    }
+
+    /// Sets `name` to `body`, or removes it if `body` is `None`, and records
+    /// the change in its history.
+    pub(super) fn apply(
+        self,
+        data: &mut super::StoreData,
+        name: &str,
+        editor: &str,
+        body: Option<&str>,
+    ) -> Result<(), String> {
+        let previous = self.current(data, name);
+        match (self, body) {
+            (Kind::Command, Some(body)) => {
+                let body =
+                    super::command::CommandRules::parse(body).map_err(|err| err.to_string())?;
+                data.commands.insert(String::from(name), Arc::new(body));
+            }
+            (Kind::Command, None) => {
+                data.remove_command(name);
+            }
+            (Kind::Listener, Some(body)) => {
+                let listener = super::listener::Listener::parse(body)
+                    .ok_or_else(|| String::from("Invalid listener."))?;
+                data.listeners
+                    .insert(String::from(name), Arc::new(listener));
+            }
+            (Kind::Listener, None) => {
+                data.listeners.remove(name);
+            }
+        }
+
+        let body = self.current(data, name);
+        match self {
+            Kind::Command => &mut data.command_history,
+            Kind::Listener => &mut data.listener_history,
+        }
+        .record(name, previous, editor, body);
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RevertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevertError::NoHistory => f.write_str("It has no history."),
+            RevertError::NothingToRevert => f.write_str("It has no earlier revision."),
+            RevertError::UnknownRevision(number) => {
+                f.write_fmt(format_args!("Revision #{number} isn't kept."))
+            }
+        }
+    }
+}
+
+impl std::error::Error for RevertError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bodies(history: &History, name: &str) -> Vec<(u64, Option<String>)> {
+        history
+            .revisions(name)
+            .map(|revisions| {
+                revisions
+                    .iter()
+                    .map(|revision| (revision.number, revision.body.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn keeps_the_original_and_bounds_revisions() {
+        let mut history = History::default();
+        history.record(
+            "hug",
+            Some(String::from("old")),
+            "Mod",
+            Some(String::from("new")),
+        );
+        assert_eq!(
+            bodies(&history, "hug"),
+            vec![
+                (1, Some(String::from("old"))),
+                (2, Some(String::from("new")))
+            ]
+        );
+        assert_eq!(history.revisions("hug").unwrap()[0].editor, None);
+        assert_eq!(
+            history.target("hug", None).unwrap().body.as_deref(),
+            Some("old")
+        );
+
+        for i in 0..MAX_REVISIONS {
+            history.record("hug", None, "Mod", Some(i.to_string()));
+        }
+        history.record("hug", None, "Mod", None);
+        let revisions = bodies(&history, "hug");
+        assert_eq!(revisions.len(), MAX_REVISIONS);
+        assert_eq!(revisions.last(), Some(&(13, None)));
+        assert_eq!(
+            history.target("hug", Some(1)),
+            Err(RevertError::UnknownRevision(1))
+        );
+        assert_eq!(
+            history.target("hug", None).unwrap().body.as_deref(),
+            Some("9")
+        );
+        assert_eq!(history.target("bonk", None), Err(RevertError::NoHistory));
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let mut history = History::default();
+        history.record(
+            "hug",
+            Some(String::from("a b")),
+            "Mod",
+            Some(String::from("+c -d ")),
+        );
+        history.record("hug", None, "Mod", None);
+        history.record("bonk", None, "Other_Mod", Some(String::from("-")));
+
+        let mut loaded = History::default();
+        for line in history.lines() {
+            loaded.load_line(&line);
+        }
+        assert_eq!(loaded.revisions("hug"), history.revisions("hug"));
+        assert_eq!(loaded.revisions("bonk"), history.revisions("bonk"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::log::{self, Target};
 use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
@@ -23,28 +24,65 @@ pub(super) fn spawn_io(
     });
 }
 
+/// Writes the store once the bot starts shutting down, after any writes that
+/// are already in progress.
+pub(super) async fn flush_on_shutdown(
+    data: super::StoreInner,
+    hold: crate::bot::shutdown::ShutdownHold,
+    shutdown: crate::bot::shutdown::Shutdown,
+) {
+    shutdown.triggered().await;
+
+    log::debug(Target::Eye, "Flushing the store");
+    if let Err(err) = refresh(data).await {
+        log::warn(
+            Target::Eye,
+            format!("Could not write the store while shutting down: {err}"),
+        );
+    }
+    drop(hold);
+}
+
 pub(super) async fn refresh(data: super::StoreInner) -> std::io::Result<()> {
+    let io_lock = data.read().await.io_lock.clone();
+    let _io_guard = io_lock.lock().await;
     let data = data.read().await;
 
     let mut stores = Vec::new();
 
     if data.options.features.custom_commands {
-        data.options.debug("Eye: Writing custom commands");
+        log::debug(Target::Eye, "Writing custom commands");
 
         stores.push((
             data.store_path.join("commands.txt"),
             data.commands
                 .iter()
                 .filter_map(|(k, v)| {
-                    (!v.is_builtin() && !v.is_temporary())
+                    (!v.is_temporary())
                         .then_some(format!("{k} {}", v.as_words_string()))
                 })
                 .collect::<Vec<_>>()
                 .join("\n"),
+        ));
+        stores.push((
+            data.store_path.join("command_history.txt"),
+            data.command_history.lines().join("\n"),
+        ));
+        stores.push((
+            data.store_path.join("command_stats.txt"),
+            command_stats(&data),
+        ));
+        stores.push((
+            data.store_path.join("aliases.txt"),
+            data.aliases
+                .iter()
+                .map(|(k, v)| format!("{k} {v}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
         ))
     }
     if data.options.features.counters {
-        data.options.debug("Eye: Writing counters");
+        log::debug(Target::Eye, "Writing counters");
 
         stores.push((
             data.store_path.join("counters.txt"),
@@ -54,25 +92,45 @@ pub(super) async fn refresh(data: super::StoreInner) -> std::io::Result<()> {
                 .collect::<Vec<_>>()
                 .join("\n"),
         ));
+        stores.push((
+            data.store_path.join("user_counters.txt"),
+            data.user_counters
+                .iter()
+                .flat_map(|(name, values)| {
+                    values.iter().map(move |(user_id, count)| {
+                        format!("{name} {user_id} {} {}", count.value, count.display_name)
+                    })
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ));
     }
     if data.options.features.listeners {
-        data.options.debug("Eye: Writing listeners");
+        log::debug(Target::Eye, "Writing listeners");
 
         stores.push((
             data.store_path.join("listeners.txt"),
             data.listeners
                 .iter()
-                .map(|(k, v)| {
-                    let (kind, pattern) = match &v.predicate {
-                        super::listener::Predicate::Exactly(pat) => ('e', pat.as_str()),
-                        super::listener::Predicate::Contains(pat) => ('c', pat.as_str()),
-                        super::listener::Predicate::Regex(pat) => ('r', pat.as_str()),
-                    };
-                    format!(
-                        "{kind} {k} {}/{}",
-                        pattern.replace('\\', "\\\\").replace('/', "\\/"),
-                        v.body.as_words_string()
-                    )
+                .map(|(k, v)| v.line(k))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ));
+        stores.push((
+            data.store_path.join("listener_history.txt"),
+            data.listener_history.lines().join("\n"),
+        ));
+    }
+
+    if data.options.features.timers {
+        log::debug(Target::Eye, "Writing timers");
+
+        stores.push((
+            data.store_path.join("timers.txt"),
+            data.timers
+                .iter()
+                .filter_map(|(k, v)| {
+                    (!v.body.is_temporary()).then_some(format!("{k} {}", v.as_words_string()))
                 })
                 .collect::<Vec<_>>()
                 .join("\n"),
@@ -92,29 +150,99 @@ pub(super) async fn refresh(data: super::StoreInner) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Writes only the usage statistics of custom commands, which change on every
+/// use, rather than the whole store.
+pub(super) async fn refresh_command_stats(data: super::StoreInner) -> std::io::Result<()> {
+    let io_lock = data.read().await.io_lock.clone();
+    let _io_guard = io_lock.lock().await;
+    let data = data.read().await;
+    if !data.options.features.custom_commands {
+        return Ok(());
+    }
+
+    let path = data.store_path.join("command_stats.txt");
+    let store = command_stats(&data);
+    drop(data);
+    tokio::fs::write(path, store).await
+}
+
+fn command_stats(data: &super::StoreData) -> String {
+    data.command_stats
+        .iter()
+        .map(|(k, v)| format!("{k} {} {} {}", v.uses, v.last_used, v.last_user))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parses a line written by [`command_stats`], skipping malformed ones.
+fn parse_command_stats(line: &str) -> Option<(&str, super::CommandStats)> {
+    let mut parts = line.trim().splitn(4, ' ');
+    let (name, uses, last_used) = (parts.next()?, parts.next()?, parts.next()?);
+    Some((
+        name,
+        super::CommandStats {
+            uses: uses.parse().ok()?,
+            last_used: last_used.parse().ok()?,
+            last_user: String::from(parts.next().unwrap_or("")),
+        },
+    ))
+}
+
 pub(super) async fn load(data: Arc<RwLock<super::StoreData>>) -> std::io::Result<()> {
     let mut data = data.write().await;
 
     if data.options.features.custom_commands {
-        data.options.debug("Eye: Loading custom commands");
+        log::debug(Target::Eye, "Loading custom commands");
 
         for command in read_create(data.store_path.join("commands.txt")).await? {
-            let command = command.trim();
+            // trailing spaces are part of the command
+            let command = command.trim_start();
 
             if let Some((name, command)) = command.split_once(' ') {
-                if data.commands.get(name).is_some() {
+                if data.commands.contains_key(name) {
                     continue;
                 }
 
-                if let Ok(command) = super::command::CommandRules::parse(command) {
-                    data.commands.insert(String::from(name), Arc::new(command));
+                match super::command::CommandRules::parse(command) {
+                    Ok(command) => {
+                        data.commands.insert(String::from(name), Arc::new(command));
+                    }
+                    Err(err) => log::warn(
+                        Target::Eye,
+                        format!("Could not load command {name:?}: {err}"),
+                    ),
                 }
             }
         }
+
+        for revision in read_create(data.store_path.join("command_history.txt")).await? {
+            data.command_history.load_line(&revision);
+        }
+
+        for line in read_create(data.store_path.join("command_stats.txt")).await? {
+            let Some((name, stats)) = parse_command_stats(&line) else {
+                continue;
+            };
+
+            crate::metrics::METRICS
+                .custom_command_uses
+                .set(name, stats.uses);
+            crate::metrics::METRICS
+                .custom_command_last_used
+                .set(name, stats.last_used);
+            data.command_stats.insert(String::from(name), stats);
+        }
+
+        for alias in read_create(data.store_path.join("aliases.txt")).await? {
+            if let Some((name, target)) = alias.trim().split_once(' ') {
+                data.aliases
+                    .insert(String::from(name), String::from(target));
+            }
+        }
     }
 
     if data.options.features.counters {
-        data.options.debug("Eye: Loading counters");
+        log::debug(Target::Eye, "Loading counters");
 
         for counter in read_create(data.store_path.join("counters.txt")).await? {
             let counter = counter.trim();
@@ -125,31 +253,82 @@ pub(super) async fn load(data: Arc<RwLock<super::StoreData>>) -> std::io::Result
                 }
             }
         }
+
+        for count in read_create(data.store_path.join("user_counters.txt")).await? {
+            let mut parts = count.trim().splitn(4, ' ');
+            let (Some(name), Some(user_id), Some(value), Some(display_name)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let Ok(value) = value.parse() else {
+                continue;
+            };
+
+            data.user_counters
+                .entry(String::from(name))
+                .or_default()
+                .insert(
+                    String::from(user_id),
+                    super::UserCount {
+                        display_name: String::from(display_name),
+                        value,
+                    },
+                );
+        }
     }
 
     if data.options.features.listeners {
-        data.options.debug("Eye: Loading listeners");
+        log::debug(Target::Eye, "Loading listeners");
 
         for listener in read_create(data.store_path.join("listeners.txt")).await? {
-            let listener = listener.trim();
+            if listener.trim().is_empty() {
+                continue;
+            }
+            let Some((name, listener)) = super::listener::Listener::parse_line(&listener) else {
+                log::warn(
+                    Target::Eye,
+                    format!("Skipped an invalid listener: {listener:?}"),
+                );
+                continue;
+            };
+            data.listeners.insert(name, Arc::new(listener));
+        }
 
-            let kind = listener.chars().next().unwrap();
-            let Some((name, pattern, command)) = super::listener::Listener::parts(&listener.chars().skip(2).collect::<String>()) else { continue; };
+        for revision in read_create(data.store_path.join("listener_history.txt")).await? {
+            data.listener_history.load_line(&revision);
+        }
+    }
 
-            let predicate = match kind {
-                'e' => super::listener::Predicate::Exactly(pattern),
-                'c' => super::listener::Predicate::Contains(pattern),
-                'r' => {
-                    let Ok(regex) = regex::Regex::new(&pattern) else { continue; };
-                    super::listener::Predicate::Regex(regex)
-                }
-                _ => continue,
+    if data.options.features.timers {
+        log::debug(Target::Eye, "Loading timers");
+
+        for timer in read_create(data.store_path.join("timers.txt")).await? {
+            let mut parts = timer.trim().splitn(4, ' ');
+            let (Some(name), Some(interval), Some(min_lines), Some(command)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let (Some(interval), Ok(min_lines), Ok(body)) = (
+                super::cooldown::parse_duration(interval),
+                min_lines.parse(),
+                super::command::CommandRules::parse(command),
+            ) else {
+                continue;
             };
 
-            let Ok(body) = super::command::CommandRules::parse(&command) else { continue; };
+            if let Some(part) = body.caller_only_part() {
+                log::warn(
+                    Target::Eye,
+                    format!("Timer {name:?} uses {part}, which won't work without a caller"),
+                );
+            }
 
-            data.listeners
-                .insert(name, super::listener::Listener { predicate, body });
+            // chat lines are counted from 0 again, and the interval from now,
+            // so each timer first posts once both have passed since the restart
+            let timer = super::timer::Timer::new(interval, min_lines, body, 0);
+            data.timers.insert(String::from(name), timer);
         }
     }
 
@@ -161,6 +340,7 @@ async fn read_create<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<String>> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(path)
         .await?;
     let file = tokio::io::BufReader::new(file);
@@ -173,3 +353,41 @@ async fn read_create<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<String>> {
 
     Ok(buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn command_stats_round_trip() {
+        let data = super::super::StoreData::mock();
+        let mut data = data.write().await;
+        for (name, uses, last_used, last_user) in [
+            ("hug", 3, 1_700_000_000, "Chatter"),
+            ("bonk", 1, 1_700_000_100, ""),
+            ("new", 0, 0, ""),
+        ] {
+            data.command_stats.insert(
+                String::from(name),
+                super::super::CommandStats {
+                    uses,
+                    last_used,
+                    last_user: String::from(last_user),
+                },
+            );
+        }
+
+        // malformed lines are skipped
+        let malformed = ["", "hug", "hug 3", "hug x 5 Chatter", "hug 3 -1 Chatter"];
+        let store = command_stats(&data);
+        let loaded: std::collections::HashMap<_, _> = store
+            .lines()
+            .chain(malformed)
+            .filter_map(parse_command_stats)
+            .collect();
+        assert_eq!(loaded.len(), data.command_stats.len());
+        for (name, stats) in &data.command_stats {
+            assert_eq!(loaded.get(name.as_str()), Some(stats), "{name}");
+        }
+    }
+}
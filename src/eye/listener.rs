@@ -16,16 +16,75 @@ pub enum Predicate {
 }
 
 impl Listener {
+    /// Returns whether the listener matched `msg`.
     pub(super) async fn execute(
         &self,
         msg: &ChatMessage,
         bot: &BotInterface,
         data: super::StoreInner,
-    ) {
+    ) -> bool {
         let message = msg.text.trim();
-        let Some(args) = self.predicate.args(message) else { return; };
+        let Some(args) = self.predicate.args(message) else { return false; };
 
         self.body.execute(args, msg, bot, data).await;
+        true
+    }
+
+    /// The listener as it's stored, without its name: its kind, then its
+    /// pattern and command, e.g. `e hello/Hi!`.
+    pub(super) fn definition(&self) -> String {
+        let (kind, pattern) = match &self.predicate {
+            Predicate::Exactly(pat) => ('e', pat.as_str()),
+            Predicate::Contains(pat) => ('c', pat.as_str()),
+            Predicate::Regex(pat) => ('r', pat.as_str()),
+        };
+        format!(
+            "{kind} {}/{}",
+            pattern.replace('\\', "\\\\").replace('/', "\\/"),
+            self.body.as_words_string()
+        )
+    }
+
+    /// Parses a listener from its [`Listener::definition`].
+    pub(super) fn parse(definition: &str) -> Option<Self> {
+        lazy_static::lazy_static! {
+            static ref DEFINITION: Regex = Regex::new(r"^(.) ((?:[^/\\]|\\.)+)/(.+)$").expect("Static regex");
+            static ref UNESCAPE: Regex = Regex::new(r"\\(.)").expect("Static regex");
+        }
+
+        let captures = DEFINITION.captures(definition)?;
+        let kind = captures.get(1)?.as_str();
+        let pattern = UNESCAPE
+            .replace_all(captures.get(2)?.as_str(), "$1")
+            .into_owned();
+        let command = captures.get(3)?.as_str();
+
+        let predicate = match kind {
+            "e" => Predicate::Exactly(pattern),
+            "c" => Predicate::Contains(pattern),
+            "r" => Predicate::Regex(Regex::new(&pattern).ok()?),
+            _ => return None,
+        };
+        let body = super::command::CommandRules::parse(command).ok()?;
+        Some(Listener { predicate, body })
+    }
+
+    /// The listener's line in `listeners.txt`: its [`Listener::definition`],
+    /// with `name` after its kind, e.g. `e greeting hello/Hi!`.
+    pub(super) fn line(&self, name: &str) -> String {
+        let definition = self.definition();
+        let (kind, rest) = definition
+            .split_once(' ')
+            .expect("Definitions start with a kind");
+        format!("{kind} {name} {rest}")
+    }
+
+    /// Parses a listener and its name from its [`Listener::line`].
+    pub(super) fn parse_line(line: &str) -> Option<(String, Self)> {
+        let (kind, rest) = line.trim().split_once(' ')?;
+        let (name, rest) = rest.split_once(' ')?;
+        let listener = Listener::parse(&format!("{kind} {rest}"))?;
+        Some((String::from(name), listener))
     }
 
     pub(super) fn parts(args: &str) -> Option<(String, String, String)> {
@@ -59,3 +118,29 @@ impl Predicate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_round_trip() {
+        for line in [
+            r"e greeting hello/Hi!",
+            r"c slash a \/ b \\ c/&REPLY Hi %0",
+            r"r digits (\\d+) apples/You have %1 apples.",
+        ] {
+            let (name, listener) = Listener::parse_line(line).unwrap();
+            assert_eq!(listener.line(&name), line);
+        }
+
+        let (name, listener) = Listener::parse_line(r"c slash a \/ b \\ c/ok").unwrap();
+        assert_eq!(name, "slash");
+        assert!(matches!(
+            listener.predicate,
+            Predicate::Contains(pattern) if pattern == r"a / b \ c"
+        ));
+        assert!(Listener::parse_line("e nameonly").is_none());
+        assert!(Listener::parse_line(r"r broken (/oops").is_none());
+    }
+}
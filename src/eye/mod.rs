@@ -1,16 +1,26 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 mod builtin;
 pub mod comet;
 mod command;
+mod cooldown;
+mod history;
 mod io;
 mod listener;
+mod permission;
+mod router;
+mod stream;
+mod timer;
 
 type StoreInner = Arc<RwLock<StoreData>>;
 
+/// How long after their last message a chatter can be picked by `%chatter`.
+const PRESENT_FOR: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Debug)]
 pub struct Store(StoreInner);
 
@@ -19,10 +29,51 @@ struct StoreData {
     pub options: crate::options::Options,
     pub store_path: PathBuf,
     pub error_reporter: tokio::sync::mpsc::Sender<crate::bot::error::BotError>,
+    /// Held while writing to the store, so that writes don't overlap.
+    pub io_lock: Arc<tokio::sync::Mutex<()>>,
 
     pub commands: HashMap<String, Arc<command::CommandRules>>,
+    /// Commands, and arguments to start them with, by alias name.
+    pub aliases: HashMap<String, String>,
+    /// How custom commands have been used, by command name.
+    pub command_stats: HashMap<String, CommandStats>,
+    pub command_history: history::History,
     pub counters: HashMap<String, i64>,
-    pub listeners: HashMap<String, listener::Listener>,
+    /// Chatters' values of per-user counters, by counter name, then user id.
+    pub user_counters: HashMap<String, HashMap<String, UserCount>>,
+    pub listeners: HashMap<String, Arc<listener::Listener>>,
+    pub listener_history: history::History,
+    pub timers: HashMap<String, timer::Timer>,
+    /// How many chat messages have been sent since the bot started.
+    pub chat_lines: u64,
+    /// Chatters who sent a message in the last [`PRESENT_FOR`], by user id.
+    pub chatters: HashMap<String, Chatter>,
+    pub rng: crate::random::Rng,
+    pub helix_cache: Arc<stream::HelixCache>,
+    pub router: router::Router,
+    pub cooldowns: cooldown::Cooldowns,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CommandStats {
+    pub uses: u64,
+    /// When the command was last used, in seconds since the Unix epoch.
+    pub last_used: u64,
+    /// The display name of the chatter who last used the command.
+    pub last_user: String,
+}
+
+#[derive(Debug)]
+struct UserCount {
+    /// The chatter's display name when their value last changed.
+    pub display_name: String,
+    pub value: i64,
+}
+
+#[derive(Debug)]
+struct Chatter {
+    pub display_name: String,
+    pub last_seen: Instant,
 }
 
 impl Store {
@@ -31,16 +82,19 @@ impl Store {
         bot: &crate::bot::Bot,
         options: crate::options::Options,
     ) -> std::io::Result<Self> {
-        let store = Store(Arc::new(RwLock::new(StoreData {
-            commands: HashMap::new(),
-            counters: HashMap::new(),
-            listeners: HashMap::new(),
-
-            store_path: store_path.into(),
-            error_reporter: bot.error_reporter(),
+        let store = Store(Arc::new(RwLock::new(StoreData::new(
+            store_path.into(),
+            bot.error_reporter(),
             options,
-        })));
+        ))));
         io::load(store.0.clone()).await?;
+
+        let shutdown = bot.shutdown();
+        tokio::spawn(io::flush_on_shutdown(
+            store.0.clone(),
+            shutdown.hold(),
+            shutdown,
+        ));
         Ok(store)
     }
     pub fn register_base_commands(
@@ -50,11 +104,137 @@ impl Store {
         builtin::register_base_commands(self, bot)
     }
 
-    pub fn register_comet_commands(
+    /// Thanks raiders in chat, with what they were last playing.
+    pub fn register_raid_thanks(
         &self,
         bot: &crate::bot::Bot,
+    ) -> impl std::future::Future<Output = ()> + 'static {
+        builtin::register_raid_thanks(self, bot)
+    }
+
+    pub fn register_comet_commands(
+        &self,
         comet_server: &comet::Server,
     ) -> impl std::future::Future<Output = ()> + 'static {
-        builtin::register_comet_commands(self, bot, comet_server)
+        builtin::register_comet_commands(self, comet_server)
+    }
+}
+
+impl StoreData {
+    fn new(
+        store_path: PathBuf,
+        error_reporter: tokio::sync::mpsc::Sender<crate::bot::error::BotError>,
+        options: crate::options::Options,
+    ) -> Self {
+        StoreData {
+            commands: HashMap::new(),
+            aliases: HashMap::new(),
+            command_stats: HashMap::new(),
+            command_history: history::History::default(),
+            counters: HashMap::new(),
+            user_counters: HashMap::new(),
+            listeners: HashMap::new(),
+            listener_history: history::History::default(),
+            timers: HashMap::new(),
+            chat_lines: 0,
+            chatters: HashMap::new(),
+            rng: crate::random::Rng::default(),
+            helix_cache: Arc::default(),
+            router: router::Router::default(),
+            cooldowns: cooldown::Cooldowns::default(),
+
+            store_path,
+            error_reporter,
+            io_lock: Arc::new(tokio::sync::Mutex::new(())),
+            options,
+        }
+    }
+
+    /// A store with the default options that isn't backed by files, for
+    /// testing handlers. Writing it fails, and the errors are dropped.
+    #[cfg(test)]
+    fn mock() -> StoreInner {
+        Arc::new(RwLock::new(StoreData::new(
+            PathBuf::from("/nonexistent/eyebot-store"),
+            tokio::sync::mpsc::channel(1).0,
+            crate::options::Options::default(),
+        )))
+    }
+
+    /// Records that the chatter who sent `msg` used the custom command `name`.
+    fn record_command_use(&mut self, name: &str, msg: &crate::chat::data::ChatMessage) {
+        let now = unix_now();
+        let stats = self.command_stats.entry(String::from(name)).or_default();
+        stats.uses += 1;
+        stats.last_used = now;
+        stats.last_user.clone_from(&msg.display_name);
+
+        crate::metrics::METRICS
+            .custom_command_uses
+            .set(name, stats.uses);
+        crate::metrics::METRICS
+            .custom_command_last_used
+            .set(name, now);
+    }
+
+    /// Removes the custom command `name`, along with its usage statistics.
+    fn remove_command(&mut self, name: &str) -> Option<Arc<command::CommandRules>> {
+        self.command_stats.remove(name);
+        crate::metrics::METRICS.custom_command_uses.remove(name);
+        crate::metrics::METRICS
+            .custom_command_last_used
+            .remove(name);
+        self.commands.remove(name)
     }
+
+    /// The counter `name`, or `owner`'s value of it if `owner`, a user id and
+    /// display name, is given. `None` if the counter doesn't exist. Per-user
+    /// values start at 0.
+    fn counter_mut(&mut self, name: &str, owner: Option<&(String, String)>) -> Option<&mut i64> {
+        let counter = self.counters.get_mut(name)?;
+        let Some((user_id, display_name)) = owner else {
+            return Some(counter);
+        };
+        let count = self
+            .user_counters
+            .entry(String::from(name))
+            .or_default()
+            .entry(user_id.clone())
+            .or_insert(UserCount {
+                display_name: display_name.clone(),
+                value: 0,
+            });
+        count.display_name.clone_from(display_name);
+        Some(&mut count.value)
+    }
+
+    fn saw_chatter(&mut self, msg: &crate::chat::data::ChatMessage) {
+        let now = Instant::now();
+        self.chatters
+            .retain(|_, chatter| now.duration_since(chatter.last_seen) < PRESENT_FOR);
+        self.chatters.insert(
+            msg.user_id.clone(),
+            Chatter {
+                display_name: msg.display_name.clone(),
+                last_seen: now,
+            },
+        );
+    }
+
+    /// The display names of chatters who sent a message recently.
+    fn present_chatters(&self) -> Vec<&str> {
+        let now = Instant::now();
+        self.chatters
+            .values()
+            .filter(|chatter| now.duration_since(chatter.last_seen) < PRESENT_FOR)
+            .map(|chatter| chatter.display_name.as_str())
+            .collect()
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
 }
@@ -0,0 +1,261 @@
+//! Who can use a command. Each [`Permission`] also allows everyone above it
+//! on the ladder, e.g. moderators can use subscriber-only commands.
+use crate::bot::interface::BotInterface;
+use crate::chat::data::ChatMessage;
+use crate::log::{self, Target};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Permission {
+    #[default]
+    Everyone,
+    Follower,
+    Subscriber,
+    Vip,
+    /// Moderators and the broadcaster.
+    Moderator,
+    Broadcaster,
+}
+
+/// Per-user exceptions to a [`Permission`], by login name.
+#[derive(Debug, Clone, Default)]
+pub struct Exceptions {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::Everyone,
+        Permission::Follower,
+        Permission::Subscriber,
+        Permission::Vip,
+        Permission::Moderator,
+        Permission::Broadcaster,
+    ];
+
+    /// The highest permission that `msg`'s author has without asking Twitch,
+    /// i.e. never [`Permission::Follower`].
+    #[must_use]
+    pub fn of(msg: &ChatMessage) -> Self {
+        if msg.user_is_broadcaster() {
+            Permission::Broadcaster
+        } else if msg.is_moderator {
+            Permission::Moderator
+        } else if msg.is_vip {
+            Permission::Vip
+        } else if msg.is_subscriber {
+            Permission::Subscriber
+        } else {
+            Permission::Everyone
+        }
+    }
+
+    /// Like [`Permission::of`], but asks Twitch whether a chatter without any
+    /// other role is a follower.
+    pub async fn fetch(msg: &ChatMessage, bot: &BotInterface) -> Self {
+        match Permission::of(msg) {
+            Permission::Everyone if is_follower(msg, bot).await => Permission::Follower,
+            permission => permission,
+        }
+    }
+
+    /// Whether `msg`'s author has at least this permission. Only asks Twitch
+    /// when checking [`Permission::Follower`].
+    pub async fn allows(self, msg: &ChatMessage, bot: &BotInterface) -> bool {
+        if Permission::of(msg) >= self {
+            return true;
+        }
+        self == Permission::Follower && is_follower(msg, bot).await
+    }
+
+    /// The custom command tag for this permission, without the `&`.
+    #[must_use]
+    pub fn tag(self) -> &'static str {
+        match self {
+            Permission::Everyone => "EVERYONE",
+            Permission::Follower => "FOLLOWER",
+            Permission::Subscriber => "SUB",
+            Permission::Vip => "VIP",
+            Permission::Moderator => "SUPER",
+            Permission::Broadcaster => "BROADCASTER",
+        }
+    }
+    #[must_use]
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.tag() == tag)
+    }
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Permission::Everyone => "everyone",
+            Permission::Follower => "follower",
+            Permission::Subscriber => "subscriber",
+            Permission::Vip => "vip",
+            Permission::Moderator => "moderator",
+            Permission::Broadcaster => "broadcaster",
+        }
+    }
+    /// Also accepts the short names `sub` and `mod`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sub" => Some(Permission::Subscriber),
+            "mod" => Some(Permission::Moderator),
+            name => Permission::ALL
+                .into_iter()
+                .find(|permission| permission.name() == name),
+        }
+    }
+}
+
+impl Exceptions {
+    /// `Some(true)` if `msg`'s author is the broadcaster or is allowed,
+    /// `Some(false)` if they are denied, and `None` if their permission decides.
+    #[must_use]
+    pub fn check(&self, msg: &ChatMessage) -> Option<bool> {
+        if msg.user_is_broadcaster() || self.allow.contains(&msg.login) {
+            Some(true)
+        } else if self.deny.contains(&msg.login) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+async fn is_follower(msg: &ChatMessage, bot: &BotInterface) -> bool {
+    match crate::twitch::follower(&msg.room_id, &msg.user_id, bot.helix_auth()).await {
+        Ok(follower) => follower.is_some(),
+        Err(err) => {
+            log::warn(
+                Target::Eye,
+                format!(
+                    "Could not check if {} follows the channel: {err}",
+                    msg.user_id
+                ),
+            );
+            false
+        }
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exceptions, Permission};
+    use crate::bot::interface::BotInterface;
+    use crate::chat::data::ChatMessage;
+    use crate::eye::command::CommandRules;
+
+    fn chatter(login: &str) -> ChatMessage {
+        ChatMessage {
+            login: String::from(login),
+            ..ChatMessage::default()
+        }
+    }
+    fn broadcaster() -> ChatMessage {
+        let mut msg = chatter("streamer");
+        msg.badges
+            .insert(String::from("broadcaster"), String::from("1"));
+        msg
+    }
+
+    #[test]
+    fn the_highest_role_wins() {
+        let mut msg = chatter("chatter");
+        assert_eq!(Permission::of(&msg), Permission::Everyone);
+        msg.is_subscriber = true;
+        assert_eq!(Permission::of(&msg), Permission::Subscriber);
+        msg.is_vip = true;
+        assert_eq!(Permission::of(&msg), Permission::Vip);
+        msg.is_moderator = true;
+        assert_eq!(Permission::of(&msg), Permission::Moderator);
+        assert_eq!(Permission::of(&broadcaster()), Permission::Broadcaster);
+
+        assert!(Permission::ALL.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn higher_roles_are_allowed() {
+        let bot = BotInterface::mock().await;
+        let vip = ChatMessage {
+            is_vip: true,
+            ..chatter("vip")
+        };
+
+        for permission in [
+            Permission::Everyone,
+            Permission::Subscriber,
+            Permission::Vip,
+        ] {
+            assert!(permission.allows(&vip, &bot).await, "{permission}");
+        }
+        for permission in [Permission::Moderator, Permission::Broadcaster] {
+            assert!(!permission.allows(&vip, &bot).await, "{permission}");
+        }
+        for permission in Permission::ALL {
+            assert!(
+                permission.allows(&broadcaster(), &bot).await,
+                "{permission}"
+            );
+        }
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        for permission in Permission::ALL {
+            assert_eq!(Permission::from_name(permission.name()), Some(permission));
+            assert_eq!(Permission::from_tag(permission.tag()), Some(permission));
+        }
+        assert_eq!(Permission::from_name("sub"), Some(Permission::Subscriber));
+        assert_eq!(Permission::from_name("MOD"), Some(Permission::Moderator));
+        assert_eq!(Permission::from_name("VIP"), Some(Permission::Vip));
+        assert_eq!(Permission::from_name("admin"), None);
+        // tags are case-sensitive, like every other tag
+        assert_eq!(Permission::from_tag("sub"), None);
+        assert_eq!(Permission::from_tag("MOD"), None);
+    }
+
+    #[test]
+    fn tags_round_trip_through_commands() {
+        for (input, permission) in [
+            ("&SUB Hi!", Permission::Subscriber),
+            ("&VIP Hi!", Permission::Vip),
+            ("&BROADCASTER Hi!", Permission::Broadcaster),
+            ("&SUPER Hi!", Permission::Moderator),
+            ("Hi!", Permission::Everyone),
+        ] {
+            let rules = CommandRules::parse(input).unwrap();
+            assert_eq!(rules.permission(), permission);
+            assert_eq!(rules.as_words_string(), input);
+        }
+    }
+
+    #[test]
+    fn exceptions_override_permissions() {
+        let exceptions = Exceptions {
+            allow: vec![String::from("friend"), String::from("both")],
+            deny: vec![
+                String::from("pest"),
+                String::from("both"),
+                String::from("streamer"),
+            ],
+        };
+
+        assert_eq!(exceptions.check(&chatter("friend")), Some(true));
+        assert_eq!(exceptions.check(&chatter("pest")), Some(false));
+        assert_eq!(exceptions.check(&chatter("both")), Some(true));
+        assert_eq!(exceptions.check(&chatter("someone")), None);
+        // the broadcaster can't be denied
+        assert_eq!(exceptions.check(&broadcaster()), Some(true));
+        assert_eq!(Exceptions::default().check(&broadcaster()), Some(true));
+    }
+}
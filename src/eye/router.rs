@@ -0,0 +1,531 @@
+//! Builtin commands, declared with their arguments, permission level, and help
+//! text. The [`Router`] parses a chat message's arguments before running a
+//! command, replying with the command's usage if they don't match.
+use super::cooldown::{self, Cooldown};
+use super::permission::Permission;
+use crate::bot::interface::BotInterface;
+use crate::chat::data::ChatMessage;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type CommandFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Handler = Box<dyn Fn(Invocation) -> CommandFuture + Send + Sync>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: Vec<&'static str>,
+    pub args: Vec<Arg>,
+    pub permission: Permission,
+    pub help: &'static str,
+    pub cooldown: Cooldown,
+    handler: Handler,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub is_optional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A single word.
+    Word,
+    Integer,
+    /// The rest of the message. Must be the last argument.
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub enum ArgValue {
+    Word(String),
+    Integer(i64),
+    Text(String),
+}
+
+/// A command's parsed arguments, in the order of its [`Arg`]s.
+#[derive(Debug, Clone)]
+pub struct Args(Vec<Option<ArgValue>>);
+
+/// Everything a [`Command`] handler gets when it runs.
+#[derive(Debug)]
+pub struct Invocation {
+    pub msg: ChatMessage,
+    pub bot: BotInterface,
+    pub args: Args,
+    pub(super) data: super::StoreInner,
+    command: Arc<Command>,
+}
+
+#[derive(Debug)]
+pub enum UsageError {
+    MissingArg(&'static str),
+    NotAnInteger { name: &'static str, value: String },
+}
+
+#[derive(Default)]
+pub struct Router {
+    commands: Vec<Arc<Command>>,
+}
+
+impl Command {
+    pub fn new<F, Fut>(name: &'static str, help: &'static str, handler: F) -> Self
+    where
+        F: Fn(Invocation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Command {
+            name,
+            aliases: Vec::new(),
+            args: Vec::new(),
+            permission: Permission::Everyone,
+            help,
+            cooldown: Cooldown::default(),
+            handler: Box::new(move |invocation| Box::pin(handler(invocation))),
+        }
+    }
+    #[must_use]
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+    #[must_use]
+    pub fn arg(mut self, arg: Arg) -> Self {
+        self.args.push(arg);
+        self
+    }
+    #[must_use]
+    pub fn permission(mut self, permission: Permission) -> Self {
+        self.permission = permission;
+        self
+    }
+    /// How often the command can be used by anyone.
+    #[must_use]
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown.global = Some(cooldown);
+        self
+    }
+    /// How often each chatter can use the command.
+    #[must_use]
+    pub fn user_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown.user = Some(cooldown);
+        self
+    }
+
+    /// E.g. `!counter:set <counter-name> <value>`.
+    #[must_use]
+    pub fn usage(&self) -> String {
+        std::iter::once(format!("!{}", self.name))
+            .chain(self.args.iter().map(Arg::to_string))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    /// The command's usage, help text, permission, aliases, and cooldowns.
+    #[must_use]
+    pub fn help_string(&self) -> String {
+        let mut help = format!("{}: {}", self.usage(), self.help);
+        if self.permission != Permission::Everyone {
+            help += &format!(" ({} only)", self.permission);
+        }
+        if !self.aliases.is_empty() {
+            help += &format!(
+                " (aliases: {})",
+                self.aliases
+                    .iter()
+                    .map(|alias| format!("!{alias}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        if let Some(cooldown) = self.cooldown.global {
+            help += &format!(" (cooldown: {})", cooldown::format_duration(cooldown));
+        }
+        if let Some(cooldown) = self.cooldown.user {
+            help += &format!(
+                " (per-user cooldown: {})",
+                cooldown::format_duration(cooldown)
+            );
+        }
+        help
+    }
+
+    pub async fn can_run(&self, msg: &ChatMessage, bot: &BotInterface) -> bool {
+        self.permission.allows(msg, bot).await
+    }
+
+    /// Parses `input`, the text after the command's name. Extra words are
+    /// ignored.
+    ///
+    /// # Errors
+    /// Returns `Err(UsageError...)`:
+    /// * `::MissingArg` if a required argument isn't in `input`.
+    /// * `::NotAnInteger` if an `ArgKind::Integer` argument couldn't be parsed.
+    pub fn parse_args(&self, input: &str) -> Result<Args, UsageError> {
+        let mut rest = input.trim();
+        let mut values = Vec::with_capacity(self.args.len());
+
+        for arg in &self.args {
+            let value = if arg.kind == ArgKind::Text {
+                std::mem::take(&mut rest)
+            } else {
+                let (word, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+                rest = remaining.trim_start();
+                word
+            };
+
+            if value.is_empty() {
+                if arg.is_optional {
+                    values.push(None);
+                    continue;
+                }
+                return Err(UsageError::MissingArg(arg.name));
+            }
+
+            values.push(Some(match arg.kind {
+                ArgKind::Word => ArgValue::Word(String::from(value)),
+                ArgKind::Text => ArgValue::Text(String::from(value)),
+                ArgKind::Integer => match value.parse() {
+                    Ok(value) => ArgValue::Integer(value),
+                    Err(_) => {
+                        return Err(UsageError::NotAnInteger {
+                            name: arg.name,
+                            value: String::from(value),
+                        })
+                    }
+                },
+            }));
+        }
+
+        Ok(Args(values))
+    }
+}
+
+impl Arg {
+    #[must_use]
+    pub const fn word(name: &'static str) -> Self {
+        Arg {
+            name,
+            kind: ArgKind::Word,
+            is_optional: false,
+        }
+    }
+    #[must_use]
+    pub const fn integer(name: &'static str) -> Self {
+        Arg {
+            name,
+            kind: ArgKind::Integer,
+            is_optional: false,
+        }
+    }
+    #[must_use]
+    pub const fn text(name: &'static str) -> Self {
+        Arg {
+            name,
+            kind: ArgKind::Text,
+            is_optional: false,
+        }
+    }
+    #[must_use]
+    pub const fn optional(self) -> Self {
+        Arg {
+            is_optional: true,
+            ..self
+        }
+    }
+}
+
+impl Args {
+    /// The word or text argument at `index`, if it was given.
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&str> {
+        match self.0.get(index)?.as_ref()? {
+            ArgValue::Word(value) | ArgValue::Text(value) => Some(value),
+            ArgValue::Integer(_) => None,
+        }
+    }
+    /// The required word or text argument at `index`.
+    #[must_use]
+    pub fn word(&self, index: usize) -> &str {
+        self.get(index)
+            .expect("Required word arguments are always parsed")
+    }
+    /// The integer argument at `index`, if it was given.
+    #[must_use]
+    pub fn get_integer(&self, index: usize) -> Option<i64> {
+        match self.0.get(index)?.as_ref()? {
+            ArgValue::Integer(value) => Some(*value),
+            ArgValue::Word(_) | ArgValue::Text(_) => None,
+        }
+    }
+    /// The required integer argument at `index`.
+    #[must_use]
+    pub fn integer(&self, index: usize) -> i64 {
+        match self.0.get(index) {
+            Some(Some(ArgValue::Integer(value))) => *value,
+            _ => panic!("Required integer arguments are always parsed"),
+        }
+    }
+}
+
+impl Invocation {
+    /// The usage of the command being run.
+    #[must_use]
+    pub fn usage(&self) -> String {
+        self.command.usage()
+    }
+    pub async fn reply<S: Into<String>>(&self, message: S) {
+        self.bot.reply(&self.msg, message).await;
+    }
+}
+
+impl Router {
+    /// Adds `command`, replacing any command with the same name.
+    ///
+    /// # Panics
+    /// Panics if the name or one of the aliases of `command` is already used
+    /// by another command, since only one of them could ever run.
+    pub fn add(&mut self, command: Command) {
+        self.commands
+            .retain(|existing| existing.name != command.name);
+        let names = std::iter::once(&command.name).chain(&command.aliases);
+        for name in names {
+            if let Some(existing) = self.get(name) {
+                panic!(
+                    "!{name} is used by both !{} and !{}",
+                    existing.name, command.name
+                );
+            }
+        }
+        self.commands.push(Arc::new(command));
+    }
+    /// Finds a command by its name or one of its aliases.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Arc<Command>> {
+        self.commands
+            .iter()
+            .find(|command| command.name == name || command.aliases.contains(&name))
+            .cloned()
+    }
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter().map(AsRef::as_ref)
+    }
+}
+
+/// Runs the builtin command that `msg` invokes, if any. Returns whether `msg`
+/// invoked a builtin command.
+pub(super) async fn route(msg: ChatMessage, bot: BotInterface, data: super::StoreInner) -> bool {
+    let Some(text) = msg.text.strip_prefix('!') else {
+        return false;
+    };
+    let text = text.trim();
+    let (name, input) = text.split_once(' ').unwrap_or((text, ""));
+
+    let Some(command) = data.read().await.router.get(name) else {
+        return false;
+    };
+    if !command.can_run(&msg, &bot).await {
+        return true;
+    }
+
+    match command.parse_args(input) {
+        Ok(args) => {
+            if !cooldown::check(command.name, command.cooldown, &msg, &bot, &data).await {
+                return true;
+            }
+            crate::metrics::METRICS.commands.inc(command.name);
+            (command.handler)(Invocation {
+                msg,
+                bot,
+                args,
+                data,
+                command: command.clone(),
+            })
+            .await;
+        }
+        Err(err) => {
+            bot.reply(&msg, format!("{err} Usage: {}", command.usage()))
+                .await;
+        }
+    }
+    true
+}
+
+impl std::fmt::Display for Arg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = if self.kind == ArgKind::Text {
+            format!("{}...", self.name)
+        } else {
+            String::from(self.name)
+        };
+        if self.is_optional {
+            f.write_fmt(format_args!("[{name}]"))
+        } else {
+            f.write_fmt(format_args!("<{name}>"))
+        }
+    }
+}
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsageError::MissingArg(name) => f.write_fmt(format_args!("Missing <{name}>.")),
+            UsageError::NotAnInteger { name, value } => f.write_fmt(format_args!(
+                "Expected an integer for <{name}>, got {value:?}."
+            )),
+        }
+    }
+}
+impl std::error::Error for UsageError {}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("args", &self.args)
+            .field("permission", &self.permission)
+            .field("cooldown", &self.cooldown)
+            .finish_non_exhaustive()
+    }
+}
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.commands()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arg, ArgValue, Command, Router, UsageError};
+
+    fn with_args(args: Vec<Arg>) -> Command {
+        args.into_iter()
+            .fold(Command::new("test", "Tests.", |_| async {}), Command::arg)
+    }
+
+    fn values(command: &Command, input: &str) -> Vec<Option<String>> {
+        let args = command.parse_args(input).expect("Arguments are valid");
+        args.0
+            .iter()
+            .map(|value| {
+                value.as_ref().map(|value| match value {
+                    ArgValue::Word(word) => format!("word {word}"),
+                    ArgValue::Integer(integer) => format!("integer {integer}"),
+                    ArgValue::Text(text) => format!("text {text}"),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn args_are_parsed() {
+        let command = with_args(vec![
+            Arg::word("name"),
+            Arg::integer("count"),
+            Arg::text("message").optional(),
+        ]);
+        assert_eq!(
+            values(&command, "  hello   -3   a  b  "),
+            [
+                Some(String::from("word hello")),
+                Some(String::from("integer -3")),
+                Some(String::from("text a  b")),
+            ]
+        );
+        assert_eq!(
+            values(&command, "hello 3"),
+            [
+                Some(String::from("word hello")),
+                Some(String::from("integer 3")),
+                None,
+            ]
+        );
+        // extra words are ignored
+        assert_eq!(
+            values(&with_args(vec![Arg::word("name")]), "a b c"),
+            [Some(String::from("word a"))]
+        );
+
+        let optional = with_args(vec![
+            Arg::word("first").optional(),
+            Arg::integer("second").optional(),
+        ]);
+        assert_eq!(values(&optional, ""), [None, None]);
+        assert_eq!(values(&optional, "a"), [Some(String::from("word a")), None]);
+    }
+
+    #[test]
+    fn bad_args_are_rejected() {
+        let command = with_args(vec![Arg::word("name"), Arg::integer("count")]);
+
+        let err = command.parse_args("").unwrap_err();
+        assert!(matches!(err, UsageError::MissingArg("name")));
+        let err = command.parse_args("hello   ").unwrap_err();
+        assert!(matches!(err, UsageError::MissingArg("count")));
+        assert_eq!(err.to_string(), "Missing <count>.");
+
+        let err = command.parse_args("hello 1.5").unwrap_err();
+        assert!(
+            matches!(&err, UsageError::NotAnInteger { name: "count", value } if value == "1.5")
+        );
+        assert_eq!(
+            err.to_string(),
+            "Expected an integer for <count>, got \"1.5\"."
+        );
+        assert!(matches!(
+            command.parse_args("hello ten"),
+            Err(UsageError::NotAnInteger { name: "count", .. })
+        ));
+    }
+
+    #[test]
+    fn usage_shows_args() {
+        let command = with_args(vec![
+            Arg::word("name"),
+            Arg::integer("count").optional(),
+            Arg::text("message"),
+        ]);
+        assert_eq!(command.usage(), "!test <name> [count] <message...>");
+        assert_eq!(
+            with_args(vec![Arg::text("message").optional()]).usage(),
+            "!test [message...]"
+        );
+        assert_eq!(with_args(Vec::new()).usage(), "!test");
+    }
+
+    #[test]
+    fn commands_are_found_by_name_or_alias() {
+        let mut router = Router::default();
+        router.add(Command::new("first", "First.", |_| async {}).alias("1"));
+        router.add(Command::new("second", "Second.", |_| async {}));
+        assert_eq!(router.get("1").unwrap().name, "first");
+        assert!(router.get("third").is_none());
+
+        // replacing by name drops the old aliases
+        router.add(Command::new("first", "Replaced.", |_| async {}).alias("one"));
+        assert_eq!(router.get("first").unwrap().help, "Replaced.");
+        assert_eq!(router.get("one").unwrap().help, "Replaced.");
+        assert!(router.get("1").is_none());
+        let names: Vec<_> = router.commands().map(|command| command.name).collect();
+        assert_eq!(names, ["second", "first"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "!1 is used by both !first and !second")]
+    fn aliases_cannot_collide() {
+        let mut router = Router::default();
+        router.add(Command::new("first", "First.", |_| async {}).alias("1"));
+        router.add(Command::new("second", "Second.", |_| async {}).alias("1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "!first is used by both !first and !second")]
+    fn aliases_cannot_shadow_names() {
+        let mut router = Router::default();
+        router.add(Command::new("first", "First.", |_| async {}));
+        router.add(Command::new("second", "Second.", |_| async {}).alias("first"));
+    }
+}
@@ -0,0 +1,314 @@
+//! Stream variables for custom commands, like `%game` and `%uptime`. Their
+//! values come from Helix, and are cached for a short time so that a command
+//! spammed in chat doesn't send a request every time.
+use crate::bot::interface::BotInterface;
+use crate::log::{self, Target};
+use crate::twitch::{HelixAuth, TwitchChannel, TwitchFollower, TwitchStream, TwitchUser};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// How long a Helix response is reused for.
+const CACHE_FOR: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamVariable {
+    Channel,
+    Game,
+    Title,
+    Uptime,
+    Viewers,
+    Followage,
+    Accountage,
+}
+
+/// Who `%followage` and `%accountage` are about.
+#[derive(Debug, Clone, Copy)]
+pub enum TargetUser<'a> {
+    Login(&'a str),
+    Id(&'a str),
+}
+
+#[derive(Debug, Default)]
+pub struct HelixCache {
+    channel: Cache<Option<TwitchChannel>>,
+    stream: Cache<Option<TwitchStream>>,
+    users: Cache<Option<TwitchUser>>,
+    followers: Cache<Option<TwitchFollower>>,
+}
+
+#[derive(Debug)]
+struct Cache<V>(Mutex<HashMap<String, (Instant, V)>>);
+
+impl StreamVariable {
+    pub const ALL: [StreamVariable; 7] = [
+        StreamVariable::Channel,
+        StreamVariable::Game,
+        StreamVariable::Title,
+        StreamVariable::Uptime,
+        StreamVariable::Viewers,
+        StreamVariable::Followage,
+        StreamVariable::Accountage,
+    ];
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        StreamVariable::ALL
+            .into_iter()
+            .find(|variable| variable.name() == name)
+    }
+    /// The variable's name, without the `%`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamVariable::Channel => "channel",
+            StreamVariable::Game => "game",
+            StreamVariable::Title => "title",
+            StreamVariable::Uptime => "uptime",
+            StreamVariable::Viewers => "viewers",
+            StreamVariable::Followage => "followage",
+            StreamVariable::Accountage => "accountage",
+        }
+    }
+}
+
+impl HelixCache {
+    /// The values of `variables`. Variables without a value, e.g. `%uptime`
+    /// while the stream is offline, or ones that Helix couldn't be asked
+    /// about, are left out.
+    pub async fn values(
+        &self,
+        variables: &[StreamVariable],
+        target: Option<TargetUser<'_>>,
+        bot: &BotInterface,
+    ) -> HashMap<StreamVariable, String> {
+        let mut values = HashMap::new();
+        for &variable in variables {
+            match self.value(variable, target, bot).await {
+                Ok(Some(value)) => {
+                    values.insert(variable, value);
+                }
+                Ok(None) => (),
+                Err(err) => log::warn(
+                    Target::Eye,
+                    format!("Could not get the value of %{}: {err}", variable.name()),
+                ),
+            }
+        }
+        values
+    }
+
+    async fn value(
+        &self,
+        variable: StreamVariable,
+        target: Option<TargetUser<'_>>,
+        bot: &BotInterface,
+    ) -> Result<Option<String>> {
+        let broadcaster_id = bot.broadcaster_id().await?;
+        let auth = bot.helix_auth();
+        let now = SystemTime::now();
+
+        Ok(match variable {
+            StreamVariable::Channel => self
+                .channel(broadcaster_id, auth)
+                .await?
+                .map(|channel| channel.broadcaster_name),
+            StreamVariable::Game => self
+                .channel(broadcaster_id, auth)
+                .await?
+                .map(|channel| channel.game_name),
+            StreamVariable::Title => self
+                .channel(broadcaster_id, auth)
+                .await?
+                .map(|channel| channel.title),
+            StreamVariable::Uptime => self
+                .stream(broadcaster_id, auth)
+                .await?
+                .map(|stream| time_since(&stream.started_at, now))
+                .transpose()?,
+            StreamVariable::Viewers => self
+                .stream(broadcaster_id, auth)
+                .await?
+                .map(|stream| stream.viewer_count.to_string()),
+            StreamVariable::Followage => {
+                let Some(user) = self.user(target, auth).await? else {
+                    return Ok(None);
+                };
+                self.follower(broadcaster_id, &user.id, auth)
+                    .await?
+                    .map(|follower| time_since(&follower.followed_at, now))
+                    .transpose()?
+            }
+            StreamVariable::Accountage => self
+                .user(target, auth)
+                .await?
+                .map(|user| time_since(&user.created_at, now))
+                .transpose()?,
+        })
+    }
+
+    async fn channel(
+        &self,
+        broadcaster_id: &str,
+        auth: &HelixAuth,
+    ) -> Result<Option<TwitchChannel>> {
+        self.channel
+            .get_or_fetch(broadcaster_id, crate::twitch::channel(broadcaster_id, auth))
+            .await
+    }
+    async fn stream(&self, broadcaster_id: &str, auth: &HelixAuth) -> Result<Option<TwitchStream>> {
+        self.stream
+            .get_or_fetch(
+                broadcaster_id,
+                crate::twitch::stream_from_user_id(broadcaster_id, auth),
+            )
+            .await
+    }
+    pub async fn user(
+        &self,
+        target: Option<TargetUser<'_>>,
+        auth: &HelixAuth,
+    ) -> Result<Option<TwitchUser>> {
+        match target {
+            Some(TargetUser::Login(login)) => {
+                let login = login.to_lowercase();
+                // anything else isn't a login, and shouldn't go in a URL
+                if login.is_empty()
+                    || !login
+                        .chars()
+                        .all(|chr| chr.is_ascii_alphanumeric() || chr == '_')
+                {
+                    return Ok(None);
+                }
+                self.users
+                    .get_or_fetch(
+                        &format!("login:{login}"),
+                        crate::twitch::user_from_login(&login, auth),
+                    )
+                    .await
+            }
+            Some(TargetUser::Id(id)) => {
+                self.users
+                    .get_or_fetch(&format!("id:{id}"), crate::twitch::user_from_id(id, auth))
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+    async fn follower(
+        &self,
+        broadcaster_id: &str,
+        user_id: &str,
+        auth: &HelixAuth,
+    ) -> Result<Option<TwitchFollower>> {
+        self.followers
+            .get_or_fetch(
+                user_id,
+                crate::twitch::follower(broadcaster_id, user_id, auth),
+            )
+            .await
+    }
+}
+
+impl<V: Clone> Cache<V> {
+    /// The value cached for `key`, or the output of `fetch` if there isn't
+    /// one yet or it's too old. Errors aren't cached.
+    async fn get_or_fetch(&self, key: &str, fetch: impl Future<Output = Result<V>>) -> Result<V> {
+        {
+            let entries = self.0.lock().expect("The cache lock is never poisoned");
+            if let Some((fetched_at, value)) = entries.get(key) {
+                if fetched_at.elapsed() < CACHE_FOR {
+                    return Ok(value.clone());
+                }
+            }
+        }
+
+        let value = fetch.await?;
+        let mut entries = self.0.lock().expect("The cache lock is never poisoned");
+        entries.retain(|_, (fetched_at, _)| fetched_at.elapsed() < CACHE_FOR);
+        entries.insert(String::from(key), (Instant::now(), value.clone()));
+        Ok(value)
+    }
+}
+
+impl<V> Default for Cache<V> {
+    fn default() -> Self {
+        Cache(Mutex::new(HashMap::new()))
+    }
+}
+
+/// How long before `now` a Helix timestamp was, e.g. `2y 41d` or `1h 5m`.
+fn time_since(timestamp: &str, now: SystemTime) -> Result<String> {
+    let then = crate::twitch::parse_timestamp(timestamp)
+        .ok_or_else(|| format!("Invalid timestamp {timestamp:?}"))?;
+    Ok(format_elapsed(now.duration_since(then).unwrap_or_default()))
+}
+
+/// The two largest units of `duration`, e.g. `2y 41d` or `1h 5m`.
+pub fn format_elapsed(duration: Duration) -> String {
+    const UNITS: [(u64, &str); 5] = [
+        (365 * 24 * 60 * 60, "y"),
+        (24 * 60 * 60, "d"),
+        (60 * 60, "h"),
+        (60, "m"),
+        (1, "s"),
+    ];
+
+    let mut rest = duration.as_secs();
+    let parts: Vec<String> = UNITS
+        .iter()
+        .map(|(size, unit)| {
+            let count = rest / size;
+            rest %= size;
+            (count, unit)
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, unit)| format!("{count}{unit}"))
+        .collect();
+
+    if parts.is_empty() {
+        String::from("0s")
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_time_shows_two_units() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        assert_eq!(format_elapsed(Duration::ZERO), "0s");
+        assert_eq!(format_elapsed(Duration::from_millis(59_999)), "59s");
+        assert_eq!(
+            format_elapsed(minutes(65) + Duration::from_secs(7)),
+            "1h 5m"
+        );
+        assert_eq!(format_elapsed(minutes(60) + Duration::from_secs(7)), "1h");
+        assert_eq!(format_elapsed(minutes(25 * 60)), "1d 1h");
+        assert_eq!(
+            format_elapsed(minutes((2 * 365 + 41) * 24 * 60 + 3)),
+            "2y 41d"
+        );
+    }
+
+    #[test]
+    fn time_since_timestamps() {
+        let now = crate::twitch::parse_timestamp("2024-03-01T01:05:00Z").unwrap();
+        assert_eq!(time_since("2024-03-01T00:00:00Z", now).unwrap(), "1h 5m");
+        assert_eq!(
+            time_since("2024-02-28T01:05:00.123456Z", now).unwrap(),
+            "2d"
+        );
+        // a stream that started after `now`, e.g. because of clock skew
+        assert_eq!(time_since("2024-03-01T02:00:00Z", now).unwrap(), "0s");
+        assert!(time_since("yesterday", now).is_err());
+    }
+}
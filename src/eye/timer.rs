@@ -0,0 +1,122 @@
+//! Custom commands that post on their own every so often, while the stream is
+//! online and chat is active.
+use crate::bot::interface::BotInterface;
+use crate::bot::shutdown::Shutdown;
+use crate::chat::data::ChatMessage;
+use crate::log::{self, Target};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often timers are checked.
+const TICK: Duration = Duration::from_secs(10);
+/// Timers can't post more often than this.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct Timer {
+    pub interval: Duration,
+    /// How many chat lines have to be sent between posts.
+    pub min_lines: u64,
+    pub body: Arc<super::command::CommandRules>,
+    last_posted: Instant,
+    lines_at_last_post: u64,
+}
+
+impl Timer {
+    /// A timer that first posts after `interval` and `min_lines` chat lines,
+    /// counting from `chat_lines`.
+    #[must_use]
+    pub fn new(
+        interval: Duration,
+        min_lines: u64,
+        body: super::command::CommandRules,
+        chat_lines: u64,
+    ) -> Self {
+        Timer {
+            interval,
+            min_lines,
+            body: Arc::new(body),
+            last_posted: Instant::now(),
+            lines_at_last_post: chat_lines,
+        }
+    }
+
+    fn is_due(&self, now: Instant, chat_lines: u64) -> bool {
+        now.duration_since(self.last_posted) >= self.interval
+            && chat_lines.saturating_sub(self.lines_at_last_post) >= self.min_lines
+    }
+
+    /// The timer in its raw form, as `!timer:set` takes it.
+    #[must_use]
+    pub fn as_words_string(&self) -> String {
+        format!(
+            "{}s {} {}",
+            self.interval.as_secs(),
+            self.min_lines,
+            self.body.as_words_string()
+        )
+    }
+}
+
+/// Posts every due timer until the bot shuts down.
+pub(super) async fn run(data: super::StoreInner, bot: BotInterface, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(TICK);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    while shutdown.until_triggered(interval.tick()).await.is_some() {
+        let now = Instant::now();
+        let data_read = data.read().await;
+        let has_due = data_read
+            .timers
+            .values()
+            .any(|timer| timer.is_due(now, data_read.chat_lines));
+        drop(data_read);
+
+        if !has_due || !is_online(&bot).await {
+            continue;
+        }
+
+        let mut data_write = data.write().await;
+        let chat_lines = data_write.chat_lines;
+        let due = data_write
+            .timers
+            .iter_mut()
+            .filter(|(_, timer)| timer.is_due(now, chat_lines))
+            .map(|(name, timer)| {
+                timer.last_posted = now;
+                timer.lines_at_last_post = chat_lines;
+                (name.clone(), timer.body.clone())
+            })
+            .collect::<Vec<_>>();
+        drop(data_write);
+
+        for (name, body) in due {
+            log::debug(Target::Eye, format!("Posting timer {name:?}"));
+            body.execute(Vec::new(), &ChatMessage::default(), &bot, data.clone())
+                .await;
+        }
+    }
+}
+
+async fn is_online(bot: &BotInterface) -> bool {
+    let broadcaster_id = match bot.broadcaster_id().await {
+        Ok(broadcaster_id) => broadcaster_id,
+        Err(err) => {
+            log::warn(
+                Target::Eye,
+                format!("Could not get the channel for timers: {err}"),
+            );
+            return false;
+        }
+    };
+    match crate::twitch::stream_from_user_id(broadcaster_id, bot.helix_auth()).await {
+        Ok(stream) => stream.is_some(),
+        Err(err) => {
+            log::warn(
+                Target::Eye,
+                format!("Could not check if the stream is online for timers: {err}"),
+            );
+            false
+        }
+    }
+}
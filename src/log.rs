@@ -0,0 +1,339 @@
+//! Leveled logging for every part of the bot, following the `log` options.
+//!
+//! Messages are printed to stdout, or stderr for warnings and errors, and can
+//! also be written to a file that is rotated once it gets too big. Anything
+//! that looks like a token is redacted before it goes anywhere.
+use crate::options::{LogFormat, LogLevel, Options};
+use regex::Regex;
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// The part of the bot that a message comes from. Each target's level can be
+/// set on its own in `log.targets`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Auth,
+    Bot,
+    Chat,
+    Eventsub,
+    Eye,
+    Comet,
+}
+
+#[derive(Debug, Default)]
+struct Logger {
+    options: Options,
+    file: Option<Mutex<LogFile>>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: Arc<Path>,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+/// Sets up logging from `options`. Messages logged before this are printed
+/// at the default `info` level.
+///
+/// # Errors
+/// Returns `Err` if `log.file` could not be opened.
+pub fn init(options: &Options) -> std::io::Result<()> {
+    let file = options
+        .log
+        .file
+        .clone()
+        .map(|path| LogFile::open(path, options.log.max_file_kb * 1024, options.log.max_files))
+        .transpose()?
+        .map(Mutex::new);
+
+    // only the first call takes effect, later ones keep the first options
+    let _ = LOGGER.set(Logger {
+        options: options.clone(),
+        file,
+    });
+    Ok(())
+}
+
+pub fn error<S: AsRef<str>>(target: Target, message: S) {
+    log(target, LogLevel::Error, message.as_ref());
+}
+pub fn warn<S: AsRef<str>>(target: Target, message: S) {
+    log(target, LogLevel::Warn, message.as_ref());
+}
+pub fn info<S: AsRef<str>>(target: Target, message: S) {
+    log(target, LogLevel::Info, message.as_ref());
+}
+pub fn debug<S: AsRef<str>>(target: Target, message: S) {
+    log(target, LogLevel::Debug, message.as_ref());
+}
+pub fn trace<S: AsRef<str>>(target: Target, message: S) {
+    log(target, LogLevel::Trace, message.as_ref());
+}
+
+fn log(target: Target, level: LogLevel, message: &str) {
+    let logger = LOGGER.get_or_init(Logger::default);
+    if level > logger.level(target) {
+        return;
+    }
+
+    let message = redact(message);
+    let line = format!("[{level} {target}] {message}");
+    if level <= LogLevel::Warn {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+
+    if let Some(file) = &logger.file {
+        let line = match logger.options.log.file_format {
+            LogFormat::Text => line,
+            LogFormat::Json => serde_json::json!({
+                "timestamp_ms": std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_millis())
+                    .unwrap_or_default(),
+                "level": level.name(),
+                "target": target.name(),
+                "message": message,
+            })
+            .to_string(),
+        };
+        file.lock()
+            .expect("The log file lock is never poisoned")
+            .write_line(&line);
+    }
+}
+
+/// Replaces OAuth codes, access tokens, refresh tokens, client secrets, and
+/// webhook secrets in `message` with `[REDACTED]`.
+#[must_use]
+pub fn redact(message: &str) -> Cow<'_, str> {
+    lazy_static::lazy_static! {
+        // Twitch tokens and secrets are 30 characters, so shorter words are
+        // left alone, e.g. the "provided" in "No OAuth provided"
+        static ref SECRET: Regex = Regex::new(
+            r#"(?i)(oauth:|oauth\s+|bearer\s+|OAuthToken\("|[?&]code=|(?:access_token|refresh_token|client_secret)"?\s*[:=]\s*"?)[a-z0-9_\-]{20,}"#
+        )
+        .expect("Static regex");
+        // webhook secrets are chosen by the user, so they're only recognized
+        // as quoted `secret` fields, e.g. in the subscription request body
+        static ref WEBHOOK_SECRET: Regex =
+            Regex::new(r#"(?i)(\bsecret"?\s*[:=]\s*")[^"]{10,}"#).expect("Static regex");
+    }
+    match SECRET.replace_all(message, "${1}[REDACTED]") {
+        Cow::Borrowed(message) => WEBHOOK_SECRET.replace_all(message, "${1}[REDACTED]"),
+        Cow::Owned(message) => Cow::Owned(
+            WEBHOOK_SECRET
+                .replace_all(&message, "${1}[REDACTED]")
+                .into_owned(),
+        ),
+    }
+}
+
+impl Logger {
+    fn level(&self, target: Target) -> LogLevel {
+        let targets = self.options.log.targets;
+        let level = match target {
+            Target::Auth => targets.auth,
+            Target::Bot => targets.bot,
+            Target::Chat => targets.chat,
+            Target::Eventsub => targets.eventsub,
+            Target::Eye => targets.eye,
+            Target::Comet => targets.comet,
+        };
+
+        level.unwrap_or(if self.options.exec.debug {
+            self.options.log.level.max(LogLevel::Debug)
+        } else {
+            self.options.log.level
+        })
+    }
+}
+
+impl LogFile {
+    fn open(path: Arc<Path>, max_size: u64, max_files: u32) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(LogFile {
+            path,
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let length = line.len() as u64 + 1;
+        if self.size > 0 && self.size + length > self.max_size {
+            if let Err(err) = self.rotate() {
+                eprintln!("Log: Could not rotate {}: {err}", self.path.display());
+            }
+        }
+
+        match writeln!(self.file, "{line}") {
+            Ok(()) => self.size += length,
+            Err(err) => eprintln!("Log: Could not write to {}: {err}", self.path.display()),
+        }
+    }
+
+    /// Moves `file` to `file.1`, `file.1` to `file.2`, and so on, dropping
+    /// the oldest past `max_files`.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files > 0 {
+            for i in (1..self.max_files).rev() {
+                let from = self.numbered(i);
+                if from.exists() {
+                    std::fs::rename(from, self.numbered(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.numbered(1))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn numbered(&self, i: u32) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{i}"));
+        PathBuf::from(path)
+    }
+}
+
+impl Target {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Auth => "auth",
+            Target::Bot => "bot",
+            Target::Chat => "chat",
+            Target::Eventsub => "eventsub",
+            Target::Eye => "eye",
+            Target::Comet => "comet",
+        }
+    }
+}
+
+impl LogLevel {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name().to_uppercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact;
+    use crate::auth::access::AccessTokenManager;
+    use crate::auth::{AccessTokenManagerOAuth, OAuthToken};
+    use crate::eventsub::data::WebhookTransport;
+    use std::borrow::Cow;
+
+    const TOKEN: &str = "abcdefghijklmnopqrstuvwxyz0123";
+    const WEBHOOK_SECRET: &str = "correct horse battery staple";
+
+    fn assert_redacted(message: &str, secret: &str) {
+        let redacted = redact(message);
+        assert!(
+            !redacted.contains(secret),
+            "{secret:?} is still in {redacted:?}"
+        );
+        assert!(redacted.contains("[REDACTED]"));
+    }
+
+    #[test]
+    fn tokens_are_redacted() {
+        let access = AccessTokenManager::new_static(String::from("clientid"), String::from(TOKEN));
+        assert_redacted(&format!("Using {access:?}"), TOKEN);
+        let response = format!(
+            r#"{{"access_token":"{TOKEN}","refresh_token":"refreshtoken0123456789abcdefgh"}}"#
+        );
+        assert_redacted(&format!("Invalid response: {response}"), TOKEN);
+        assert_redacted(
+            &format!("Invalid response: {response}"),
+            "refreshtoken0123456789abcdefgh",
+        );
+
+        // sent as the IRC password, and in Helix requests
+        assert_redacted(
+            &format!(
+                "Could not send {:?}",
+                irc::proto::Command::PASS(format!("oauth:{TOKEN}"))
+            ),
+            TOKEN,
+        );
+        assert_redacted(&format!("Authorization: Bearer {TOKEN}"), TOKEN);
+        assert_redacted(
+            &format!("Request to http://localhost:3000/?code={TOKEN}&scope=chat failed"),
+            TOKEN,
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let oauth = AccessTokenManagerOAuth {
+            oauth: OAuthToken(String::from(TOKEN)),
+            client_id: String::from("clientid"),
+            client_secret: String::from("clientsecret0123456789abcdefgh"),
+            redirect_url: String::from("http://localhost:3000"),
+            tokens_store_path: std::path::PathBuf::from("store"),
+        };
+        let message = format!("Starting with {oauth:?}");
+        assert_redacted(&message, TOKEN);
+        assert_redacted(&message, "clientsecret0123456789abcdefgh");
+
+        let transport = WebhookTransport {
+            callback_url: String::from("https://example.com/eventsub"),
+            secret: String::from(WEBHOOK_SECRET),
+            host_address: String::from("0.0.0.0:8080"),
+        };
+        assert_redacted(&format!("Using {transport:?}"), WEBHOOK_SECRET);
+        let body = serde_json::json!({
+            "method": "webhook",
+            "callback": transport.callback_url,
+            "secret": transport.secret,
+        });
+        assert_redacted(&format!("Could not subscribe with {body}"), WEBHOOK_SECRET);
+    }
+
+    #[test]
+    fn ordinary_text_is_unchanged() {
+        for message in [
+            "No OAuth provided. Starting server at http:://localhost:3000 ...",
+            "Keep the webhook secret secret",
+            "Bearer of bad news",
+            "!cmd:set secret Psst, the code=1234",
+            "Could not subscribe to channel.raid: Twitch error 409: subscription already exists",
+        ] {
+            assert!(matches!(redact(message), Cow::Borrowed(redacted) if redacted == message));
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::eventsub::subscription::Subscription;
 use auth::OAuthToken;
 use clap::Parser;
 use eventsub::event;
+use log::Target;
 use std::path::PathBuf;
 use std::process::ExitCode;
 
@@ -11,7 +12,10 @@ pub mod chat;
 mod cli;
 pub mod eventsub;
 pub mod eye;
+pub mod log;
+pub mod metrics;
 pub mod options;
+pub mod random;
 pub mod twitch;
 
 #[tokio::main]
@@ -27,45 +31,56 @@ async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     } else {
         options::Options::default()
     };
+    log::init(&options)?;
 
-    let token_manager = match auth::access::AccessTokenManager::new_tokens(
-        auth::AccessTokenManagerTokens {
+    let token_manager =
+        match auth::access::AccessTokenManager::new_tokens(auth::AccessTokenManagerTokens {
             client_id: args.clientid.clone(),
             client_secret: args.clientsecret.clone(),
             redirect_url: String::from("http://localhost:3000"),
             tokens_store_path: tokens_store_path.join("access"),
-        },
-        options,
-    )
-    .await
-    {
-        Ok(_) if args.reauth => None,
-        Ok(manager) => Some(manager),
-        Err(auth::error::AccessTokenManagerError::InvalidTokens) => {
-            println!("The stored tokens are invalid/missing!");
-            None
-        }
-        Err(err) => return Err(err.into()),
-    };
+        })
+        .await
+        {
+            Ok(_) if args.reauth => None,
+            Ok(manager) => Some(manager),
+            Err(auth::error::AccessTokenManagerError::InvalidTokens) => {
+                log::warn(Target::Auth, "The stored tokens are invalid/missing!");
+                None
+            }
+            Err(err) => return Err(err.into()),
+        };
 
     let token_manager = match token_manager {
         Some(manager) => manager,
         None => {
             let oauth = run_oauth_server(args.oauth.clone(), args.clientid.clone()).await?;
-            auth::access::AccessTokenManager::new_oauth(
-                auth::AccessTokenManagerOAuth {
-                    oauth,
-                    client_id: args.clientid.clone(),
-                    client_secret: args.clientsecret.clone(),
-                    redirect_url: String::from("http://localhost:3000"),
-                    tokens_store_path: tokens_store_path.join("access"),
-                },
-                options,
-            )
+            auth::access::AccessTokenManager::new_oauth(auth::AccessTokenManagerOAuth {
+                oauth,
+                client_id: args.clientid.clone(),
+                client_secret: args.clientsecret.clone(),
+                redirect_url: String::from("http://localhost:3000"),
+                tokens_store_path: tokens_store_path.join("access"),
+            })
             .await?
         }
     };
 
+    if let Some(cli::Command::Eventsub { app, action }) = args.command {
+        let access_token = if app {
+            token_manager.get_app_access_token().await?
+        } else {
+            token_manager.get_credentials().await?.access_token
+        };
+        return run_eventsub_command(
+            action,
+            &args.eventsub_subscriptions_url,
+            &args.clientid,
+            &access_token,
+        )
+        .await;
+    }
+
     let broadcaster_user_id = twitch::user_from_login(
         "eye_motif",
         &twitch::HelixAuth {
@@ -85,61 +100,42 @@ async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             chat_channel: String::from("eye_motif"),
             chat_implicit_access: args.chat_access,
             subscriptions: vec![
-                Subscription::ChannelPointRedeem {
-                    broadcaster_user_id: broadcaster_user_id.clone(),
-                    reward_id: None,
-                },
-                Subscription::RaidTo {
-                    broadcaster_user_id: broadcaster_user_id.clone(),
-                },
-                Subscription::Subscription {
-                    broadcaster_user_id: broadcaster_user_id.clone(),
-                },
+                Subscription::channel_point_redeem(broadcaster_user_id.clone(), None),
+                Subscription::raid_to(broadcaster_user_id.clone()),
+                Subscription::channel_subscription(broadcaster_user_id.clone()),
             ],
+            eventsub_transport: match (args.webhook_callback, args.webhook_secret) {
+                (Some(callback_url), Some(secret)) => {
+                    eventsub::data::Transport::Webhook(eventsub::data::WebhookTransport {
+                        callback_url,
+                        secret,
+                        host_address: args.webhook_address,
+                    })
+                }
+                _ => eventsub::data::Transport::Websocket,
+            },
+            eventsub_urls: eventsub::data::EventsubUrls {
+                websocket: args.eventsub_url,
+                subscriptions: args.eventsub_subscriptions_url,
+            },
         },
-        options,
+        options.clone(),
     )
     .await?;
 
+    if let Some(address) = options.metrics.address {
+        metrics::serve(address, bot.interface())?;
+    }
+
     if options.features.eye {
-        let eye_store = eye::Store::new(tokens_store_path.clone(), &bot, options).await?;
+        let eye_store = eye::Store::new(tokens_store_path.clone(), &bot, options.clone()).await?;
         tokio::spawn(eye_store.register_base_commands(&bot));
 
-        tokio::spawn(bot.on_event::<event::Raid, _>(|notif, bot| async move {
-            match twitch::stream_from_user_id(
-                &notif.payload.event.from_broadcaster_user_id,
-                bot.helix_auth(),
-            )
-            .await
-            {
-                Ok(Some(stream)) => {
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-                    bot.say(format!(
-                        "Thank you so much @{} for the raid!!! <3",
-                        stream.user_name
-                    ))
-                    .await;
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    bot.say(format!(
-                        "{} was last playing \"{}\" with {} viewer{}! :D",
-                        stream.user_name,
-                        stream.game_name,
-                        stream.viewer_count,
-                        if stream.viewer_count == 1 { "" } else { "s" }
-                    ))
-                    .await;
-                }
-                Ok(None) => bot.say("Thank you so much for the raid!!! <3").await,
-                Err(err) => {
-                    eprintln!("Error getting a Stream from a user id: {err}");
-                    bot.say("Thank you so much for the raid!!! <3").await;
-                }
-            };
-        }));
+        tokio::spawn(eye_store.register_raid_thanks(&bot));
 
         tokio::spawn(
             bot.on_event::<event::Subscription, _>(move |notif, bot| async move {
-                options.debug(format!(">> SUBSCRIPTION {notif:?}"));
+                log::debug(Target::Eventsub, format!("Subscription: {notif:?}"));
                 bot.say(format!(
                     "Thank you so much @{} for the sub!!! <3",
                     notif.payload.event.user_name
@@ -152,9 +148,11 @@ async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if options.features.comet {
             // TODO: add options for port
             let comet_server =
-                eye::comet::Server::new(8000, "eye_motif", bot.error_reporter(), options).await?;
+                eye::comet::Server::new(8000, "eye_motif", bot.error_reporter(), bot.shutdown())
+                    .await?;
 
-            tokio::spawn(eye_store.register_comet_commands(&bot, &comet_server));
+            tokio::spawn(eye_store.register_comet_commands(&comet_server));
+            tokio::spawn(bot.attach_comet(&comet_server));
 
             tokio::spawn(bot.on_event_comet::<event::ChannelPointRedeem, _>(
                 &comet_server,
@@ -343,6 +341,43 @@ fn run_comet_chat_manager(
     );
 }
 
+async fn run_eventsub_command(
+    action: cli::EventsubAction,
+    subscriptions_url: &str,
+    client_id: &str,
+    access_token: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match action {
+        cli::EventsubAction::List => {
+            let list =
+                eventsub::manage::list_subscriptions(subscriptions_url, client_id, access_token)
+                    .await?;
+            for subscription in &list.subscriptions {
+                println!("{subscription}");
+            }
+            println!("{}", list.cost);
+        }
+        cli::EventsubAction::Delete { id } => {
+            eventsub::manage::delete_subscription(subscriptions_url, &id, client_id, access_token)
+                .await?;
+            println!("Deleted subscription {id}.");
+        }
+        cli::EventsubAction::Prune => {
+            let result =
+                eventsub::manage::prune_subscriptions(subscriptions_url, client_id, access_token)
+                    .await?;
+            for subscription in &result.pruned {
+                println!("Deleted {subscription}");
+            }
+            for subscription in &result.failed {
+                println!("Could not delete {subscription}");
+            }
+            println!("{result}");
+        }
+    }
+    Ok(())
+}
+
 async fn run_oauth_server(
     oauth: Option<String>,
     client_id: String,
@@ -358,6 +393,8 @@ async fn run_oauth_server(
                     "chat:edit",
                     "channel:read:redemptions",
                     "channel:read:subscriptions",
+                    "user:manage:whispers",
+                    "moderator:read:followers",
                 ]
                 .into_iter()
                 .map(String::from)
@@ -0,0 +1,394 @@
+//! Counters for every subsystem, served in the Prometheus text format at
+//! `/metrics` when `metrics.address` is set.
+use crate::log::{self, Target};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+lazy_static::lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub chat_messages_received: Counter,
+    pub chat_messages_sent: Counter,
+    /// By command name, builtin or custom.
+    pub commands: LabeledCounter,
+    /// By listener name.
+    pub listener_matches: LabeledCounter,
+    /// By subscription type.
+    pub eventsub_notifications: LabeledCounter,
+    /// By subsystem, e.g. `chat`.
+    pub reconnects: LabeledCounter,
+    /// Every use of each custom command, including ones from before the bot
+    /// started.
+    pub custom_command_uses: LabeledGauge,
+    /// When each custom command was last used, in seconds since the Unix
+    /// epoch.
+    pub custom_command_last_used: LabeledGauge,
+    pub helix_requests: Counter,
+    pub helix_errors: Counter,
+    helix_request_micros: Counter,
+    pub comet_connected: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+#[derive(Debug, Default)]
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+/// Like a [`LabeledCounter`], but its values are set instead of counted.
+#[derive(Debug, Default)]
+pub struct LabeledGauge(Mutex<BTreeMap<String, u64>>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+    #[must_use]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl LabeledCounter {
+    pub fn inc(&self, label: &str) {
+        let mut counts = self.0.lock().expect("The metrics lock is never poisoned");
+        match counts.get_mut(label) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(String::from(label), 1);
+            }
+        }
+    }
+    /// Every label's count, sorted by label.
+    #[must_use]
+    pub fn get(&self) -> Vec<(String, u64)> {
+        self.0
+            .lock()
+            .expect("The metrics lock is never poisoned")
+            .iter()
+            .map(|(label, count)| (label.clone(), *count))
+            .collect()
+    }
+}
+
+impl LabeledGauge {
+    pub fn set(&self, label: &str, value: u64) {
+        self.0
+            .lock()
+            .expect("The metrics lock is never poisoned")
+            .insert(String::from(label), value);
+    }
+    pub fn remove(&self, label: &str) {
+        self.0
+            .lock()
+            .expect("The metrics lock is never poisoned")
+            .remove(label);
+    }
+    /// Every label's value, sorted by label.
+    #[must_use]
+    pub fn get(&self) -> Vec<(String, u64)> {
+        self.0
+            .lock()
+            .expect("The metrics lock is never poisoned")
+            .iter()
+            .map(|(label, value)| (label.clone(), *value))
+            .collect()
+    }
+}
+
+impl Metrics {
+    /// Runs a Helix request, recording how long it took and whether it failed.
+    pub async fn time_helix<T, E>(
+        &self,
+        request: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let result = request.await;
+
+        self.helix_requests.inc();
+        self.helix_request_micros
+            .add(u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX));
+        if result.is_err() {
+            self.helix_errors.inc();
+        }
+        result
+    }
+
+    /// Counts `message` if it is an EventSub notification, in the shape that
+    /// the websocket transport sends.
+    pub fn eventsub_message(&self, message: &serde_json::Value) {
+        if message.pointer("/metadata/message_type") != Some(&serde_json::json!("notification")) {
+            return;
+        }
+        if let Some(subscription_type) = message
+            .pointer("/payload/subscription/type")
+            .and_then(serde_json::Value::as_str)
+        {
+            self.eventsub_notifications.inc(subscription_type);
+        }
+    }
+
+    /// Every metric in the Prometheus text format.
+    #[must_use]
+    pub fn render(&self, handler_timings: &crate::bot::handler::HandlerTimings) -> String {
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "eyebot_chat_messages_received_total",
+            "Chat messages received.",
+            self.chat_messages_received.get(),
+        );
+        counter(
+            &mut out,
+            "eyebot_chat_messages_sent_total",
+            "Chat messages sent, including replies.",
+            self.chat_messages_sent.get(),
+        );
+        labeled(
+            &mut out,
+            "eyebot_commands_total",
+            "Commands run, by name.",
+            "command",
+            &self.commands.get(),
+        );
+        labeled(
+            &mut out,
+            "eyebot_listener_matches_total",
+            "Listener matches, by name.",
+            "listener",
+            &self.listener_matches.get(),
+        );
+        labeled(
+            &mut out,
+            "eyebot_eventsub_notifications_total",
+            "EventSub notifications, by subscription type.",
+            "type",
+            &self.eventsub_notifications.get(),
+        );
+        labeled(
+            &mut out,
+            "eyebot_reconnects_total",
+            "Reconnects, by subsystem.",
+            "subsystem",
+            &self.reconnects.get(),
+        );
+        gauges(
+            &mut out,
+            "eyebot_custom_command_uses",
+            "Uses of each custom command, since it was created.",
+            "command",
+            &self.custom_command_uses.get(),
+        );
+        gauges(
+            &mut out,
+            "eyebot_custom_command_last_used_timestamp_seconds",
+            "When each custom command was last used.",
+            "command",
+            &self.custom_command_last_used.get(),
+        );
+
+        header(
+            &mut out,
+            "eyebot_helix_request_duration_seconds",
+            "How long Helix requests took.",
+            "summary",
+        );
+        let _ = writeln!(
+            out,
+            "eyebot_helix_request_duration_seconds_sum {}",
+            self.helix_request_micros.get() as f64 / 1_000_000.0
+        );
+        let _ = writeln!(
+            out,
+            "eyebot_helix_request_duration_seconds_count {}",
+            self.helix_requests.get()
+        );
+        counter(
+            &mut out,
+            "eyebot_helix_errors_total",
+            "Helix requests that failed.",
+            self.helix_errors.get(),
+        );
+
+        header(
+            &mut out,
+            "eyebot_comet_connected",
+            "Whether a Comet client is connected.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "eyebot_comet_connected {}",
+            u8::from(self.comet_connected.load(Ordering::Relaxed))
+        );
+
+        let timings = handler_timings.list();
+        header(
+            &mut out,
+            "eyebot_handler_duration_seconds",
+            "How long concurrent chat handlers took, by handler.",
+            "summary",
+        );
+        for (name, timing) in &timings {
+            let name = escape(name);
+            let _ = writeln!(
+                out,
+                "eyebot_handler_duration_seconds_sum{{handler=\"{name}\"}} {}",
+                timing.total.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "eyebot_handler_duration_seconds_count{{handler=\"{name}\"}} {}",
+                timing.calls
+            );
+        }
+        header(
+            &mut out,
+            "eyebot_handler_slow_calls_total",
+            "Handler calls that took at least bot.slow_handler_ms.",
+            "counter",
+        );
+        for (name, timing) in &timings {
+            let _ = writeln!(
+                out,
+                "eyebot_handler_slow_calls_total{{handler=\"{}\"}} {}",
+                escape(name),
+                timing.slow_calls
+            );
+        }
+
+        out
+    }
+}
+
+/// Serves `/metrics` at `address` until the program exits.
+///
+/// # Errors
+/// Returns `Err` if the server couldn't be started, e.g. if `address` is
+/// already in use.
+pub fn serve(
+    address: std::net::SocketAddr,
+    bot: crate::bot::interface::BotInterface,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = tiny_http::Server::http(address)?;
+    log::debug(Target::Bot, format!("Listening at {address}"));
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                tiny_http::Response::from_string(METRICS.render(bot.handler_timings())).with_header(
+                    tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                        .expect("Static header"),
+                )
+            } else {
+                tiny_http::Response::from_string("Not found.").with_status_code(404)
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+fn labeled(out: &mut String, name: &str, help: &str, label: &str, values: &[(String, u64)]) {
+    header(out, name, help, "counter");
+    for (value_label, value) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{}\"}} {value}", escape(value_label));
+    }
+}
+
+fn gauges(out: &mut String, name: &str, help: &str, label: &str, values: &[(String, u64)]) {
+    header(out, name, help, "gauge");
+    for (value_label, value) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{}\"}} {value}", escape(value_label));
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::bot::handler::HandlerTimings;
+
+    #[test]
+    fn metrics_are_rendered() {
+        let metrics = Metrics::default();
+        metrics.chat_messages_received.add(3);
+        metrics.commands.inc("say \"hi\"");
+        metrics.commands.inc("back\\slash");
+        metrics.commands.inc("new\nline");
+        metrics.commands.inc("new\nline");
+        metrics.custom_command_uses.set("hug", 7);
+        metrics.helix_requests.add(2);
+        metrics.helix_request_micros.add(1_500_000);
+
+        let out = metrics.render(&HandlerTimings::default());
+        let lines: Vec<_> = out.lines().collect();
+        let has = |expected: &str| {
+            assert!(
+                lines.contains(&expected),
+                "{expected:?} is missing from:\n{out}"
+            );
+        };
+
+        has("# HELP eyebot_chat_messages_received_total Chat messages received.");
+        has("# TYPE eyebot_chat_messages_received_total counter");
+        has("eyebot_chat_messages_received_total 3");
+        has("eyebot_chat_messages_sent_total 0");
+
+        has("# TYPE eyebot_commands_total counter");
+        has(r#"eyebot_commands_total{command="say \"hi\""} 1"#);
+        has(r#"eyebot_commands_total{command="back\\slash"} 1"#);
+        has(r#"eyebot_commands_total{command="new\nline"} 2"#);
+
+        has("# TYPE eyebot_custom_command_uses gauge");
+        has(r#"eyebot_custom_command_uses{command="hug"} 7"#);
+
+        has("# TYPE eyebot_helix_request_duration_seconds summary");
+        has("eyebot_helix_request_duration_seconds_sum 1.5");
+        has("eyebot_helix_request_duration_seconds_count 2");
+        has("eyebot_comet_connected 0");
+
+        // every metric has its HELP and TYPE lines before its samples
+        let mut described = Vec::new();
+        for line in &lines {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                described.push(help.split(' ').next().unwrap());
+            } else if !line.starts_with("# TYPE ") {
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert!(
+                    described.iter().any(|metric| name.starts_with(metric)),
+                    "{name} has no HELP line"
+                );
+            }
+        }
+        assert_eq!(
+            out.matches("# HELP ").count(),
+            out.matches("# TYPE ").count()
+        );
+    }
+}
@@ -1,12 +1,18 @@
 use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Default)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 pub struct Options {
     pub features: Features,
     pub exec: Exec,
     pub bot: Bot,
+    pub plugins: Plugins,
+    pub supervisor: Supervisor,
+    pub metrics: Metrics,
+    pub log: Log,
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -17,13 +23,15 @@ pub struct Features {
     pub custom_commands: bool,
     pub counters: bool,
     pub listeners: bool,
+    pub timers: bool,
     pub comet: bool,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 pub struct Exec {
+    /// Raises the default log level to at least `debug`.
     pub debug: bool,
 }
 
@@ -32,25 +40,106 @@ pub struct Exec {
 #[serde(deny_unknown_fields)]
 pub struct Bot {
     pub duplicate_message_depth: usize,
+    /// Whisper chatters when a command they used is on cooldown.
+    pub cooldown_whisper: bool,
+    /// Handlers that take at least this long are reported as slow.
+    pub slow_handler_ms: u64,
 }
 
-impl Options {
-    pub fn debug<S: Into<String>>(&self, s: S) {
-        if self.exec.debug {
-            println!("[DEBUG] {}", s.into());
-        }
-    }
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Plugins {
+    /// The names of plugins that start disabled.
+    #[serde(deserialize_with = "shared")]
+    pub disabled: Arc<[String]>,
 }
 
-impl Default for Options {
-    fn default() -> Self {
-        Self {
-            features: Features::default(),
-            exec: Exec::default(),
-            bot: Bot::default(),
-        }
-    }
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Supervisor {
+    /// What to do about errors that the bot can keep running through.
+    pub recoverable: Policy,
+    /// What to do about errors that stop chat or EventSub.
+    pub fatal: Policy,
+    /// How many times within an hour `Policy::Restart` restarts chat and
+    /// EventSub before exiting instead.
+    pub max_restarts: u32,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    /// Where to serve `/metrics`, e.g. `"127.0.0.1:9100"`. Not served if unset.
+    pub address: Option<std::net::SocketAddr>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Log {
+    /// The level for targets that aren't set in `targets`.
+    pub level: LogLevel,
+    pub targets: LogTargets,
+    /// A file to also write messages to.
+    #[serde(deserialize_with = "shared_path")]
+    pub file: Option<Arc<Path>>,
+    pub file_format: LogFormat,
+    /// How big the file can get before it is rotated.
+    pub max_file_kb: u64,
+    /// How many rotated files to keep.
+    pub max_files: u32,
+}
+
+/// Levels for each target, overriding `log.level`.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct LogTargets {
+    pub auth: Option<LogLevel>,
+    pub bot: Option<LogLevel>,
+    pub chat: Option<LogLevel>,
+    pub eventsub: Option<LogLevel>,
+    pub eye: Option<LogLevel>,
+    pub comet: Option<LogLevel>,
 }
+
+/// Each level includes the ones before it.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// The same lines that are printed.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Print the error and keep running.
+    Log,
+    /// Print the error, post it in chat, and keep running.
+    Notify,
+    /// Print the error and restart the subsystem that failed.
+    Restart,
+    /// Stop the bot with the error.
+    Exit,
+}
+
 impl Default for Features {
     fn default() -> Self {
         Self {
@@ -58,19 +147,53 @@ impl Default for Features {
             custom_commands: true,
             counters: true,
             listeners: true,
+            timers: true,
             comet: false,
         }
     }
 }
-impl Default for Exec {
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self {
+            duplicate_message_depth: 0,
+            cooldown_whisper: false,
+            slow_handler_ms: 1000,
+        }
+    }
+}
+
+impl Default for Supervisor {
     fn default() -> Self {
-        Self { debug: false }
+        Self {
+            recoverable: Policy::Log,
+            fatal: Policy::Exit,
+            max_restarts: 5,
+        }
     }
 }
-impl Default for Bot {
+
+impl Default for Log {
     fn default() -> Self {
         Self {
-            duplicate_message_depth: 0,
+            level: LogLevel::Info,
+            targets: LogTargets::default(),
+            file: None,
+            file_format: LogFormat::Json,
+            max_file_kb: 1024,
+            max_files: 3,
         }
     }
 }
+
+/// Owned data is kept behind `Arc` so that cloning `Options` stays cheap.
+fn shared<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Arc<[T]>, D::Error> {
+    Ok(Vec::<T>::deserialize(deserializer)?.into())
+}
+fn shared_path<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Arc<Path>>, D::Error> {
+    Ok(Option::<std::path::PathBuf>::deserialize(deserializer)?.map(Arc::from))
+}
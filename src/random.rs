@@ -0,0 +1,91 @@
+//! Random numbers, from `ring`'s system RNG, or from a fixed seed in tests.
+use ring::rand::SecureRandom;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref SYSTEM: ring::rand::SystemRandom = ring::rand::SystemRandom::new();
+}
+
+#[derive(Debug, Default)]
+pub enum Rng {
+    #[default]
+    System,
+    /// A SplitMix64 generator, which gives the same numbers for the same seed.
+    Seeded(Mutex<u64>),
+}
+
+impl Rng {
+    #[must_use]
+    pub fn seeded(seed: u64) -> Self {
+        Rng::Seeded(Mutex::new(seed))
+    }
+
+    pub fn fill(&self, bytes: &mut [u8]) {
+        match self {
+            Rng::System => fill(bytes),
+            Rng::Seeded(state) => {
+                let mut state = state.lock().expect("The RNG lock is never poisoned");
+                for chunk in bytes.chunks_mut(8) {
+                    let value = split_mix(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(&value[..chunk.len()]);
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn next_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// A number in `0..bound`, without favoring any of them. `bound` must not
+    /// be 0.
+    #[must_use]
+    pub fn below(&self, bound: u64) -> u64 {
+        // values past the last multiple of `bound` would favor small numbers
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < limit {
+                return value % bound;
+            }
+        }
+    }
+
+    /// A number in `min..=max`. `min` must not be greater than `max`.
+    #[must_use]
+    pub fn range(&self, min: i64, max: i64) -> i64 {
+        let span = max.abs_diff(min);
+        let offset = if span == u64::MAX {
+            self.next_u64()
+        } else {
+            self.below(span + 1)
+        };
+        min.wrapping_add_unsigned(offset)
+    }
+
+    /// A random item from `items`, or `None` if it's empty.
+    #[must_use]
+    pub fn choose<'a, T>(&self, items: &'a [T]) -> Option<&'a T> {
+        let length = u64::try_from(items.len())
+            .ok()
+            .filter(|length| *length > 0)?;
+        items.get(usize::try_from(self.below(length)).ok()?)
+    }
+}
+
+/// Fills `bytes` from the system RNG.
+pub fn fill(bytes: &mut [u8]) {
+    // only fails if the system RNG isn't ready yet
+    while SYSTEM.fill(bytes).is_err() {}
+}
+
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
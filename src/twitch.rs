@@ -1,12 +1,15 @@
 use crate::auth::access::AccessTokenManager;
 use reqwest::Client;
-use ring::rand::SecureRandom;
 use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// The most characters that Twitch allows in a chat message.
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
 #[derive(Debug)]
 pub struct HelixAuth {
     pub client_id: String,
@@ -20,12 +23,13 @@ pub struct TwitchError {
     pub message: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct TwitchUser {
     pub id: String,
     pub login: String,
     pub display_name: String,
     pub description: String,
+    pub created_at: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -37,7 +41,7 @@ pub struct TwitchEmote {
     pub theme_mode: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct TwitchChannel {
     pub broadcaster_id: String,
     pub broadcaster_login: String,
@@ -49,7 +53,15 @@ pub struct TwitchChannel {
     pub tags: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwitchFollower {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub followed_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct TwitchStream {
     pub id: String,
     pub user_id: String,
@@ -97,9 +109,13 @@ pub async fn user_from_login(login: &str, auth: &HelixAuth) -> Result<Option<Twi
 pub async fn user_from_id(id: &str, auth: &HelixAuth) -> Result<Option<TwitchUser>> {
     get_paginated_value(format!("https://api.twitch.tv/helix/users?id={id}"), auth).await
 }
+/// The user that the access token belongs to.
+pub async fn token_user(auth: &HelixAuth) -> Result<Option<TwitchUser>> {
+    get_paginated_value("https://api.twitch.tv/helix/users", auth).await
+}
 pub async fn channel(broadcaster_id: &str, auth: &HelixAuth) -> Result<Option<TwitchChannel>> {
     get_paginated_value(
-        format!("https://api.twitch.tv/helix/channels?broadcaster_id={broadcaster_id}'"),
+        format!("https://api.twitch.tv/helix/channels?broadcaster_id={broadcaster_id}"),
         auth,
     )
     .await
@@ -112,6 +128,20 @@ pub async fn stream_from_user_id(user_id: &str, auth: &HelixAuth) -> Result<Opti
     .await
 }
 
+/// Requires the `moderator:read:followers` scope. `None` if `user_id` doesn't
+/// follow `broadcaster_id`.
+pub async fn follower(
+    broadcaster_id: &str,
+    user_id: &str,
+    auth: &HelixAuth,
+) -> Result<Option<TwitchFollower>> {
+    get_paginated_value(
+        format!("https://api.twitch.tv/helix/channels/followers?broadcaster_id={broadcaster_id}&user_id={user_id}"),
+        auth,
+    )
+    .await
+}
+
 pub async fn get_global_badges(auth: &HelixAuth) -> Result<HashMap<String, Vec<TwitchBadgeUrls>>> {
     Ok(
         get_paginated_values("https://api.twitch.tv/helix/chat/badges/global", auth)
@@ -149,25 +179,99 @@ pub async fn get_all_badges(
         get_global_badges(auth),
         get_channel_badges(broadcaster_id, auth),
     )?;
-    Ok(global.into_iter().chain(channel.into_iter()).collect())
+    Ok(global.into_iter().chain(channel).collect())
+}
+
+/// Requires the `user:manage:whispers` scope, and `from_user_id` must have a
+/// verified phone number.
+pub async fn send_whisper(
+    from_user_id: &str,
+    to_user_id: &str,
+    message: &str,
+    auth: &HelixAuth,
+) -> Result<()> {
+    crate::metrics::METRICS
+        .time_helix(post_whisper(from_user_id, to_user_id, message, auth))
+        .await
+}
+
+async fn post_whisper(
+    from_user_id: &str,
+    to_user_id: &str,
+    message: &str,
+    auth: &HelixAuth,
+) -> Result<()> {
+    let response = Client::new()
+        .post("https://api.twitch.tv/helix/whispers")
+        .query(&[("from_user_id", from_user_id), ("to_user_id", to_user_id)])
+        .header("Client-Id", &auth.client_id)
+        .header(
+            "Authorization",
+            format!(
+                "Bearer {}",
+                auth.access.get_credentials().await?.access_token
+            ),
+        )
+        .header("Content-Type", "application/json")
+        .body(serde_json::json!({ "message": message }).to_string())
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    // success has no body
+    if let Ok(error) = serde_json::from_str::<TwitchError>(&response) {
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Parses the `2023-01-01T00:00:00Z` timestamps that Twitch uses. Fractions of
+/// a second are ignored.
+pub fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let number = |from: usize, to: usize| timestamp.get(from..to)?.parse::<u64>().ok();
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // leap years from 1970 up to, but not including, `year`
+    let leap_days = (year - 1969) / 4 - (year - 1901) / 100 + (year - 1601) / 400;
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = (year - 1970) * 365
+        + leap_days
+        + DAYS_BEFORE_MONTH[usize::try_from(month - 1).ok()?]
+        + u64::from(is_leap_year && month > 2)
+        + day
+        - 1;
+
+    Some(
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(days * 24 * 60 * 60 + hour * 60 * 60 + minute * 60 + second),
+    )
 }
 
 pub fn random_chatter_color() -> String {
-    lazy_static::lazy_static!(
-        static ref RNG: ring::rand::SystemRandom = ring::rand::SystemRandom::new();
-    );
     let mut color = [0u8; 3];
-    loop {
-        if RNG.fill(&mut color).is_ok() {
-            break;
-        }
-    }
+    crate::random::fill(&mut color);
     format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
 }
 
 async fn get_paginated_values<U: reqwest::IntoUrl>(
     url: U,
     auth: &HelixAuth,
+) -> Result<Vec<serde_json::Value>> {
+    crate::metrics::METRICS
+        .time_helix(request_paginated_values(url, auth))
+        .await
+}
+
+async fn request_paginated_values<U: reqwest::IntoUrl>(
+    url: U,
+    auth: &HelixAuth,
 ) -> Result<Vec<serde_json::Value>> {
     let response = Client::new()
         .get(url)
@@ -202,7 +306,7 @@ async fn get_paginated_value<T: serde::de::DeserializeOwned, U: reqwest::IntoUrl
     let arr = get_paginated_values(url, auth).await?;
 
     Ok(arr
-        .get(0)
+        .first()
         .map(|value| serde_json::from_value(value.clone()))
         .transpose()?)
 }
@@ -235,3 +339,41 @@ impl PartialEq for TwitchEmote {
     }
 }
 impl Eq for TwitchEmote {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(seconds: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn timestamps_parse() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), unix(0));
+        assert_eq!(parse_timestamp("2023-01-01T00:00:00Z"), unix(1_672_531_200));
+        assert_eq!(
+            parse_timestamp("2024-02-29T12:34:56.789012Z"),
+            unix(1_709_210_096)
+        );
+        // 2000 is a leap year, but 2100 isn't
+        assert_eq!(parse_timestamp("2000-03-01T00:00:00Z"), unix(951_868_800));
+        assert_eq!(parse_timestamp("2100-03-01T00:00:00Z"), unix(4_107_542_400));
+    }
+
+    #[test]
+    fn invalid_timestamps_are_none() {
+        for timestamp in [
+            "",
+            "2023-01-01",
+            "1969-12-31T23:59:59Z",
+            "2023-00-01T00:00:00Z",
+            "2023-13-01T00:00:00Z",
+            "2023-01-00T00:00:00Z",
+            "2023-01-32T00:00:00Z",
+            "not a timestamp at all",
+        ] {
+            assert_eq!(parse_timestamp(timestamp), None, "{timestamp:?}");
+        }
+    }
+}